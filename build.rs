@@ -0,0 +1,410 @@
+//! Generates the linker script `link.x` for the firmware binaries and
+//! the FE310 register definitions `fe310_regs.rs` from
+//! `fe310-g002.svd`, see `src/lib/fe310/regs.rs`.
+//!
+//! ## Linker script
+//!
+//! The board memory map and the stack/heap sizes are declared once in
+//! `BOARD`. The layout is picked with a cargo feature:
+//!
+//! - `layout-flash-bootloader`: code and read-only data in flash after
+//!   the vendor boot loader, `.data` copied to RAM by `_start` (default)
+//! - `layout-flash`: as above at the start of flash, for boards where
+//!   the image is booted directly and the boot loader is not needed
+//! - `layout-flash-slot-a`, `layout-flash-slot-b`: application images
+//!   for the update slots of `hal::update`, booted by `update-boot`
+//! - `layout-ram`: whole image in RAM, for debug images loaded with
+//!   `openocd_hifive1_revb.cfg`. There is no heap, the code needs the space.
+//!
+//! Without a layout feature no script is passed to the linker and the
+//! build must supply one with `-C link-arg=-T<script>`.
+//!
+//! In the flash layouts the RAM sections start `_ram_reserved` bytes
+//! into RAM, 0 unless the binary defines it. The `serial-boot` binary
+//! leaves the first `loader_ram_offset` bytes of RAM to the
+//! `layout-ram` images it loads. It does not allocate, linking with
+//! `_no_heap` defined leaves it without a heap.
+//!
+//! ## QEMU tests
+//!
+//! Without `qemu-system-riscv32` on the `PATH` the `qemu_missing` cfg
+//! is set and `tests/qemu.rs` marks its tests as ignored, unless
+//! `QEMU_REQUIRED` is set.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Memory map of a board and the sizes reserved in its RAM.
+struct Board {
+    name: &'static str,
+    flash_origin: u32,
+    flash_size: u32,
+    ram_origin: u32,
+    ram_size: u32,
+    /// Where the vendor boot loader in flash jumps to
+    bootloader_offset: u32,
+    /// RAM below this offset is free for the images loaded by `serial-boot`
+    loader_ram_offset: u32,
+    /// Update slots A and B, as `hal::update::UPDATE_SLOTS`
+    slot_offsets: [u32; 2],
+    slot_size: u32,
+    stack_size: u32,
+    heap_size: u32,
+}
+
+/// HiFive1 Rev B: 4MB QSPI flash, 16KB DTIM on the FE310-G002. The
+/// SiFive boot loader occupies the first 64KB of flash and jumps to
+/// 0x2001_0000. QEMU `sifive_e,revb=true` uses the same map.
+const BOARD: Board = Board {
+    name: "hifive1-revb",
+    flash_origin: 0x2000_0000,
+    flash_size: 0x0040_0000,
+    ram_origin: 0x8000_0000,
+    ram_size: 0x4000,
+    bootloader_offset: 0x1_0000,
+    loader_ram_offset: 0x2C00,
+    slot_offsets: [0x10_0000, 0x20_0000],
+    slot_size: 0x10_0000,
+    stack_size: 0x1000,
+    heap_size: 0x1000,
+};
+
+enum Layout {
+    /// Image of at most `size` bytes in flash, `boot_offset` bytes
+    /// after the start of flash
+    Flash { boot_offset: u32, size: u32 },
+    Ram,
+}
+
+fn selected_layout(board: &Board) -> Option<Layout> {
+    let layouts = [
+        ("LAYOUT_FLASH", Layout::Flash { boot_offset: 0, size: board.flash_size }),
+        ("LAYOUT_FLASH_BOOTLOADER", Layout::Flash {
+            boot_offset: board.bootloader_offset,
+            size: board.flash_size - board.bootloader_offset,
+        }),
+        ("LAYOUT_FLASH_SLOT_A", Layout::Flash { boot_offset: board.slot_offsets[0], size: board.slot_size }),
+        ("LAYOUT_FLASH_SLOT_B", Layout::Flash { boot_offset: board.slot_offsets[1], size: board.slot_size }),
+        ("LAYOUT_RAM", Layout::Ram),
+    ];
+    let mut selected = layouts.into_iter()
+        .filter(|(feature, _)| env::var_os(format!("CARGO_FEATURE_{}", feature)).is_some());
+
+    match (selected.next(), selected.next()) {
+        (Some((_, layout)), None) => Some(layout),
+        (None, _) => None,
+        _ => panic!("select only one of the layout-* features"),
+    }
+}
+
+fn linker_script(board: &Board, layout: &Layout) -> String {
+    let mut s = String::new();
+
+    let (code, code_origin, code_size, heap_size) = match layout {
+        Layout::Flash { boot_offset, size } => ("flash", board.flash_origin + boot_offset, *size, board.heap_size),
+        Layout::Ram => ("ram", board.ram_origin, board.ram_size, 0),
+    };
+    // RAM images are never loaded by serial-boot
+    let reserved = match layout {
+        Layout::Flash { .. } => "
+    /* Left to the RAM images loaded by serial-boot */
+    .ram_reserved (NOLOAD) :
+    {
+        . += DEFINED(_ram_reserved) ? _ram_reserved : 0;
+    } > ram
+",
+        Layout::Ram => "",
+    };
+
+    writeln!(s, "/* Generated by build.rs for board {}, do not edit */", board.name).unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "OUTPUT_ARCH(\"riscv\")").unwrap();
+    writeln!(s, "ENTRY(_start)").unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "MEMORY").unwrap();
+    writeln!(s, "{{").unwrap();
+    if let Layout::Flash { .. } = layout {
+        writeln!(s, "    flash : ORIGIN = 0x{:08X}, LENGTH = 0x{:X}", code_origin, code_size).unwrap();
+    }
+    writeln!(s, "    ram   : ORIGIN = 0x{:08X}, LENGTH = 0x{:X}", board.ram_origin, board.ram_size).unwrap();
+    writeln!(s, "}}").unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "_ram_start = ORIGIN(ram);").unwrap();
+    writeln!(s, "_ram_end = ORIGIN(ram) + LENGTH(ram);").unwrap();
+    writeln!(s, "_stack_size = 0x{:X};", board.stack_size).unwrap();
+    writeln!(s, "_heap_size = DEFINED(_no_heap) ? 0 : 0x{:X};", heap_size).unwrap();
+
+    write!(s, r#"
+SECTIONS
+{{
+    .text :
+    {{
+        _image_start = .;
+        KEEP(*(.entry))
+        KEEP(*(.image_header))
+        KEEP(*(.entry.*))
+        KEEP(*(.mtvec_table))
+        *(.text*)
+        . = ALIGN(4);
+    }} > {code}
+
+    .rodata :
+    {{
+        *(.srodata*)
+        *(.rodata*)
+        . = ALIGN(4);
+    }} > {code}
+{reserved}
+    /* Copied from _sidata to RAM by _start, with the code that runs
+       while flash is not readable */
+    .data : ALIGN(4)
+    {{
+        _sdata = .;
+        *(.ramtext*)
+        *(.sdata*)
+        *(.data*)
+        . = ALIGN(4);
+        _edata = .;
+    }} > ram AT > {code}
+    _sidata = LOADADDR(.data);
+    /* Length of the image file, see hal::image */
+    _image_len = LOADADDR(.data) + SIZEOF(.data) - _image_start;
+
+    /* Zeroed by _start */
+    .bss (NOLOAD) : ALIGN(4)
+    {{
+        _sbss = .;
+        *(.sbss*)
+        *(.bss*)
+        *(COMMON)
+        . = ALIGN(4);
+        _ebss = .;
+    }} > ram
+
+    .heap (NOLOAD) : ALIGN(8)
+    {{
+        _heap_start = .;
+        . += _heap_size;
+        _heap_end = .;
+    }} > ram
+
+    /* Stack grows down from _stack_start */
+    .stack (NOLOAD) : ALIGN(16)
+    {{
+        _stack_end = .;
+        . += _stack_size;
+        . = ALIGN(16);
+        _stack_start = .;
+    }} > ram
+}}
+
+ASSERT(_stack_start <= ORIGIN(ram) + LENGTH(ram), "stack and heap do not fit in RAM")
+"#, code = code, reserved = reserved).unwrap();
+
+    s
+}
+
+/// Register description of the SoC
+const SVD: &str = "fe310-g002.svd";
+
+fn svd_text<'a>(node: roxmltree::Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    node.children().find(|c| c.has_tag_name(tag)).and_then(|c| c.text()).map(str::trim)
+}
+
+fn svd_number(node: roxmltree::Node, tag: &str) -> Option<u32> {
+    let s = svd_text(node, tag)?;
+    let n = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    Some(n.unwrap_or_else(|_| panic!("{}: invalid number {:?} in <{}>", SVD, s, tag)))
+}
+
+/// `0x1001_3000` style address or value
+fn hex32(v: u32) -> String {
+    format!("0x{:04X}_{:04X}", v >> 16, v & 0xFFFF)
+}
+
+/// Writes `text` as a one line doc comment
+fn write_doc(s: &mut String, indent: &str, text: Option<&str>) {
+    if let Some(t) = text {
+        writeln!(s, "{}/// {}", indent, t.split_whitespace().collect::<Vec<_>>().join(" ")).unwrap();
+    }
+}
+
+/// Method name of field `name`, keywords like `if` are raw identifiers
+fn field_method(name: &str) -> String {
+    match name {
+        "if" | "in" | "type" | "loop" | "match" => format!("r#{}", name),
+        _ => name.to_string(),
+    }
+}
+
+fn write_register(s: &mut String, reg: roxmltree::Node) {
+    let name = svd_text(reg, "name").expect("register without name");
+    let offset = svd_number(reg, "addressOffset").unwrap_or_else(|| panic!("{}: {} has no addressOffset", SVD, name));
+    let fields: Vec<_> = reg.descendants().filter(|n| n.has_tag_name("field")).collect();
+    let access = svd_text(reg, "access").unwrap_or("read-write");
+    let readable = access != "write-only";
+    let writable = access != "read-only";
+
+    write_doc(s, "    ", svd_text(reg, "description"));
+    writeln!(s, "    pub mod {} {{", name.trim_end_matches("[%s]")).unwrap();
+    writeln!(s, "        use crate::regs::*;").unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "        pub const OFFSET: u32 = 0x{:02X};", offset).unwrap();
+    let reset = svd_number(reg, "resetValue");
+    if let Some(reset) = reset {
+        writeln!(s, "        pub const RESET: u32 = {};", hex32(reset)).unwrap();
+    }
+    let dim = svd_number(reg, "dim");
+    if let Some(dim) = dim {
+        writeln!(s, "        pub const COUNT: usize = {};", dim).unwrap();
+        writeln!(s, "        pub const STRIDE: u32 = {};", svd_number(reg, "dimIncrement").unwrap_or(4)).unwrap();
+    }
+
+    let mut readers = String::new();
+    let mut writers = String::new();
+    for f in fields {
+        let fname = svd_text(f, "name").expect("field without name");
+        let bit = svd_number(f, "bitOffset").unwrap();
+        let width = svd_number(f, "bitWidth").unwrap();
+        assert!(bit + width <= 32, "{}: field {}.{} exceeds 32 bits", SVD, name, fname);
+        let faccess = svd_text(f, "access").unwrap_or(access);
+        let (konst, method) = (fname.to_uppercase(), field_method(fname));
+
+        writeln!(s).unwrap();
+        write_doc(s, "        ", svd_text(f, "description"));
+        if faccess == "read-only" {
+            writeln!(s, "        /// Read-only").unwrap();
+        }
+        writeln!(s, "        pub const {}: Field = Field::new({}, {});", konst, bit, width).unwrap();
+
+        if readable && faccess != "write-only" {
+            if width == 1 {
+                writeln!(readers, "            pub fn {}(&self) -> bool {{ self.field({}) != 0 }}", method, konst).unwrap();
+            } else {
+                writeln!(readers, "            pub fn {}(&self) -> u32 {{ self.field({}) }}", method, konst).unwrap();
+            }
+        }
+        if writable && faccess != "read-only" {
+            writeln!(writers, "            pub fn {}(&mut self) -> FieldWriter<'_, Spec> {{ self.field({}) }}", method, konst).unwrap();
+        }
+    }
+
+    writeln!(s).unwrap();
+    writeln!(s, "        pub struct Spec;").unwrap();
+    writeln!(s).unwrap();
+    writeln!(s, "        impl RegSpec for Spec {{").unwrap();
+    writeln!(s, "            const OFFSET: u32 = OFFSET;").unwrap();
+    if reset.is_some() {
+        writeln!(s, "            const RESET: u32 = RESET;").unwrap();
+    }
+    if dim.is_some() {
+        writeln!(s, "            const COUNT: usize = COUNT;").unwrap();
+        writeln!(s, "            const STRIDE: u32 = STRIDE;").unwrap();
+    }
+    writeln!(s, "        }}").unwrap();
+    if readable {
+        writeln!(s, "        impl Readable for Spec {{}}").unwrap();
+    }
+    if writable {
+        writeln!(s, "        impl Writable for Spec {{}}").unwrap();
+    }
+    if !readers.is_empty() {
+        writeln!(s).unwrap();
+        if readers.contains(" len(") {
+            writeln!(s, "        #[allow(clippy::len_without_is_empty)]").unwrap();
+        }
+        writeln!(s, "        impl R<Spec> {{").unwrap();
+        s.push_str(&readers);
+        writeln!(s, "        }}").unwrap();
+    }
+    if !writers.is_empty() {
+        writeln!(s).unwrap();
+        writeln!(s, "        impl W<Spec> {{").unwrap();
+        s.push_str(&writers);
+        writeln!(s, "        }}").unwrap();
+    }
+    writeln!(s, "    }}").unwrap();
+}
+
+/// One module per peripheral with its base address and a module per
+/// register with the offset, the reset value, the fields and a `Spec`
+/// type with named field accessors for `regs::Reg`. A derived
+/// peripheral re-exports the registers of the one it is derived from.
+fn register_defs(svd: &str) -> String {
+    let doc = roxmltree::Document::parse(svd).unwrap_or_else(|e| panic!("{}: {}", SVD, e));
+    let mut s = String::new();
+
+    writeln!(s, "// Generated by build.rs from {}, do not edit", SVD).unwrap();
+
+    for p in doc.descendants().filter(|n| n.has_tag_name("peripheral")) {
+        let name = svd_text(p, "name").expect("peripheral without name").to_lowercase();
+        let base = svd_number(p, "baseAddress").unwrap_or_else(|| panic!("{}: {} has no baseAddress", SVD, name));
+
+        writeln!(s).unwrap();
+        match p.attribute("derivedFrom") {
+            Some(from) => {
+                let from = doc.descendants()
+                    .find(|n| n.has_tag_name("peripheral") && svd_text(*n, "name") == Some(from))
+                    .unwrap_or_else(|| panic!("{}: {} derived from unknown {}", SVD, name, from));
+                write_doc(&mut s, "", svd_text(from, "description"));
+                writeln!(s, "pub mod {} {{", name).unwrap();
+                writeln!(s, "    pub use super::{}::*;", svd_text(from, "name").unwrap().to_lowercase()).unwrap();
+                writeln!(s).unwrap();
+                writeln!(s, "    pub const BASE: u32 = {};", hex32(base)).unwrap();
+            }
+            None => {
+                write_doc(&mut s, "", svd_text(p, "description"));
+                writeln!(s, "pub mod {} {{", name).unwrap();
+                writeln!(s, "    pub const BASE: u32 = {};", hex32(base)).unwrap();
+                for reg in p.descendants().filter(|n| n.has_tag_name("register")) {
+                    writeln!(s).unwrap();
+                    write_register(&mut s, reg);
+                }
+            }
+        }
+        writeln!(s, "}}").unwrap();
+    }
+
+    s
+}
+
+fn qemu_missing() -> bool {
+    env::var_os("QEMU_REQUIRED").is_none()
+        && Command::new("qemu-system-riscv32").arg("--version").output().is_err()
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed={}", SVD);
+    println!("cargo:rerun-if-env-changed=PATH");
+    println!("cargo:rerun-if-env-changed=QEMU_REQUIRED");
+
+    println!("cargo:rustc-check-cfg=cfg(qemu_missing)");
+    if qemu_missing() {
+        println!("cargo:rustc-cfg=qemu_missing");
+    }
+
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let svd = fs::read_to_string(SVD).unwrap_or_else(|e| panic!("{}: {}", SVD, e));
+    fs::write(out.join("fe310_regs.rs"), register_defs(&svd)).unwrap();
+
+    let layout = match selected_layout(&BOARD) {
+        Some(l) => l,
+        None => return,
+    };
+
+    fs::write(out.join("link.x"), linker_script(&BOARD, &layout)).unwrap();
+
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+    if let Layout::Flash { .. } = layout {
+        println!("cargo:rustc-link-arg-bin=serial-boot=--defsym=_ram_reserved=0x{:X}", BOARD.loader_ram_offset);
+        println!("cargo:rustc-link-arg-bin=serial-boot=--defsym=_no_heap=1");
+    }
+}
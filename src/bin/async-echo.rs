@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+use core::future::Future;
+use core::panic::PanicInfo;
+use core::pin::{pin, Pin};
+
+use hal::executor;
+use hal::interrupt;
+use hal::peripherals::Peripherals;
+use hal::serial;
+use hal::serial::{Configure, DoSendByte, EnableRx, EnableTx};
+use hal::timer::Timer;
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop{}
+}
+
+fn send_str(uart: &serial::Uart, s: &str) {
+    for b in s.bytes() {
+        if uart.do_send_byte(b).is_err() {
+            break;
+        }
+    }
+}
+
+#[no_mangle]
+// Called from hal `_start` once the stack is set up
+pub extern "C" fn main() -> ! {
+
+    interrupt::m_trap_init();
+
+    let periph = Peripherals::take().unwrap();
+    let pins = periph.gpio.split();
+
+    let uart = serial::Uart::new(periph.uart0, serial::UartConfig {
+        baud: 115200,
+        start_bits: serial::UartBitCount::One,
+        stop_bits: serial::UartBitCount::One,
+        fifo: serial::UartFifoDepth::Max
+    });
+    uart.mux_pins(pins.pin17, Some(pins.pin16)).unwrap();
+    uart.configure().unwrap();
+    uart.enable_tx().unwrap();
+    uart.enable_rx().unwrap();
+
+    // Device link, received bytes are forwarded to the console
+    let link = serial::Uart::new(periph.uart1, serial::UartConfig {
+        baud: 115200,
+        start_bits: serial::UartBitCount::One,
+        stop_bits: serial::UartBitCount::One,
+        fifo: serial::UartFifoDepth::Max
+    });
+    link.mux_pins(pins.pin18, Some(pins.pin23)).unwrap();
+    link.configure().unwrap();
+    link.enable_rx().unwrap();
+
+    unsafe { interrupt::enable(); }
+
+    // Echo received bytes while printing a few ticks
+    let echo = pin!(async {
+        loop {
+            let b = uart.read().await;
+            let _ = uart.do_send_byte(b);
+        }
+    });
+    let forward = pin!(async {
+        loop {
+            let b = link.read().await;
+            let _ = uart.do_send_byte(b);
+        }
+    });
+    let ticks = pin!(async {
+        for _ in 0..3 {
+            Timer::after(100).await;
+            send_str(&uart, "tick\r\n");
+        }
+    });
+
+    let mut tasks: [Pin<&mut dyn Future<Output = ()>>; 3] = [echo, forward, ticks];
+    executor::run(&mut tasks);
+
+    loop {}
+}
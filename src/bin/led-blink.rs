@@ -1,7 +1,8 @@
 #![no_std]
 #![no_main]
 
-#[path = "../lib/dio.rs"] mod dio;
+use hal::dio;
+use hal::peripherals::Peripherals;
 
 use core::panic::PanicInfo;
 use core::arch::asm;
@@ -28,16 +29,6 @@ fn panic(_info: &PanicInfo) -> ! {
     }
 }
 
-fn set_stack(){
-    extern "C" {
-        static  _stack_start: u32;
-    }
-    unsafe{
-        let sp1 = &_stack_start;
-        asm!("mv sp, {}", in(reg) sp1);
-    }
-}
-
 fn set_trap_handler()
 {
     type FnPtr = fn();
@@ -68,29 +59,27 @@ fn delay(v: u32)
 }
 
 #[no_mangle]
-// Link section needed to pin it to a location
-// to boot from flash
-#[link_section = ".entry"] 
-pub extern "C" fn _start() -> ! {
+// Called from hal `_start` once the stack is set up
+pub extern "C" fn main() -> ! {
 
-    set_stack();
     set_trap_handler();
     clear_external_interrupt();
 
-    let p = dio::DioPin {instance: 0, port: 0, pin_num: 21};
+    let periph = Peripherals::take().unwrap();
+    let p = periph.gpio.split().pin21;
     let mode = dio::DioFuncMode::Gpio;
 
     p.setup_pin();
-    p.enable_pin_outlet();
-    p.set_pin_func_mode(&mode);
+    p.enable_pin_outlet().unwrap();
+    p.set_pin_func_mode(&mode).unwrap();
 
     loop{
 
-        p.set_pin_outlet_high();
+        p.set_pin_outlet_high().unwrap();
 
         delay(0xfffff);
 
-        p.set_pin_outlet_low();
+        p.set_pin_outlet_low().unwrap();
 
         delay(0xfffff);
 
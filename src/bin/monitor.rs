@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+//! UART monitor on UART0 at 115200 baud, see `hal::monitor`. Adds an
+//! `image` command showing the header of the running image.
+
+use core::fmt;
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use hal::image;
+use hal::mmio::Mmio;
+use hal::monitor::{Monitor, MonitorArgs, MonitorCommand, MonitorError};
+use hal::peripherals::Peripherals;
+use hal::serial;
+use hal::serial::{Configure, EnableRx, EnableTx};
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop{}
+}
+
+fn image_info(args: &mut MonitorArgs, out: &mut dyn fmt::Write) -> Result<(), MonitorError> {
+    let verify = match args.next() {
+        None => false,
+        Some("verify") => true,
+        Some(_) => return Err(MonitorError::Usage),
+    };
+
+    let header = image::running_header();
+    let (major, minor, patch) = header.version();
+    write!(out, "version {}.{}.{}, {} bytes, entry {:08x}, checksum {:08x}\r\n",
+        major, minor, patch, header.length, header.entry, header.checksum)?;
+    if verify {
+        match image::running_verify() {
+            Ok(_) => write!(out, "checksum ok\r\n")?,
+            Err(e) => write!(out, "checksum: {:?}\r\n", e)?,
+        }
+    }
+    Ok(())
+}
+
+#[no_mangle]
+// Called from hal `_start` once the stack is set up
+pub extern "C" fn main() -> ! {
+
+    let periph = Peripherals::take().unwrap();
+    let pins = periph.gpio.split();
+
+    let mut uart = serial::Uart::new(periph.uart0, serial::UartConfig {
+        baud: 115200,
+        start_bits: serial::UartBitCount::One,
+        stop_bits: serial::UartBitCount::One,
+        fifo: serial::UartFifoDepth::Max
+    });
+    uart.mux_pins(pins.pin17, Some(pins.pin16)).unwrap();
+    uart.configure().unwrap();
+    uart.enable_tx().unwrap();
+    uart.enable_rx().unwrap();
+
+    // Safety: peek, poke and gpio are meant to bypass the drivers
+    let mmio = unsafe { Mmio::new() };
+    let mut monitor = Monitor::new(&mmio);
+    monitor.register(MonitorCommand {
+        name: "image",
+        usage: "[verify]",
+        help: "header of the running image",
+        run: image_info,
+    }).unwrap();
+
+    let _ = write!(uart, "monitor: type help for the commands\r\n");
+    monitor.run(&mut uart)
+}
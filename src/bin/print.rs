@@ -1,14 +1,14 @@
 #![no_std]
 #![no_main]
 
-#[path = "../lib/dio.rs"] mod dio;
-#[path = "../lib/serial.rs"] mod serial;
+use hal::dio;
+use hal::serial;
+use hal::peripherals::Peripherals;
 
 use core::panic::PanicInfo;
 use core::arch::asm;
-use crate::serial::{Configure, DoSendByte, EnableTx, DisableTx};
+use hal::serial::{Configure, DoSendByte, EnableTx, DisableTx};
 
-const UART0_TX_GPIO17: u8 = 17; /* .equiv GPIO17, 17   --UART0 Tx */
 const PLIC_BASE: u32 = 0xC000000; // 0xC00 << 16 = 0xC00 0000,  
 const PLIC_CLAIMCOMP_CTX1_OFFSET: u32 = 0x200004;
 const MIE_SET: u32 = 0;
@@ -30,16 +30,6 @@ fn panic(_info: &PanicInfo) -> ! {
     }
 }
 
-fn set_stack(){
-    extern "C" {
-        static  _stack_start: u32;
-    }
-    unsafe{
-        let sp1 = &_stack_start;
-        asm!("mv sp, {}", in(reg) sp1);
-    }
-}
-
 fn set_trap_handler()
 {
     type FnPtr = fn();
@@ -70,38 +60,33 @@ fn delay(v: u32)
 }
 
 #[no_mangle]
-// Link section needed to pin it to a location
-// to boot from flash
-#[link_section = ".entry"]
-pub extern "C" fn _start() -> ! {
+// Called from hal `_start` once the stack is set up
+pub extern "C" fn main() -> ! {
 
-    set_stack();
     set_trap_handler();
     clear_external_interrupt();
 
-    let p = dio::DioPin {instance: 0, port: 0, pin_num: 17};
+    let periph = Peripherals::take().unwrap();
+    let p = periph.gpio.split().pin17;
     let mode = dio::DioFuncMode::Mux;
 
     p.setup_pin();
-    p.set_pin_func_mode(&mode);
-    p.select_pin_iof_func(false);
+    p.set_pin_func_mode(&mode).unwrap();
+    p.select_pin_iof_func(false).unwrap();
 
     let uart_cfg = serial::UartConfig {
         baud: 115200,
-        start_bits: serial::UartBitCount::Two,
+        start_bits: serial::UartBitCount::One,
         stop_bits: serial::UartBitCount::Two,
         fifo: serial::UartFifoDepth::Max
     };
 
-    let uart = serial::Uart {
-        instance: 0,
-        config: uart_cfg,
-    };
+    let uart = serial::Uart::new(periph.uart0, uart_cfg);
 
-    uart.configure();
+    uart.configure().unwrap();
 
     delay(0xffff);
-    uart.enable_tx();
+    uart.enable_tx().unwrap();
         
 //W e   l   c  o   m   e     t   o    L  e   a  r   n    R  I  S  C  V    LF CR  NULL
 //87,101,108,99,111,109,101, 116,111, 76,101,97,114,110, 82,73,83,67,86,  10,13, 00;
@@ -111,11 +96,11 @@ let note: [u8; 9] = [ b'W', b'e', b'l', b'c', b'o', b'm', b'e', 10, 13];
 
 for i in 1..10 {
     for c in note.iter() {
-        uart.do_send_byte(*c);
+        let _ = uart.do_send_byte(*c);
     }
 }
     delay(0xfff); // Delay to flush fifo before its disabled
-    uart.disable_tx();
+    uart.disable_tx().unwrap();
     loop {}
 }
 
@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+
+use hal::interrupt;
+use hal::semihosting::{self, SemihostingWriter};
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let _ = writeln!(SemihostingWriter, "{}", info);
+    semihosting::semihosting_exit(1)
+}
+
+#[no_mangle]
+// Called from hal `_start` once the stack is set up
+pub extern "C" fn main() -> ! {
+
+    // Without a debug host, semihosting calls fail instead of faulting
+    interrupt::m_trap_init();
+
+    semihosting::semihosting_write_str("Hello from semihosting\n");
+
+    match semihosting::semihosting_clock() {
+        Some(cs) => { let _ = writeln!(SemihostingWriter, "clock {} cs", cs); }
+        None => semihosting::semihosting_write_str("no clock\n"),
+    }
+
+    semihosting::semihosting_exit(0)
+}
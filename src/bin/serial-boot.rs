@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+
+//! Serial boot loader: receives a `layout-ram` image on UART0 with
+//! XMODEM or YMODEM, stores it at the start of RAM and jumps to it.
+//!
+//!     cargo build --release --no-default-features --features rt,layout-ram --bin led-blink
+//!     riscv64-unknown-elf-objcopy -O binary led-blink led-blink.bin
+//!     cargo image-stamp led-blink.bin
+//!     sx led-blink.bin < /dev/ttyACM0 > /dev/ttyACM0
+//!
+//! Images without a valid `hal::image` header are not started.
+//!
+//! The loader itself runs from flash and keeps its RAM above
+//! `_ram_reserved`, which is also the largest image it accepts. The
+//! image owns the whole RAM once started.
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::ptr::addr_of;
+
+use hal::image::{self, ImageError};
+use hal::peripherals::Peripherals;
+use hal::serial;
+use hal::serial::{Configure, DoSendByte, EnableRx, EnableTx};
+use hal::xmodem::{self, XmodemError};
+
+/// Load address and entry point of `layout-ram` images
+const RAM_ORIGIN: usize = 0x8000_0000;
+
+extern "C" {
+    /// Size of the load area, defined for this binary by build.rs
+    static _ram_reserved: u8;
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop{}
+}
+
+fn send_str(uart: &serial::Uart, s: &str) {
+    for b in s.bytes() {
+        if uart.do_send_byte(b).is_err() {
+            break;
+        }
+    }
+}
+
+#[no_mangle]
+// Called from hal `_start` once the stack is set up
+pub extern "C" fn main() -> ! {
+
+    let periph = Peripherals::take().unwrap();
+    let pins = periph.gpio.split();
+
+    let mut uart = serial::Uart::new(periph.uart0, serial::UartConfig {
+        baud: 115200,
+        start_bits: serial::UartBitCount::One,
+        stop_bits: serial::UartBitCount::One,
+        fifo: serial::UartFifoDepth::Max
+    });
+    uart.mux_pins(pins.pin17, Some(pins.pin16)).unwrap();
+    uart.configure().unwrap();
+    uart.enable_tx().unwrap();
+    uart.enable_rx().unwrap();
+
+    let size = addr_of!(_ram_reserved) as usize;
+    if size == 0 {
+        send_str(&uart, "serial-boot: no RAM for images, build with a flash layout\r\n");
+        loop {}
+    }
+
+    // Nothing of the loader is in this area, it is overwritten freely
+    let image = unsafe { core::slice::from_raw_parts_mut(RAM_ORIGIN as *mut u8, size) };
+
+    send_str(&uart, "serial-boot: send a RAM image with XMODEM or YMODEM\r\n");
+    loop {
+        match xmodem::xmodem_receive(&mut uart, image) {
+            Ok(0) => {}
+            Ok(len) => match image::image_verify(&image[..len]) {
+                Ok(_) => break,
+                Err(ImageError::Checksum) => send_str(&uart, "\r\nserial-boot: checksum mismatch\r\n"),
+                Err(_) => send_str(&uart, "\r\nserial-boot: not a firmware image\r\n"),
+            },
+            // No sender yet, keep asking
+            Err(XmodemError::Timeout) => {}
+            Err(XmodemError::TooLarge) => send_str(&uart, "\r\nserial-boot: image too large\r\n"),
+            Err(_) => send_str(&uart, "\r\nserial-boot: transfer failed\r\n"),
+        }
+    }
+
+    send_str(&uart, "\r\nserial-boot: starting image\r\n");
+
+    unsafe {
+        // The image was written as data
+        asm!("fence.i");
+        let entry: extern "C" fn() -> ! = core::mem::transmute(RAM_ORIGIN);
+        entry()
+    }
+}
@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use core::ptr::addr_of_mut;
+
+use hal::interrupt;
+use hal::kernel::{self, MessageQueue};
+use hal::peripherals::Peripherals;
+use hal::serial;
+use hal::serial::{Configure, DoSendByte, EnableTx};
+
+static mut PRODUCER_STACK: [u32; 256] = [0; 256];
+static mut CONSUMER_STACK: [u32; 256] = [0; 256];
+
+static QUEUE: MessageQueue<u8, 2> = MessageQueue::new();
+
+/// UART0, set up by `main` and used by the consumer
+static CONSOLE: kernel::Mutex<Option<serial::Uart>> = kernel::Mutex::new(None);
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop{}
+}
+
+fn send_str(uart: &serial::Uart, s: &str) {
+    for b in s.bytes() {
+        if uart.do_send_byte(b).is_err() {
+            break;
+        }
+    }
+}
+
+/// Higher priority, blocks whenever the queue is full
+extern "C" fn producer(count: u32) {
+    for i in 0..count as u8 {
+        QUEUE.send(i);
+    }
+}
+
+extern "C" fn consumer(count: u32) {
+    let console = CONSOLE.lock();
+    let uart = console.as_ref().unwrap();
+
+    for _ in 0..count {
+        let v = QUEUE.recv();
+        send_str(uart, "recv ");
+        let _ = uart.do_send_byte(b'0' + v);
+        send_str(uart, "\r\n");
+    }
+    kernel::kernel_sleep_ms(50);
+    send_str(uart, "done\r\n");
+}
+
+#[no_mangle]
+// Called from hal `_start` once the stack is set up
+pub extern "C" fn main() -> ! {
+
+    interrupt::m_trap_init();
+
+    let periph = Peripherals::take().unwrap();
+
+    let uart = serial::Uart::new(periph.uart0, serial::UartConfig {
+        baud: 115200,
+        start_bits: serial::UartBitCount::One,
+        stop_bits: serial::UartBitCount::One,
+        fifo: serial::UartFifoDepth::Max
+    });
+    uart.mux_pins(periph.gpio.split().pin17, None).unwrap();
+    uart.configure().unwrap();
+    uart.enable_tx().unwrap();
+    *CONSOLE.try_lock().unwrap() = Some(uart);
+
+    kernel::kernel_spawn(producer, 5, 2, unsafe { &mut *addr_of_mut!(PRODUCER_STACK) }).unwrap();
+    kernel::kernel_spawn(consumer, 5, 1, unsafe { &mut *addr_of_mut!(CONSUMER_STACK) }).unwrap();
+    kernel::kernel_start()
+}
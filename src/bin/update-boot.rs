@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+
+//! Boot stage of `hal::update`: starts the image of the active slot,
+//! or the previous one when the active image failed its trial or its
+//! CRC. Without a valid image it waits for one on UART0:
+//!
+//!     cargo build --release --no-default-features --features rt,layout-flash-slot-a --bin led-blink
+//!     riscv64-unknown-elf-objcopy -O binary led-blink led-blink.bin
+//!     cargo image-stamp led-blink.bin
+//!     sx led-blink.bin < /dev/ttyACM0 > /dev/ttyACM0
+//!
+//! The image must be built for the slot named in the prompt.
+
+use core::panic::PanicInfo;
+
+use hal::flash::{Flash, FLASH_BASE};
+use hal::peripherals::Peripherals;
+use hal::serial;
+use hal::serial::{Configure, DoSendByte, EnableRx, EnableTx};
+use hal::update::{self, UpdateError, UPDATE_SLOTS};
+use hal::xmodem::XmodemError;
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    loop{}
+}
+
+fn send_str(uart: &serial::Uart, s: &str) {
+    for b in s.bytes() {
+        if uart.do_send_byte(b).is_err() {
+            break;
+        }
+    }
+}
+
+fn send_dec(uart: &serial::Uart, v: u8) {
+    let digits = [v / 100, v / 10 % 10, v % 10];
+    let first = digits.iter().position(|d| *d != 0).unwrap_or(2);
+    for d in &digits[first..] {
+        if uart.do_send_byte(b'0' + d).is_err() {
+            break;
+        }
+    }
+}
+
+const SLOT_NAMES: [&str; 2] = ["A", "B"];
+
+#[no_mangle]
+// Called from hal `_start` once the stack is set up
+pub extern "C" fn main() -> ! {
+
+    let periph = Peripherals::take().unwrap();
+    let pins = periph.gpio.split();
+    let flash = Flash::new(periph.qspi0);
+
+    let mut uart = serial::Uart::new(periph.uart0, serial::UartConfig {
+        baud: 115200,
+        start_bits: serial::UartBitCount::One,
+        stop_bits: serial::UartBitCount::One,
+        fifo: serial::UartFifoDepth::Max
+    });
+    uart.mux_pins(pins.pin17, Some(pins.pin16)).unwrap();
+    uart.configure().unwrap();
+    uart.enable_tx().unwrap();
+    uart.enable_rx().unwrap();
+
+    let slot = loop {
+        match update::update_boot_slot(&flash) {
+            Ok(slot) => break slot,
+            Err(UpdateError::NoImage) => {}
+            Err(_) => send_str(&uart, "update-boot: flash error\r\n"),
+        }
+
+        let target = update::update_target_slot(&flash).unwrap_or(0);
+        send_str(&uart, "update-boot: no valid image, send one for slot ");
+        send_str(&uart, SLOT_NAMES[target]);
+        send_str(&uart, " with XMODEM or YMODEM\r\n");
+        match update::update_receive(&mut uart, &flash) {
+            Ok(_) => {}
+            // No sender yet, ask again
+            Err(UpdateError::Transfer(XmodemError::Timeout)) => {}
+            Err(UpdateError::Verify) => send_str(&uart, "\r\nupdate-boot: verify failed\r\n"),
+            Err(UpdateError::WrongSlot) => send_str(&uart, "\r\nupdate-boot: image built for the other slot\r\n"),
+            Err(UpdateError::Image(_)) => send_str(&uart, "\r\nupdate-boot: not a firmware image\r\n"),
+            Err(_) => send_str(&uart, "\r\nupdate-boot: transfer failed\r\n"),
+        }
+    };
+
+    send_str(&uart, "update-boot: starting slot ");
+    send_str(&uart, SLOT_NAMES[slot]);
+    if let Ok(header) = update::update_slot_header(&flash, slot) {
+        let (major, minor, patch) = header.version();
+        send_str(&uart, ", version ");
+        for (i, v) in [major, minor, patch].into_iter().enumerate() {
+            if i > 0 {
+                send_str(&uart, ".");
+            }
+            send_dec(&uart, v);
+        }
+    }
+    send_str(&uart, "\r\n");
+
+    unsafe {
+        // `_start` of slot images is at the start of the slot
+        let entry: extern "C" fn() -> ! = core::mem::transmute((FLASH_BASE + UPDATE_SLOTS[slot]) as usize);
+        entry()
+    }
+}
@@ -1,23 +1,200 @@
-
 //! # Digital IO Abstraction
+//!
+//! A `DioPin` is pin `pin_num` of port `port` of DIO instance `instance`.
+//! Pin operations go to the `DioBackend` of the instance: instance 0 is
+//! the on-chip GPIO, other instances are registered with `dio_register`,
+//! e.g. the GPIO expanders of `expander`:
+//!
+//! ```ignore
+//! static IO: Mcp23017<I2c> = Mcp23017::new(i2c, 0x20);
+//!
+//! let io = dio::dio_register(1, &IO)?;
+//! let led = io.pin(0, 3)?;
+//! led.enable_pin_outlet()?;
+//! led.set_pin_outlet_high()?;
+//! ```
+
+#[path = "fe310/gpio.rs"] pub (crate) mod gpio;
 
-#[path = "fe310/gpio.rs"] mod gpio;
+use core::cell::Cell;
+
+use crate::error::HalError;
+use crate::mmio::MMIO;
+use crate::peripherals::Gpio;
+use crate::sync::{CriticalSection, Mutex};
 
 pub type DioInstance =u8;
 pub type DioPort =u8;
 pub type DioPinNum = u8;
 
+/// DIO instances, including the on-chip GPIO
+pub const DIO_MAX_INSTANCES: usize = 4;
+
+/// Pins of a DIO instance. Called with a port and pin number checked
+/// against `ports` and `pins_per_port`.
+pub trait DioBackend: Sync {
+    fn ports(&self) -> DioPort;
+    fn pins_per_port(&self) -> DioPinNum;
+
+    fn enable_inlet(&self, port: DioPort, pin: DioPinNum) -> Result<(), HalError>;
+    fn enable_outlet(&self, port: DioPort, pin: DioPinNum) -> Result<(), HalError>;
+    fn read_inlet(&self, port: DioPort, pin: DioPinNum) -> Result<DioLogic, HalError>;
+    fn read_outlet(&self, port: DioPort, pin: DioPinNum) -> Result<DioLogic, HalError>;
+    fn write_outlet(&self, port: DioPort, pin: DioPinNum, v: DioLogic) -> Result<(), HalError>;
+
+    fn enable_inlet_pullup(&self, _port: DioPort, _pin: DioPinNum) -> Result<(), HalError> {
+        Err(HalError::Unsupported)
+    }
+}
+
+/// On-chip GPIO, instance 0, one port of 32 pins
+struct OnChipGpio;
+
+impl DioBackend for OnChipGpio {
+    fn ports(&self) -> DioPort { 1 }
+    fn pins_per_port(&self) -> DioPinNum { 32 }
+
+    fn enable_inlet(&self, _port: DioPort, pin: DioPinNum) -> Result<(), HalError> {
+        gpio::enable_inlet(&MMIO, pin);
+        Ok(())
+    }
+
+    fn enable_outlet(&self, _port: DioPort, pin: DioPinNum) -> Result<(), HalError> {
+        gpio::enable_outlet(&MMIO, pin);
+        Ok(())
+    }
+
+    fn read_inlet(&self, _port: DioPort, pin: DioPinNum) -> Result<DioLogic, HalError> {
+        Ok(DioLogic::from(gpio::read_input(&MMIO, pin)))
+    }
+
+    fn read_outlet(&self, _port: DioPort, pin: DioPinNum) -> Result<DioLogic, HalError> {
+        Ok(DioLogic::from(gpio::read_output(&MMIO, pin)))
+    }
+
+    fn write_outlet(&self, _port: DioPort, pin: DioPinNum, v: DioLogic) -> Result<(), HalError> {
+        match v {
+            DioLogic::H => gpio::set_high(&MMIO, pin),
+            DioLogic::L => gpio::set_low(&MMIO, pin),
+        }
+        Ok(())
+    }
+
+    fn enable_inlet_pullup(&self, _port: DioPort, pin: DioPinNum) -> Result<(), HalError> {
+        gpio::enable_pullup(&MMIO, pin);
+        Ok(())
+    }
+}
+
+static ON_CHIP_GPIO: OnChipGpio = OnChipGpio;
+
+static BACKENDS: Mutex<[Cell<Option<&'static dyn DioBackend>>; DIO_MAX_INSTANCES]> = Mutex::new([
+    Cell::new(Some(&ON_CHIP_GPIO)), Cell::new(None), Cell::new(None), Cell::new(None),
+]);
+
+/// Runs `f` in a critical section. Host tests have no interrupts, any
+/// context will do.
+fn dio_free<R>(f: impl FnOnce(CriticalSection) -> R) -> R {
+    #[cfg(target_arch = "riscv32")]
+    return crate::interrupt::free(f);
+    #[cfg(not(target_arch = "riscv32"))]
+    return f(unsafe { CriticalSection::new() });
+}
+
+fn dio_backend(instance: DioInstance) -> Result<&'static dyn DioBackend, HalError> {
+    dio_free(|cs| BACKENDS.borrow(cs).get(instance as usize).and_then(Cell::get))
+        .ok_or(HalError::InvalidInstance)
+}
+
+/// Pins a `DioBank` can hand out, numbered `port * pins_per_port + pin`
+pub const DIO_BANK_MAX_PINS: usize = 128;
+
+/// Makes `backend` DIO instance `instance`, 1 - `DIO_MAX_INSTANCES` - 1.
+/// Fails with `InvalidInstance` when the instance is already in use.
+pub fn dio_register(instance: DioInstance, backend: &'static dyn DioBackend) -> Result<DioBank, HalError> {
+    dio_free(|cs| {
+        let slot = BACKENDS.borrow(cs).get(instance as usize).filter(|s| s.get().is_none());
+        match slot {
+            Some(s) if instance != 0 => {
+                s.set(Some(backend));
+                Ok(DioBank { instance, claimed: Cell::new(0) })
+            }
+            _ => Err(HalError::InvalidInstance),
+        }
+    })
+}
+
+/// Pins of an instance registered with `dio_register`.
+pub struct DioBank {
+    instance: DioInstance,
+    /// Pins handed out by `pin`, one bit each
+    claimed: Cell<u128>,
+}
+
+impl DioBank {
+    pub fn instance(&self) -> DioInstance {
+        self.instance
+    }
+
+    /// Pin `pin_num` of port `port`, `InvalidPin` if the backend has none
+    /// and `InUse` if it was handed out before.
+    pub fn pin(&self, port: DioPort, pin_num: DioPinNum) -> Result<DioPin, HalError> {
+        let b = dio_backend(self.instance)?;
+        let bit = port as usize * b.pins_per_port() as usize + pin_num as usize;
+        if port >= b.ports() || pin_num >= b.pins_per_port() || bit >= DIO_BANK_MAX_PINS {
+            return Err(HalError::InvalidPin);
+        }
+        if self.claimed.get() & (1 << bit) != 0 {
+            return Err(HalError::InUse);
+        }
+        self.claimed.set(self.claimed.get() | (1 << bit));
+        Ok(DioPin { instance: self.instance, port, pin_num })
+    }
+}
+
 pub struct DioPin {
-    pub instance: DioInstance,
-    pub port: DioPort,
-    pub pin_num: DioPinNum,
+    instance: DioInstance,
+    port: DioPort,
+    pin_num: DioPinNum,
+}
+
+macro_rules! dio_pins {
+    ($($pin:ident = $n:expr,)*) => {
+        /// Pins of the on-chip GPIO, see `Gpio::split`
+        pub struct DioPins {
+            $(pub $pin: DioPin,)*
+        }
+
+        impl Gpio {
+            /// Splits the GPIO into its pins, each owned separately.
+            pub fn split(self) -> DioPins {
+                DioPins {
+                    $($pin: DioPin { instance: 0, port: 0, pin_num: $n },)*
+                }
+            }
+        }
+    };
 }
 
+dio_pins! {
+    pin0 = 0, pin1 = 1, pin2 = 2, pin3 = 3, pin4 = 4, pin5 = 5, pin6 = 6, pin7 = 7,
+    pin8 = 8, pin9 = 9, pin10 = 10, pin11 = 11, pin12 = 12, pin13 = 13, pin14 = 14, pin15 = 15,
+    pin16 = 16, pin17 = 17, pin18 = 18, pin19 = 19, pin20 = 20, pin21 = 21, pin22 = 22, pin23 = 23,
+    pin24 = 24, pin25 = 25, pin26 = 26, pin27 = 27, pin28 = 28, pin29 = 29, pin30 = 30, pin31 = 31,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum DioLogic{
     H,
     L,
 }
 
+impl From<bool> for DioLogic {
+    fn from(high: bool) -> DioLogic {
+        if high { DioLogic::H } else { DioLogic::L }
+    }
+}
+
 enum DioDriveStrength{
     I10ma,
     I20ma,
@@ -49,37 +226,63 @@ pub struct DioConfig{
 }
 
 impl DioPin {
+    pub fn instance(&self) -> DioInstance {
+        self.instance
+    }
+
+    pub fn port(&self) -> DioPort {
+        self.port
+    }
+
+    pub fn pin_num(&self) -> DioPinNum {
+        self.pin_num
+    }
+
+    fn backend(&self) -> Result<&'static dyn DioBackend, HalError> {
+        dio_backend(self.instance)
+    }
+
     pub fn setup_pin(&self) {}
     pub fn configure_pin(&self, cfg: DioConfig){}
 
-    pub fn enable_pin_inlet(&self) {
-        gpio::enable_inlet((*self).pin_num);
+    pub fn enable_pin_inlet(&self) -> Result<(), HalError> {
+        self.backend()?.enable_inlet(self.port, self.pin_num)
     }
 
-    pub fn enable_pin_inlet_pullup(&self) {}
+    pub fn enable_pin_inlet_pullup(&self) -> Result<(), HalError> {
+        self.backend()?.enable_inlet_pullup(self.port, self.pin_num)
+    }
 
-    pub fn enable_pin_outlet(&self)  {
-        gpio::enable_outlet((*self).pin_num);
+    pub fn enable_pin_outlet(&self) -> Result<(), HalError> {
+        self.backend()?.enable_outlet(self.port, self.pin_num)
     }
-    pub fn read_pin_inlet_state(&self) -> DioLogic{
-        let p = DioLogic::H;
-        return p;
+
+    pub fn read_pin_inlet_state(&self) -> Result<DioLogic, HalError> {
+        self.backend()?.read_inlet(self.port, self.pin_num)
     }
-    pub fn read_pin_outlet_state(&self) -> DioLogic {
-        let p = DioLogic::H;
-        return p;
+
+    pub fn read_pin_outlet_state(&self) -> Result<DioLogic, HalError> {
+        self.backend()?.read_outlet(self.port, self.pin_num)
     }
-    pub fn write_pin_outlet_state(&self, v: DioLogic) {
 
+    pub fn write_pin_outlet_state(&self, v: DioLogic) -> Result<(), HalError> {
+        self.backend()?.write_outlet(self.port, self.pin_num, v)
     }
-    pub fn toggle_pin_outlet_state(&self) {}
 
-    pub fn set_pin_outlet_high(&self) {
-        gpio::set_high((*self).pin_num);
+    pub fn toggle_pin_outlet_state(&self) -> Result<(), HalError> {
+        let v = match self.read_pin_outlet_state()? {
+            DioLogic::H => DioLogic::L,
+            DioLogic::L => DioLogic::H,
+        };
+        self.write_pin_outlet_state(v)
     }
 
-    pub fn set_pin_outlet_low(&self) {
-        gpio::set_low((*self).pin_num);
+    pub fn set_pin_outlet_high(&self) -> Result<(), HalError> {
+        self.write_pin_outlet_state(DioLogic::H)
+    }
+
+    pub fn set_pin_outlet_low(&self) -> Result<(), HalError> {
+        self.write_pin_outlet_state(DioLogic::L)
     }
 
     pub fn set_pin_dir_as_in(&self) {}
@@ -89,13 +292,178 @@ impl DioPin {
         return p;
     }
 
-    pub fn set_pin_func_mode(&self, mode: &DioFuncMode){
+    /// Only pins of the on-chip GPIO have peripheral functions, other
+    /// instances fail with `Unsupported`.
+    pub fn set_pin_func_mode(&self, mode: &DioFuncMode) -> Result<(), HalError> {
+        self.on_chip()?;
         match mode {
-            DioFuncMode::Gpio => gpio::set_as_dio((*self).pin_num),
-            DioFuncMode::Mux => gpio::set_as_iof((*self).pin_num),
+            DioFuncMode::Gpio => gpio::set_as_dio(&MMIO, self.pin_num),
+            DioFuncMode::Mux => gpio::set_as_iof(&MMIO, self.pin_num),
+        }
+        Ok(())
+    }
+
+    pub fn select_pin_iof_func(&self, s: bool) -> Result<(), HalError> {
+        self.on_chip()?;
+        gpio::select_iof_func(&MMIO, self.pin_num, s);
+        Ok(())
+    }
+
+    /// Hands the pin to a peripheral, IOF1 if `iof1` else IOF0.
+    pub(crate) fn mux_to_iof(&self, iof1: bool) -> Result<(), HalError> {
+        self.set_pin_func_mode(&DioFuncMode::Mux)?;
+        self.select_pin_iof_func(iof1)
+    }
+
+    fn on_chip(&self) -> Result<(), HalError> {
+        if self.instance == 0 { Ok(()) } else { Err(HalError::Unsupported) }
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+pub use self::edge_async::FallingEdge;
+#[cfg(target_arch = "riscv32")]
+pub(crate) use self::edge_async::dio_gpio_interrupt;
+
+#[cfg(target_arch = "riscv32")]
+mod edge_async {
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+
+    use super::{gpio, DioPin, DioPinNum};
+    use crate::error::HalError;
+    use crate::event::EventFlags;
+    use crate::executor::WakerSlot;
+    use crate::interrupt::{self, MIE_MEIE};
+    use crate::mmio::MMIO;
+    use crate::plic::{self, PlicIntrPriorityLevels, PlicIntrSources};
+
+    static EDGE_WAKERS: [WakerSlot; 32] = [const { WakerSlot::new() }; 32];
+    /// Pins that saw a falling edge since their future was armed
+    static FALLEN: EventFlags = EventFlags::new();
+
+    impl DioPin {
+        /// Waits for a falling edge on the pin. The inlet must be enabled.
+        /// Only pins of the on-chip GPIO, instance 0, have edge interrupts.
+        pub fn wait_for_falling_edge(&self) -> Result<FallingEdge, HalError> {
+            if self.instance != 0 {
+                return Err(HalError::InvalidInstance);
+            }
+            let src = PlicIntrSources::gpio(self.pin_num)?;
+            Ok(FallingEdge { pin: self.pin_num, src, armed: false })
+        }
+    }
+
+    /// Future returned by `DioPin::wait_for_falling_edge`.
+    pub struct FallingEdge {
+        pin: DioPinNum,
+        src: PlicIntrSources,
+        armed: bool,
+    }
+
+    impl Future for FallingEdge {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let bit = 1 << self.pin;
+
+            if !self.armed {
+                // Only edges after the first poll count
+                gpio::clear_fall_pending(&MMIO, self.pin);
+                FALLEN.clear(bit);
+                self.armed = true;
+            } else if FALLEN.take(bit) != 0 {
+                return Poll::Ready(());
+            }
+
+            EDGE_WAKERS[self.pin as usize].register(cx.waker());
+
+            plic::plic_set_intr_priority_for_src(&MMIO, self.src, PlicIntrPriorityLevels::level1_highest);
+            plic::plic_enable_src_to_interrupt(&MMIO, self.src);
+            gpio::enable_fall_intr(&MMIO, self.pin);
+            interrupt::mie_enable(MIE_MEIE);
+
+            Poll::Pending
+        }
+    }
+
+    impl Drop for FallingEdge {
+        fn drop(&mut self) {
+            if self.armed {
+                gpio::disable_fall_intr(&MMIO, self.pin);
+            }
+        }
+    }
+
+    /// PLIC interrupt of GPIO `pin`.
+    pub(crate) fn dio_gpio_interrupt(pin: DioPinNum) {
+        if gpio::fall_pending(&MMIO) & (1 << pin) != 0 {
+            gpio::disable_fall_intr(&MMIO, pin);
+            gpio::clear_fall_pending(&MMIO, pin);
+            FALLEN.set(1 << pin);
+            EDGE_WAKERS[pin as usize].wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicU8, Ordering};
+
+    /// One port of 8 outputs
+    struct Latch(AtomicU8);
+
+    impl DioBackend for Latch {
+        fn ports(&self) -> DioPort { 1 }
+        fn pins_per_port(&self) -> DioPinNum { 8 }
+
+        fn enable_inlet(&self, _port: DioPort, _pin: DioPinNum) -> Result<(), HalError> {
+            Err(HalError::Unsupported)
+        }
+
+        fn enable_outlet(&self, _port: DioPort, _pin: DioPinNum) -> Result<(), HalError> {
+            Ok(())
+        }
+
+        fn read_inlet(&self, _port: DioPort, _pin: DioPinNum) -> Result<DioLogic, HalError> {
+            Err(HalError::Unsupported)
+        }
+
+        fn read_outlet(&self, _port: DioPort, pin: DioPinNum) -> Result<DioLogic, HalError> {
+            Ok(DioLogic::from(self.0.load(Ordering::Relaxed) & (1 << pin) != 0))
+        }
+
+        fn write_outlet(&self, _port: DioPort, pin: DioPinNum, v: DioLogic) -> Result<(), HalError> {
+            match v {
+                DioLogic::H => self.0.fetch_or(1 << pin, Ordering::Relaxed),
+                DioLogic::L => self.0.fetch_and(!(1 << pin), Ordering::Relaxed),
+            };
+            Ok(())
         }
     }
-    pub fn select_pin_iof_func(&self, s: bool){
-        gpio::select_iof_func((*self).pin_num, s);
+
+    static LATCH: Latch = Latch(AtomicU8::new(0));
+
+    #[test]
+    fn pins_dispatch_to_registered_backend() {
+        assert!(matches!(dio_register(0, &LATCH), Err(HalError::InvalidInstance)));
+        assert!(matches!(dio_register(DIO_MAX_INSTANCES as u8, &LATCH), Err(HalError::InvalidInstance)));
+
+        let bank = dio_register(2, &LATCH).unwrap();
+        assert!(matches!(dio_register(2, &LATCH), Err(HalError::InvalidInstance)));
+        assert!(matches!(bank.pin(0, 8), Err(HalError::InvalidPin)));
+
+        let p = bank.pin(0, 5).unwrap();
+        assert!(matches!(bank.pin(0, 5), Err(HalError::InUse)));
+        p.set_pin_outlet_high().unwrap();
+        p.toggle_pin_outlet_state().unwrap();
+        p.toggle_pin_outlet_state().unwrap();
+        assert_eq!(LATCH.0.load(Ordering::Relaxed), 1 << 5);
+        assert_eq!(p.read_pin_outlet_state(), Ok(DioLogic::H));
+
+        assert_eq!(p.read_pin_inlet_state(), Err(HalError::Unsupported));
+        assert_eq!(p.set_pin_func_mode(&DioFuncMode::Mux), Err(HalError::Unsupported));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,25 @@
+//! # HAL Errors
+//!
+//! Driver functions report bad arguments and hardware that does not
+//! respond with a `HalError` instead of panicking, so the firmware can
+//! decide to retry, fall back or report the problem.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HalError {
+    /// No peripheral with this instance number
+    InvalidInstance,
+    /// No pin with this number on the peripheral
+    InvalidPin,
+    /// Setting the hardware or the driver does not support
+    Unsupported,
+    /// The hardware did not become ready in time
+    Timeout,
+    /// The device did not acknowledge its address or data
+    Nack,
+    /// Baud rate or bus clock not reachable from the input clock
+    BaudOutOfRange,
+    /// Address outside the device or not aligned for the operation
+    InvalidAddress,
+    /// The pin was handed out before
+    InUse,
+}
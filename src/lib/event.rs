@@ -0,0 +1,101 @@
+//! # Event Flags
+//!
+//! A word of event bits that interrupt handlers set and the main loop
+//! waits on and clears. Setting is a single `amoor.w`, clearing an
+//! `amoand.w`, so no critical section is needed on either side and an
+//! event raised between a test and a clear is never lost.
+//!
+//! ```ignore
+//! static EVENTS: EventFlags = EventFlags::new();
+//! const UART_RX: usize = 1 << 0;
+//! const BUTTON: usize = 1 << 1;
+//!
+//! // ISR
+//! EVENTS.set(UART_RX);
+//! // main loop
+//! let ev = EVENTS.wait_any(UART_RX | BUTTON);
+//! ```
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct EventFlags {
+    bits: AtomicUsize,
+}
+
+impl EventFlags {
+    pub const fn new() -> EventFlags {
+        EventFlags { bits: AtomicUsize::new(0) }
+    }
+
+    /// Raises the events in `mask`.
+    pub fn set(&self, mask: usize) {
+        self.bits.fetch_or(mask, Ordering::Release);
+    }
+
+    /// Clears the events in `mask`.
+    pub fn clear(&self, mask: usize) {
+        self.bits.fetch_and(!mask, Ordering::AcqRel);
+    }
+
+    /// Currently raised events.
+    pub fn get(&self) -> usize {
+        self.bits.load(Ordering::Acquire)
+    }
+
+    /// Clears the events in `mask` and returns those that were raised.
+    pub fn take(&self, mask: usize) -> usize {
+        self.bits.fetch_and(!mask, Ordering::AcqRel) & mask
+    }
+
+    /// Sleeps in `wfi` until one of the events in `mask` is raised, then
+    /// takes and returns the raised events of `mask`. The events must be
+    /// raised from an enabled interrupt to wake the hart.
+    #[cfg(target_arch = "riscv32")]
+    pub fn wait_any(&self, mask: usize) -> usize {
+        use crate::interrupt;
+
+        loop {
+            // `wfi` wakes on a pending interrupt even with MIE clear, the
+            // handler then runs once MIE is restored. Testing with MIE
+            // clear keeps an event raised before `wfi` from being missed.
+            let mie = interrupt::disable();
+            let ev = self.take(mask);
+            if ev == 0 {
+                unsafe { core::arch::asm!("wfi"); }
+            }
+            if mie {
+                unsafe { interrupt::enable(); }
+            }
+            if ev != 0 {
+                return ev;
+            }
+        }
+    }
+}
+
+impl Default for EventFlags {
+    fn default() -> EventFlags {
+        EventFlags::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_take_and_clear() {
+        let ev = EventFlags::new();
+
+        ev.set(0b0101);
+        ev.set(0b0010);
+        assert_eq!(ev.get(), 0b0111);
+
+        assert_eq!(ev.take(0b1100), 0b0100);
+        assert_eq!(ev.get(), 0b0011);
+
+        ev.clear(0b0001);
+        assert_eq!(ev.take(!0), 0b0010);
+        assert_eq!(ev.get(), 0);
+    }
+}
@@ -0,0 +1,120 @@
+//! # Async Executor
+//!
+//! Single threaded executor for `async` tasks. Tasks are polled only
+//! after their waker was called; with no task ready the hart sleeps in
+//! `wfi`. Wakers are called from the interrupt handlers of the drivers,
+//! so machine interrupts must be enabled:
+//!
+//! ```ignore
+//! interrupt::m_trap_init();
+//! unsafe { interrupt::enable(); }
+//!
+//! let echo = pin!(async { loop { let b = uart.read().await; let _ = uart.do_send_byte(b); } });
+//! let tick = pin!(async { loop { Timer::after(500).await; let _ = uart.do_send_byte(b'.'); } });
+//! let mut tasks: [Pin<&mut dyn Future<Output = ()>>; 2] = [echo, tick];
+//! executor::run(&mut tasks);
+//! ```
+//!
+//! Futures provided by the drivers: `Uart::read`,
+//! `DioPin::wait_for_falling_edge` and `timer::Timer`.
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use crate::event::EventFlags;
+use crate::interrupt::{self, Mutex};
+
+/// Most tasks `run` accepts, one ready bit each
+pub const EXECUTOR_MAX_TASKS: usize = usize::BITS as usize;
+
+/// Ready bit of every task
+static READY: EventFlags = EventFlags::new();
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(waker_clone, waker_wake, waker_wake, waker_drop);
+
+/// The waker data is the task index.
+fn task_waker(task: usize) -> Waker {
+    unsafe { Waker::from_raw(RawWaker::new(task as *const (), &VTABLE)) }
+}
+
+fn waker_clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+fn waker_wake(data: *const ()) {
+    READY.set(1 << data as usize);
+}
+
+fn waker_drop(_: *const ()) {}
+
+/// Polls `tasks` until all have completed. Not reentrant.
+pub fn run(tasks: &mut [Pin<&mut dyn Future<Output = ()>>]) {
+    assert!(tasks.len() <= EXECUTOR_MAX_TASKS, "Too many tasks");
+
+    let mut pending = if tasks.len() == EXECUTOR_MAX_TASKS { usize::MAX } else { (1 << tasks.len()) - 1 };
+    READY.set(pending);
+
+    while pending != 0 {
+        let mut ready = READY.wait_any(pending);
+
+        while ready != 0 {
+            let i = ready.trailing_zeros() as usize;
+            ready &= ready - 1;
+
+            let waker = task_waker(i);
+            if tasks[i].as_mut().poll(&mut Context::from_waker(&waker)).is_ready() {
+                pending &= !(1 << i);
+            }
+        }
+    }
+}
+
+/// Runs `fut` to completion and returns its output. Not reentrant.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let waker = task_waker(0);
+
+    READY.set(1);
+    loop {
+        READY.wait_any(1);
+        if let Poll::Ready(v) = fut.as_mut().poll(&mut Context::from_waker(&waker)) {
+            return v;
+        }
+    }
+}
+
+/// Waker storage shared between a future and an interrupt handler.
+pub struct WakerSlot {
+    waker: Mutex<RefCell<Option<Waker>>>,
+}
+
+impl WakerSlot {
+    pub const fn new() -> WakerSlot {
+        WakerSlot { waker: Mutex::new(RefCell::new(None)) }
+    }
+
+    /// Stores the waker of the polling task, replacing the previous one.
+    pub fn register(&self, w: &Waker) {
+        interrupt::free(|cs| {
+            let mut slot = self.waker.borrow_ref_mut(cs);
+            if !slot.as_ref().is_some_and(|old| old.will_wake(w)) {
+                *slot = Some(w.clone());
+            }
+        });
+    }
+
+    /// Wakes and forgets the stored waker, if any.
+    pub fn wake(&self) {
+        if let Some(w) = interrupt::free(|cs| self.waker.borrow_ref_mut(cs).take()) {
+            w.wake();
+        }
+    }
+}
+
+impl Default for WakerSlot {
+    fn default() -> WakerSlot {
+        WakerSlot::new()
+    }
+}
@@ -0,0 +1,222 @@
+//! # GPIO Expanders
+//!
+//! `DioBackend`s for GPIO expanders, registered as DIO instances with
+//! `dio::dio_register`:
+//!
+//! - `Mcp23017`, 16 pins over I2C, ports A and B of 8 pins
+//! - `Hc595`, a chain of `N` 74HC595 shift registers over SPI, one
+//!   port of 8 outputs per register. The latch clock (RCLK) is wired
+//!   to the chip select, port 0 is the register next to the MCU.
+//!
+//! The drivers keep a copy of the direction and output registers, so
+//! changing one pin does not read the device first.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::dio::{DioBackend, DioLogic, DioPinNum, DioPort};
+use crate::error::HalError;
+use crate::i2c::I2cBus;
+use crate::spi::SpiBus;
+
+/// MCP23017 registers of port A, `IOCON.BANK` = 0; port B is at the
+/// next address
+const MCP23017_IODIR: u8 = 0x00;
+const MCP23017_GPPU: u8 = 0x0C;
+const MCP23017_GPIO: u8 = 0x12;
+const MCP23017_OLAT: u8 = 0x14;
+
+pub struct Mcp23017<B> {
+    bus: B,
+    addr: u8,
+    /// `IODIR`, 1 for inputs
+    iodir: [AtomicU8; 2],
+    gppu: [AtomicU8; 2],
+    olat: [AtomicU8; 2],
+}
+
+impl<B: I2cBus> Mcp23017<B> {
+    /// Expander at 7 bit address `addr`, 0x20 - 0x27. The copies of the
+    /// registers start at their reset values: all pins inputs, low.
+    pub const fn new(bus: B, addr: u8) -> Mcp23017<B> {
+        Mcp23017 {
+            bus,
+            addr,
+            iodir: [AtomicU8::new(0xFF), AtomicU8::new(0xFF)],
+            gppu: [AtomicU8::new(0), AtomicU8::new(0)],
+            olat: [AtomicU8::new(0), AtomicU8::new(0)],
+        }
+    }
+
+    /// Updates bit `pin` of the copy `regs[port]` and writes it to
+    /// register `reg` of the port.
+    fn update(&self, regs: &[AtomicU8; 2], reg: u8, port: DioPort, pin: DioPinNum, set: bool) -> Result<(), HalError> {
+        let bit = 1 << pin;
+        let r = &regs[port as usize];
+        let v = if set {
+            r.fetch_or(bit, Ordering::AcqRel) | bit
+        } else {
+            r.fetch_and(!bit, Ordering::AcqRel) & !bit
+        };
+        self.bus.write(self.addr, &[reg + port, v])
+    }
+}
+
+impl<B: I2cBus> DioBackend for Mcp23017<B> {
+    fn ports(&self) -> DioPort { 2 }
+    fn pins_per_port(&self) -> DioPinNum { 8 }
+
+    fn enable_inlet(&self, port: DioPort, pin: DioPinNum) -> Result<(), HalError> {
+        self.update(&self.iodir, MCP23017_IODIR, port, pin, true)
+    }
+
+    fn enable_outlet(&self, port: DioPort, pin: DioPinNum) -> Result<(), HalError> {
+        self.update(&self.iodir, MCP23017_IODIR, port, pin, false)
+    }
+
+    fn read_inlet(&self, port: DioPort, pin: DioPinNum) -> Result<DioLogic, HalError> {
+        let mut v = [0];
+        self.bus.write_read(self.addr, &[MCP23017_GPIO + port], &mut v)?;
+        Ok(DioLogic::from(v[0] & (1 << pin) != 0))
+    }
+
+    fn read_outlet(&self, port: DioPort, pin: DioPinNum) -> Result<DioLogic, HalError> {
+        Ok(DioLogic::from(self.olat[port as usize].load(Ordering::Acquire) & (1 << pin) != 0))
+    }
+
+    fn write_outlet(&self, port: DioPort, pin: DioPinNum, v: DioLogic) -> Result<(), HalError> {
+        self.update(&self.olat, MCP23017_OLAT, port, pin, v == DioLogic::H)
+    }
+
+    fn enable_inlet_pullup(&self, port: DioPort, pin: DioPinNum) -> Result<(), HalError> {
+        self.update(&self.gppu, MCP23017_GPPU, port, pin, true)
+    }
+}
+
+pub struct Hc595<B, const N: usize> {
+    bus: B,
+    outputs: [AtomicU8; N],
+}
+
+impl<B: SpiBus, const N: usize> Hc595<B, N> {
+    /// All outputs start low, call `flush` to write them.
+    pub const fn new(bus: B) -> Hc595<B, N> {
+        Hc595 { bus, outputs: [const { AtomicU8::new(0) }; N] }
+    }
+
+    /// Shifts the outputs of all registers out, the farthest first.
+    pub fn flush(&self) -> Result<(), HalError> {
+        let mut bytes = [0; N];
+        for (b, o) in bytes.iter_mut().zip(self.outputs.iter().rev()) {
+            *b = o.load(Ordering::Acquire);
+        }
+        self.bus.write(&bytes)
+    }
+}
+
+impl<B: SpiBus, const N: usize> DioBackend for Hc595<B, N> {
+    fn ports(&self) -> DioPort { N as DioPort }
+    fn pins_per_port(&self) -> DioPinNum { 8 }
+
+    fn enable_inlet(&self, _port: DioPort, _pin: DioPinNum) -> Result<(), HalError> {
+        Err(HalError::Unsupported)
+    }
+
+    /// Pins are always outputs.
+    fn enable_outlet(&self, _port: DioPort, _pin: DioPinNum) -> Result<(), HalError> {
+        Ok(())
+    }
+
+    fn read_inlet(&self, _port: DioPort, _pin: DioPinNum) -> Result<DioLogic, HalError> {
+        Err(HalError::Unsupported)
+    }
+
+    fn read_outlet(&self, port: DioPort, pin: DioPinNum) -> Result<DioLogic, HalError> {
+        Ok(DioLogic::from(self.outputs[port as usize].load(Ordering::Acquire) & (1 << pin) != 0))
+    }
+
+    fn write_outlet(&self, port: DioPort, pin: DioPinNum, v: DioLogic) -> Result<(), HalError> {
+        let o = &self.outputs[port as usize];
+        match v {
+            DioLogic::H => o.fetch_or(1 << pin, Ordering::AcqRel),
+            DioLogic::L => o.fetch_and(!(1 << pin), Ordering::AcqRel),
+        };
+        self.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    /// Records writes, reads return `input`
+    #[derive(Default)]
+    struct FakeBus {
+        writes: Mutex<Vec<Vec<u8>>>,
+        input: u8,
+    }
+
+    impl FakeBus {
+        fn writes(&self) -> Vec<Vec<u8>> {
+            self.writes.lock().unwrap().clone()
+        }
+    }
+
+    impl I2cBus for FakeBus {
+        fn write_read(&self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), HalError> {
+            assert_eq!(addr, 0x21);
+            self.writes.lock().unwrap().push(bytes.to_vec());
+            buf.fill(self.input);
+            Ok(())
+        }
+    }
+
+    impl SpiBus for FakeBus {
+        fn write(&self, bytes: &[u8]) -> Result<(), HalError> {
+            self.writes.lock().unwrap().push(bytes.to_vec());
+            Ok(())
+        }
+
+        fn transfer(&self, _buf: &mut [u8]) -> Result<(), HalError> {
+            Err(HalError::Unsupported)
+        }
+    }
+
+    #[test]
+    fn mcp23017_writes_port_registers() {
+        let io = Mcp23017::new(FakeBus::default(), 0x21);
+
+        io.enable_outlet(1, 2).unwrap();
+        io.write_outlet(1, 2, DioLogic::H).unwrap();
+        io.write_outlet(0, 7, DioLogic::H).unwrap();
+        io.write_outlet(1, 2, DioLogic::L).unwrap();
+        io.enable_inlet_pullup(0, 0).unwrap();
+
+        assert_eq!(io.bus.writes(), [[0x01, 0xFB], [0x15, 0x04], [0x14, 0x80], [0x15, 0x00], [0x0C, 0x01]]);
+        assert_eq!(io.read_outlet(0, 7), Ok(DioLogic::H));
+    }
+
+    #[test]
+    fn mcp23017_reads_gpio_register() {
+        let io = Mcp23017::new(FakeBus { input: 0x10, ..Default::default() }, 0x21);
+
+        assert_eq!(io.read_inlet(1, 4), Ok(DioLogic::H));
+        assert_eq!(io.read_inlet(1, 3), Ok(DioLogic::L));
+        assert_eq!(io.bus.writes(), [[0x13], [0x13]]);
+    }
+
+    #[test]
+    fn hc595_shifts_farthest_register_first() {
+        let chain: Hc595<FakeBus, 3> = Hc595::new(FakeBus::default());
+
+        chain.write_outlet(0, 0, DioLogic::H).unwrap();
+        chain.write_outlet(2, 7, DioLogic::H).unwrap();
+
+        assert_eq!(chain.bus.writes(), [[0x00, 0x00, 0x01], [0x80, 0x00, 0x01]]);
+        assert_eq!(chain.read_outlet(2, 7), Ok(DioLogic::H));
+        assert_eq!(chain.read_inlet(0, 0), Err(HalError::Unsupported));
+    }
+}
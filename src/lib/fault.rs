@@ -0,0 +1,205 @@
+//! # Fault Diagnostics
+//!
+//! Synchronous exceptions that are not `ecall` end up in `fault_handle`.
+//! It decodes `mcause`, dumps the saved registers to the registered
+//! writer and passes a `FaultInfo` to the application hook, which
+//! decides whether to halt, reset or skip the faulting instruction.
+
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::ptr;
+
+use crate::interrupt::TrapFrame;
+use crate::mmio::MMIO;
+
+/// Byte sink used for the register dump, e.g. a UART send function.
+pub type FaultWriter = fn(u8);
+pub type FaultHook = fn(&FaultInfo) -> FaultAction;
+
+const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2",
+    "s0", "s1", "a0", "a1", "a2", "a3", "a4", "a5",
+    "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+    "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6",
+];
+
+static mut FAULT_WRITER: Option<FaultWriter> = None;
+static mut FAULT_HOOK: Option<FaultHook> = None;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultKind {
+    InstrAddrMisaligned,
+    InstrAccessFault,
+    IllegalInstr,
+    Breakpoint,
+    LoadAddrMisaligned,
+    LoadAccessFault,
+    StoreAddrMisaligned,
+    StoreAccessFault,
+    EcallFromUmode,
+    EcallFromMmode,
+    Reserved(u32),
+}
+
+impl FaultKind {
+    pub fn from_mcause(mcause: u32) -> FaultKind {
+        match mcause & 0x7FFF_FFFF {
+            0 => FaultKind::InstrAddrMisaligned,
+            1 => FaultKind::InstrAccessFault,
+            2 => FaultKind::IllegalInstr,
+            3 => FaultKind::Breakpoint,
+            4 => FaultKind::LoadAddrMisaligned,
+            5 => FaultKind::LoadAccessFault,
+            6 => FaultKind::StoreAddrMisaligned,
+            7 => FaultKind::StoreAccessFault,
+            8 => FaultKind::EcallFromUmode,
+            11 => FaultKind::EcallFromMmode,
+            other => FaultKind::Reserved(other),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FaultKind::InstrAddrMisaligned => "Instruction address misaligned",
+            FaultKind::InstrAccessFault => "Instruction access fault",
+            FaultKind::IllegalInstr => "Illegal instruction",
+            FaultKind::Breakpoint => "Breakpoint",
+            FaultKind::LoadAddrMisaligned => "Load address misaligned",
+            FaultKind::LoadAccessFault => "Load access fault",
+            FaultKind::StoreAddrMisaligned => "Store/AMO address misaligned",
+            FaultKind::StoreAccessFault => "Store/AMO access fault",
+            FaultKind::EcallFromUmode => "Environment call from U-mode",
+            FaultKind::EcallFromMmode => "Environment call from M-mode",
+            FaultKind::Reserved(_) => "Reserved exception",
+        }
+    }
+
+    /// Faults raised while fetching, `mepc` cannot be read or skipped.
+    fn is_fetch_fault(&self) -> bool {
+        matches!(self, FaultKind::InstrAddrMisaligned | FaultKind::InstrAccessFault)
+    }
+}
+
+/// Snapshot of the faulting context handed to the hook.
+pub struct FaultInfo<'a> {
+    pub kind: FaultKind,
+    pub mcause: u32,
+    /// Address of the faulting instruction
+    pub mepc: u32,
+    /// Faulting address for access faults, instruction bits for illegal instruction
+    pub mtval: u32,
+    pub mstatus: u32,
+    pub from_umode: bool,
+    /// All registers at the time of the fault
+    pub frame: &'a TrapFrame,
+}
+
+impl<'a> FaultInfo<'a> {
+    pub fn new(frame: &'a TrapFrame) -> FaultInfo<'a> {
+        FaultInfo {
+            kind: FaultKind::from_mcause(frame.mcause),
+            mcause: frame.mcause,
+            mepc: frame.mepc,
+            mtval: frame.mtval,
+            mstatus: frame.mstatus,
+            from_umode: frame.is_from_umode(),
+            frame,
+        }
+    }
+}
+
+pub enum FaultAction {
+    /// Stop in a `wfi` loop, a debugger can still attach
+    Halt,
+    /// Reset the SoC through the AON watchdog
+    Reset,
+    /// Continue after the faulting instruction. Treated as `Halt`
+    /// for instruction fetch faults.
+    Resume,
+}
+
+/// Sets the byte sink used to dump faults. Without a writer nothing is printed.
+pub fn fault_set_writer(w: FaultWriter) {
+    unsafe { FAULT_WRITER = Some(w); }
+}
+
+/// Sets the hook called with every M-mode fault. Without a hook the hart halts.
+pub fn fault_set_hook(hook: FaultHook) {
+    unsafe { FAULT_HOOK = Some(hook); }
+}
+
+/// Dumps the fault held in `frame` to the fault writer.
+pub fn fault_report(frame: &TrapFrame) {
+    if let Some(w) = unsafe { FAULT_WRITER } {
+        let _ = fault_write(&mut FaultWriterFmt(w), &FaultInfo::new(frame));
+    }
+}
+
+/// Default handler for synchronous exceptions taken in M-mode.
+pub(crate) fn fault_handle(frame: &mut TrapFrame) {
+
+    fault_report(frame);
+
+    let info = FaultInfo::new(frame);
+    let action = match unsafe { FAULT_HOOK } {
+        Some(hook) => hook(&info),
+        None => FaultAction::Halt,
+    };
+
+    match action {
+        FaultAction::Resume if !info.kind.is_fetch_fault() => {
+            frame.mepc += fault_instr_len(frame.mepc);
+        }
+        FaultAction::Reset => fault_reset(),
+        _ => fault_halt(),
+    }
+}
+
+/// Writes a human readable dump of `info` to `w`.
+pub fn fault_write(w: &mut dyn Write, info: &FaultInfo) -> fmt::Result {
+    write!(w, "\r\n*** FAULT: {}", info.kind.name())?;
+    if info.from_umode {
+        write!(w, " (U-mode)")?;
+    }
+    write!(w, "\r\nmcause  0x{:08x}  mepc 0x{:08x}  mtval 0x{:08x}  mstatus 0x{:08x}\r\n",
+           info.mcause, info.mepc, info.mtval, info.mstatus)?;
+
+    for (i, name) in REG_NAMES.iter().enumerate().skip(1) {
+        write!(w, "{:>4} 0x{:08x}", name, info.frame.regs[i])?;
+        if i % 4 == 3 {
+            write!(w, "\r\n")?;
+        } else {
+            write!(w, "  ")?;
+        }
+    }
+    Ok(())
+}
+
+struct FaultWriterFmt(FaultWriter);
+
+impl Write for FaultWriterFmt {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            (self.0)(b);
+        }
+        Ok(())
+    }
+}
+
+/// Length of the instruction at `addr`, 2 for compressed instructions.
+fn fault_instr_len(addr: u32) -> u32 {
+    let half = unsafe { ptr::read_volatile(addr as *const u16) };
+    if half & 0x3 == 0x3 { 4 } else { 2 }
+}
+
+fn fault_halt() -> ! {
+    loop {
+        unsafe { asm!("wfi"); }
+    }
+}
+
+/// Lets the AON watchdog expire immediately with reset enabled.
+fn fault_reset() -> ! {
+    crate::wdog::wdog_reset(&MMIO);
+    fault_halt()
+}
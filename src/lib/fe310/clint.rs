@@ -0,0 +1,93 @@
+//!  FE310-G002 Core Local Interruptor
+//!
+//! `mtime` counts at the 32.768 kHz real time clock. A machine timer
+//! interrupt is pending while `mtime >= mtimecmp`.
+
+use crate::mmio::RegAccess;
+use crate::regs::{clint, Reg};
+
+/// `mtime` ticks per second
+pub const MTIME_HZ: u32 = 32_768;
+
+/// CLINT register `$reg`
+macro_rules! clint_reg {
+    ($r:expr, $reg:ident) => { Reg::<clint::$reg::Spec, _>::new($r, clint::BASE) }
+}
+
+/// Reads the 64 bit `mtime`, retrying when the low word wrapped
+/// between the reads of the two halves.
+pub fn clint_read_mtime (r: &impl RegAccess) -> u64 {
+    loop {
+        let hi = clint_reg!(r, mtime_hi).read().bits();
+        let lo = clint_reg!(r, mtime_lo).read().bits();
+        if clint_reg!(r, mtime_hi).read().bits() == hi {
+            return ((hi as u64) << 32) | lo as u64;
+        }
+    }
+}
+
+/// Sets `mtimecmp` without raising a spurious interrupt: the high word
+/// is parked at its maximum while the low word is written.
+pub fn clint_set_mtimecmp (r: &impl RegAccess, cmp: u64) {
+    let hi = clint_reg!(r, mtimecmp_hi);
+    hi.write(|w| w.bits(u32::MAX));
+    clint_reg!(r, mtimecmp_lo).write(|w| w.bits(cmp as u32));
+    hi.write(|w| w.bits((cmp >> 32) as u32));
+}
+
+/// Raises or clears the machine software interrupt of hart 0.
+pub fn clint_set_msip (r: &impl RegAccess, pending: bool) {
+    clint_reg!(r, msip).write(|w| w.msip().bit(pending));
+}
+
+/// `mtime` ticks in `ms` milliseconds, rounded up.
+pub fn clint_ms_to_ticks (ms: u32) -> u64 {
+    (ms as u64 * MTIME_HZ as u64).div_ceil(1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::mock::{MockRegs, RegOp};
+
+    #[test]
+    fn register_addresses_match_fe310_map() {
+        let r = MockRegs::new();
+        assert_eq!(clint_reg!(&r, msip).addr(), 0x0200_0000);
+        assert_eq!(clint_reg!(&r, mtimecmp_lo).addr(), 0x0200_4000);
+        assert_eq!(clint_reg!(&r, mtimecmp_hi).addr(), 0x0200_4004);
+        assert_eq!(clint_reg!(&r, mtime_lo).addr(), 0x0200_BFF8);
+        assert_eq!(clint_reg!(&r, mtime_hi).addr(), 0x0200_BFFC);
+    }
+
+    #[test]
+    fn mtime_read_retries_on_carry() {
+        let r = MockRegs::new();
+        r.push_read(0x0200_BFFC, 1);
+        r.push_read(0x0200_BFF8, 0xFFFF_FFFF);
+        r.push_read(0x0200_BFFC, 2);
+        r.push_read(0x0200_BFFC, 2);
+        r.push_read(0x0200_BFF8, 0x10);
+        r.push_read(0x0200_BFFC, 2);
+
+        assert_eq!(clint_read_mtime(&r), 0x2_0000_0010);
+    }
+
+    #[test]
+    fn mtimecmp_written_high_word_last() {
+        let r = MockRegs::new();
+        clint_set_mtimecmp(&r, 0x1_2345_6789);
+        assert_eq!(r.ops(), [
+            RegOp::Write(0x0200_4004, u32::MAX),
+            RegOp::Write(0x0200_4000, 0x2345_6789),
+            RegOp::Write(0x0200_4004, 1),
+        ]);
+    }
+
+    #[test]
+    fn ms_to_ticks_rounds_up() {
+        assert_eq!(clint_ms_to_ticks(1000), 32_768);
+        assert_eq!(clint_ms_to_ticks(1), 33);
+        assert_eq!(clint_ms_to_ticks(0), 0);
+    }
+}
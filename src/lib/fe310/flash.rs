@@ -0,0 +1,86 @@
+//!  FE310-G002 QSPI0 flash commands
+//!
+//! QSPI0 maps the boot flash at 0x2000_0000 while `fctrl.en` is set.
+//! Erase and program commands go through the direct mode FIFOs with
+//! `fctrl.en` clear, so no code or data may be fetched from flash until
+//! the command completes. `_flash_cmd` is therefore written in assembly
+//! and linked in `.ramtext`, which `_start` copies to RAM with `.data`;
+//! the caller keeps interrupts disabled and passes buffers in RAM.
+
+use core::arch::global_asm;
+
+use crate::regs::qspi0;
+
+extern "C" {
+    /// Sends `cmd_len` command bytes and `data_len` data bytes in one
+    /// frame, then polls the status register until the flash is idle and
+    /// returns it. Memory mapped reads are enabled again on return.
+    pub (crate) fn _flash_cmd(cmd: *const u8, cmd_len: usize, data: *const u8, data_len: usize) -> u32;
+}
+
+global_asm!(r#"
+    .section .ramtext.flash_cmd, "ax"
+    .align 2
+    .global _flash_cmd
+_flash_cmd:
+    li t0, {base}
+    sw zero, {fctrl}(t0)
+    li t1, 0x80000
+    sw t1, {fmt}(t0)        # 8 bit single lane frames, MSB first
+1:
+    lw t3, {rxdata}(t0)     # drop stale Rx bytes
+    bgez t3, 1b
+    li t1, 2
+    sw t1, {csmode}(t0)     # HOLD: CS stays asserted between frames
+
+2:
+    beqz a1, 3f
+    lbu t2, 0(a0)
+    jal t6, 8f
+    addi a0, a0, 1
+    addi a1, a1, -1
+    j 2b
+3:
+    beqz a3, 4f
+    lbu t2, 0(a2)
+    jal t6, 8f
+    addi a2, a2, 1
+    addi a3, a3, -1
+    j 3b
+4:
+    sw zero, {csmode}(t0)   # AUTO releases CS, the flash starts the command
+
+5:
+    li t1, 2
+    sw t1, {csmode}(t0)
+    li t2, 0x05             # read status register
+    jal t6, 8f
+    li t2, 0
+    jal t6, 8f
+    sw zero, {csmode}(t0)
+    andi t1, t2, 1          # write in progress
+    bnez t1, 5b
+
+    mv a0, t2
+    li t1, 1
+    sw t1, {fctrl}(t0)
+    ret
+
+    # Sends t2 and returns the byte received meanwhile in t2, link t6
+8:
+    lw t3, {txdata}(t0)
+    bltz t3, 8b
+    sw t2, {txdata}(t0)
+9:
+    lw t2, {rxdata}(t0)
+    bltz t2, 9b
+    andi t2, t2, 0xFF
+    jr t6
+"#,
+    base = const qspi0::BASE,
+    fctrl = const qspi0::fctrl::OFFSET,
+    fmt = const qspi0::fmt::OFFSET,
+    csmode = const qspi0::csmode::OFFSET,
+    txdata = const qspi0::txdata::OFFSET,
+    rxdata = const qspi0::rxdata::OFFSET,
+);
@@ -1,82 +1,165 @@
 //!  Hifive1-RevB board Gpio Interface
 //!
 
-#[repr(C)]
- struct GpioMmapRegs{
-    iput_val: u32, /* Pin Value */
-    iput_async_en: u32, /* Pin input enable */
-    oput_en: u32, /* pin output enable */
-    oput_val: u32, /* Output Value */
-    pue_async: u32, /* Internal pull-up enable*/
-    ds: u32, /* Pin drive strength */
-    rise_ie: u32, /* Rise interrupt enable */
-    rise_ip: u32, /* Rise intr pending */
-    fall_ie: u32, /* Fall intr enable */
-    fall_ip: u32, /* Fall intr pending */
-    high_ie: u32, /* Fall intr enable */
-    high_ip: u32, /* Fall intr pending */
-    low_ie: u32,
-    low_ip: u32,
-    iof_en: u32,
-    iof_sel: u32,
-    out_xor: u32,
-    passthru_high_en: u32,
-    pthru_low_en: u32,
-}
-
-const GPIO: *mut GpioMmapRegs = 0x1001_2000 as *mut GpioMmapRegs; // private to this file 
+use crate::mmio::RegAccess;
+use crate::regs::gpio0;
+
+/// Address of GPIO register `$r`
+macro_rules! gpio_reg {
+    ($r:ident) => { gpio0::BASE + gpio0::$r::OFFSET }
+}
 
 fn generate_mask (num: u8) -> u32{
-    if num == 0 {
-        return 1
-    }else {
-        return 1 << num
-    }
+    1 << num
 }
 
-pub (crate) fn enable_inlet( p: u8) {
-    unsafe {
-        (*GPIO).iput_async_en = (*GPIO).iput_async_en | generate_mask(p);
-    }
+pub (crate) fn enable_inlet(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(input_en), |v| v | generate_mask(p));
 }
 
-pub (crate) fn enable_outlet(p: u8) {
-    unsafe {
-        (*GPIO).oput_en  = (*GPIO).oput_en | generate_mask(p);
-    }
+pub (crate) fn enable_outlet(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(output_en), |v| v | generate_mask(p));
 }
 
-pub (crate) fn set_as_iof(p: u8) {
-    unsafe {
-        (*GPIO).iof_en = (*GPIO).iof_en | generate_mask(p);
-    }
+pub (crate) fn disable_outlet(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(output_en), |v| v & !generate_mask(p));
 }
 
-pub (crate) fn select_iof_func(p: u8, s: bool) {
-    unsafe {
-        if s == true {
-            (*GPIO).iof_sel = (*GPIO).iof_sel | generate_mask(p);
-        }
-        else {
-            (*GPIO).iof_sel = (*GPIO).iof_sel & (!generate_mask(p));
-        }
-    }
+pub (crate) fn enable_pullup(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(pue), |v| v | generate_mask(p));
 }
 
-pub (crate) fn set_as_dio(p: u8) {
-    unsafe {
-        (*GPIO).iof_en = (*GPIO).iof_en & (! generate_mask(p));
-    }
+/// Level on the pin, the inlet must be enabled.
+pub (crate) fn read_input(r: &impl RegAccess, p: u8) -> bool {
+    r.read32(gpio_reg!(input_val)) & generate_mask(p) != 0
+}
+
+/// Level the pin is driven to.
+pub (crate) fn read_output(r: &impl RegAccess, p: u8) -> bool {
+    r.read32(gpio_reg!(output_val)) & generate_mask(p) != 0
+}
+
+pub (crate) fn set_as_iof(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(iof_en), |v| v | generate_mask(p));
 }
 
-pub (crate) fn set_high( p: u8) {
-    unsafe {
-        (*GPIO).oput_val =  (*GPIO).oput_val  | generate_mask(p);
+pub (crate) fn select_iof_func(r: &impl RegAccess, p: u8, s: bool) {
+    if s {
+        r.modify32(gpio_reg!(iof_sel), |v| v | generate_mask(p));
     }
+    else {
+        r.modify32(gpio_reg!(iof_sel), |v| v & !generate_mask(p));
+    }
+}
+
+pub (crate) fn set_as_dio(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(iof_en), |v| v & !generate_mask(p));
+}
+
+pub (crate) fn set_high(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(output_val), |v| v | generate_mask(p));
+}
+
+pub (crate) fn set_low(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(output_val), |v| v & !generate_mask(p));
+}
+
+pub (crate) fn enable_fall_intr(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(fall_ie), |v| v | generate_mask(p));
+}
+
+pub (crate) fn disable_fall_intr(r: &impl RegAccess, p: u8) {
+    r.modify32(gpio_reg!(fall_ie), |v| v & !generate_mask(p));
 }
 
-pub (crate) fn set_low(p: u8) { 
-    unsafe {
-        (*GPIO).oput_val =  (*GPIO).oput_val & (! generate_mask(p)) ;
+/// Pins with a pending falling edge interrupt.
+pub (crate) fn fall_pending(r: &impl RegAccess) -> u32 {
+    r.read32(gpio_reg!(fall_ip))
+}
+
+/// `fall_ip` bits are cleared by writing 1, other pins are left pending.
+pub (crate) fn clear_fall_pending(r: &impl RegAccess, p: u8) {
+    r.write32(gpio_reg!(fall_ip), generate_mask(p));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::mock::MockRegs;
+
+    #[test]
+    fn mask_sets_single_bit() {
+        assert_eq!(generate_mask(0), 0x0000_0001);
+        assert_eq!(generate_mask(21), 0x0020_0000);
+        assert_eq!(generate_mask(31), 0x8000_0000);
+    }
+
+    #[test]
+    fn register_addresses_match_fe310_map() {
+        assert_eq!(gpio_reg!(input_val), 0x1001_2000);
+        assert_eq!(gpio_reg!(output_en), 0x1001_2008);
+        assert_eq!(gpio_reg!(output_val), 0x1001_200C);
+        assert_eq!(gpio_reg!(iof_en), 0x1001_2038);
+        assert_eq!(gpio_reg!(iof_sel), 0x1001_203C);
+    }
+
+    #[test]
+    fn set_high_and_low_keep_other_pins() {
+        let r = MockRegs::new();
+        r.set(gpio_reg!(output_val), 0x0000_0101);
+
+        set_high(&r, 21);
+        assert_eq!(r.get(gpio_reg!(output_val)), 0x0020_0101);
+
+        set_low(&r, 0);
+        assert_eq!(r.get(gpio_reg!(output_val)), 0x0020_0100);
+    }
+
+    #[test]
+    fn enable_outlet_only_touches_output_enable() {
+        let r = MockRegs::new();
+        enable_outlet(&r, 5);
+        assert_eq!(r.writes(gpio_reg!(output_en)), [0x20]);
+        assert_eq!(r.ops().len(), 2);
+    }
+
+    #[test]
+    fn read_input_and_output_levels() {
+        let r = MockRegs::new();
+        r.set(gpio_reg!(input_val), 1 << 3);
+        r.set(gpio_reg!(output_val), 1 << 21);
+
+        assert!(read_input(&r, 3));
+        assert!(!read_input(&r, 21));
+        assert!(read_output(&r, 21));
+        assert!(!read_output(&r, 3));
+    }
+
+    #[test]
+    fn iof_select_and_dio_mode() {
+        let r = MockRegs::new();
+        r.set(gpio_reg!(iof_sel), 0xFFFF_FFFF);
+
+        set_as_iof(&r, 17);
+        select_iof_func(&r, 17, false);
+        assert_eq!(r.get(gpio_reg!(iof_en)), 1 << 17);
+        assert_eq!(r.get(gpio_reg!(iof_sel)), !(1 << 17));
+
+        select_iof_func(&r, 17, true);
+        set_as_dio(&r, 17);
+        assert_eq!(r.get(gpio_reg!(iof_sel)), 0xFFFF_FFFF);
+        assert_eq!(r.get(gpio_reg!(iof_en)), 0);
+    }
+
+    #[test]
+    fn fall_interrupt_enable_and_clear() {
+        let r = MockRegs::new();
+        enable_fall_intr(&r, 19);
+        enable_fall_intr(&r, 2);
+        disable_fall_intr(&r, 2);
+        assert_eq!(r.get(gpio_reg!(fall_ie)), 1 << 19);
+
+        clear_fall_pending(&r, 19);
+        assert_eq!(r.writes(gpio_reg!(fall_ip)), [1 << 19]);
     }
 }
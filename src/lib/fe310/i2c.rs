@@ -0,0 +1,177 @@
+//!  FE310-G002 I2C Master Interface
+//!
+//! The controller is the OpenCores I2C master: a byte is sent or
+//! received per command written to `cr`, `sr.tip` is set until the
+//! command completes.
+
+use crate::error::HalError;
+use crate::mmio::RegAccess;
+use crate::regs::{i2c0, Reg, R};
+
+/// Polls of `sr.tip` before a byte transfer gives up, a byte takes
+/// 9 SCL periods
+const I2C_TIMEOUT_POLLS: u32 = 1 << 16;
+
+/// I2C register `$reg`
+macro_rules! i2c_reg {
+    ($r:expr, $reg:ident) => { Reg::<i2c0::$reg::Spec, _>::new($r, i2c0::BASE) }
+}
+
+/// Prescaler for SCL frequency `scl_hz` from input clock `clk_hz`:
+/// ``` prer = clk / (5 * scl) - 1 ```
+pub (crate) fn i2c_compute_prescaler ( clk_hz: u32, scl_hz: u32) -> Result<u32, HalError> {
+    let prer = scl_hz.checked_mul(5)
+        .and_then(|d| clk_hz.checked_div(d))
+        .and_then(|q| q.checked_sub(1))
+        .ok_or(HalError::BaudOutOfRange)?;
+
+    if prer > 0xFFFF {
+        return Err(HalError::BaudOutOfRange);
+    }
+    Ok(prer)
+}
+
+/// Sets the prescaler and enables the controller, the prescaler can
+/// only be changed while it is disabled.
+pub (crate) fn i2c_enable ( r: &impl RegAccess, prer: u32) {
+    i2c_reg!(r, ctr).write(|w| w.en().clear_bit());
+    i2c_reg!(r, prer_lo).write(|w| w.value().bits(prer & 0xFF));
+    i2c_reg!(r, prer_hi).write(|w| w.value().bits(prer >> 8));
+    i2c_reg!(r, ctr).write(|w| w.en().set_bit());
+}
+
+fn i2c_wait ( r: &impl RegAccess) -> Result<R<i2c0::sr::Spec>, HalError> {
+    i2c_wait_polls(r, I2C_TIMEOUT_POLLS)
+}
+
+fn i2c_wait_polls ( r: &impl RegAccess, polls: u32) -> Result<R<i2c0::sr::Spec>, HalError> {
+    let sr = i2c_reg!(r, sr);
+
+    for _ in 0..polls {
+        let v = sr.read();
+        if !v.tip() {
+            return Ok(v);
+        }
+    }
+    Err(HalError::Timeout)
+}
+
+/// Sends `b`, after a (repeated) start condition if `start`.
+fn i2c_write_byte ( r: &impl RegAccess, b: u8, start: bool) -> Result<(), HalError> {
+    i2c_reg!(r, txr).write(|w| w.data().bits(b as u32));
+    i2c_reg!(r, cr).write(|w| w.wr().set_bit().sta().bit(start));
+
+    if i2c_wait(r)?.rxack() {
+        return Err(HalError::Nack);
+    }
+    Ok(())
+}
+
+/// Receives a byte, the last byte of a read is not acknowledged.
+fn i2c_read_byte ( r: &impl RegAccess, last: bool) -> Result<u8, HalError> {
+    i2c_reg!(r, cr).write(|w| w.rd().set_bit().ack().bit(last));
+    i2c_wait(r)?;
+    Ok(i2c_reg!(r, rxr).read().data() as u8)
+}
+
+fn i2c_stop ( r: &impl RegAccess) -> Result<(), HalError> {
+    i2c_reg!(r, cr).write(|w| w.sto().set_bit());
+    i2c_wait(r).map(|_| ())
+}
+
+/// Writes `bytes` to the device at 7 bit address `addr`, then reads
+/// `buf.len()` bytes after a repeated start if `buf` is not empty. A
+/// stop condition ends the transfer, also when it fails.
+pub (crate) fn i2c_write_read ( r: &impl RegAccess, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), HalError> {
+    let res = i2c_transfer(r, addr, bytes, buf);
+    let stop = i2c_stop(r);
+    res.and(stop)
+}
+
+fn i2c_transfer ( r: &impl RegAccess, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), HalError> {
+    if !bytes.is_empty() || buf.is_empty() {
+        i2c_write_byte(r, addr << 1, true)?;
+        for b in bytes {
+            i2c_write_byte(r, *b, false)?;
+        }
+    }
+
+    if !buf.is_empty() {
+        i2c_write_byte(r, (addr << 1) | 1, true)?;
+        let n = buf.len();
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = i2c_read_byte(r, i + 1 == n)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::mock::MockRegs;
+
+    const I2C_TXR: u32 = 0x1001_600C;
+    const I2C_CR: u32 = 0x1001_6010;
+
+    /// `cr` commands: start, write, read, NACK, stop
+    const STA_WR: u32 = 0x90;
+    const WR: u32 = 0x10;
+    const RD: u32 = 0x20;
+    const RD_NACK: u32 = 0x28;
+    const STO: u32 = 0x40;
+
+    #[test]
+    fn prescaler_for_standard_and_fast_mode() {
+        assert_eq!(i2c_compute_prescaler(16_000_000, 100_000), Ok(31));
+        assert_eq!(i2c_compute_prescaler(16_000_000, 400_000), Ok(7));
+        assert_eq!(i2c_compute_prescaler(16_000_000, 0), Err(HalError::BaudOutOfRange));
+        assert_eq!(i2c_compute_prescaler(16_000_000, 4_000_000), Err(HalError::BaudOutOfRange));
+    }
+
+    #[test]
+    fn enable_writes_prescaler_while_disabled() {
+        let r = MockRegs::new();
+        i2c_enable(&r, 0x1F3);
+        assert_eq!(r.writes(0x1001_6000), [0xF3]);
+        assert_eq!(r.writes(0x1001_6004), [0x01]);
+        assert_eq!(r.writes(0x1001_6008), [0x00, 0x80]);
+    }
+
+    #[test]
+    fn write_then_read_with_repeated_start() {
+        let r = MockRegs::new();
+        // tip is clear and every byte acknowledged
+        for _ in 0..6 {
+            r.push_read(I2C_CR, 0);
+        }
+        r.push_read(I2C_TXR, 0xA5);
+        r.push_read(I2C_TXR, 0x5A);
+
+        let mut buf = [0; 2];
+        i2c_write_read(&r, 0x20, &[0x12], &mut buf).unwrap();
+
+        assert_eq!(buf, [0xA5, 0x5A]);
+        assert_eq!(r.writes(I2C_TXR), [0x40, 0x12, 0x41]);
+        assert_eq!(r.writes(I2C_CR), [STA_WR, WR, STA_WR, RD, RD_NACK, STO]);
+    }
+
+    #[test]
+    fn nack_ends_transfer_with_stop() {
+        let r = MockRegs::new();
+        r.push_read(I2C_CR, 0x80);
+        r.push_read(I2C_CR, 0);
+
+        assert_eq!(i2c_write_read(&r, 0x20, &[0x00, 0xFF], &mut []), Err(HalError::Nack));
+        assert_eq!(r.writes(I2C_CR), [STA_WR, STO]);
+    }
+
+    #[test]
+    fn busy_controller_times_out() {
+        let r = MockRegs::new();
+        for _ in 0..3 {
+            r.push_read(I2C_CR, 0x02);
+        }
+        assert!(matches!(i2c_wait_polls(&r, 3), Err(HalError::Timeout)));
+    }
+}
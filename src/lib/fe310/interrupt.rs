@@ -1,120 +1,395 @@
 
-use core::arch::asm;
-use core::ptr; // for read/write volatile 
+//!  FE310-G002 Machine mode trap handling
+//!
+//! `_m_trap_entry` is installed in `mtvec` (direct mode). It saves the
+//! interrupted context into a `TrapFrame` on the stack, calls
+//! `m_trap_handler` and restores the, possibly modified, frame before `mret`.
+//!
+//! `mscratch` holds the M-mode stack pointer while a hart runs in U-mode
+//! and is 0 while it runs in M-mode.
+//!
+//! A handler can resume a different context by storing the address of
+//! another saved `TrapFrame` in `M_TRAP_NEXT_FRAME`, see `kernel`.
 
-pub type mTrapHandlerFnPtr = fn();
-const TRAP_CAUSE_INTR_BIT_MASk: u32 = 0x8000_0000;
+use core::arch::{asm, global_asm};
+use core::cell::Cell;
+
+use crate::mmio::MMIO;
+use crate::plic;
+
+pub use crate::sync::{CriticalSection, Mutex};
+
+pub type MTrapHandlerFnPtr = extern "C" fn(&mut TrapFrame);
+const TRAP_CAUSE_INTR_BIT_MASK: u32 = 0x8000_0000;
 const INTRPT_EXCEP_CODE_MASK: u32 = 0x0000_000F;
-const HART0_MMODE_CLAIM: u32 =  0x0C20_0004;
+
+pub const MSTATUS_MIE: u32 = 0x0000_0008;
+pub const MSTATUS_MPIE: u32 = 0x0000_0080;
+pub const MSTATUS_MPP_MASK: u32 = 0x0000_1800;
+pub const MSTATUS_MPP_MMODE: u32 = 0x0000_1800;
+pub const MSTATUS_MPP_UMODE: u32 = 0x0000_0000;
+
+pub const MIE_MSIE: u32 = 0x0000_0008;
+pub const MIE_MTIE: u32 = 0x0000_0080;
+pub const MIE_MEIE: u32 = 0x0000_0800;
+
+pub const REG_RA: usize = 1;
+pub const REG_SP: usize = 2;
+pub const REG_A0: usize = 10;
+pub const REG_A1: usize = 11;
+pub const REG_A7: usize = 17;
+
+/// Context saved by `_m_trap_entry`. Keep in sync with the offsets
+/// used in the assembly below.
+#[repr(C)]
+pub struct TrapFrame {
+    /// x0 - x31, `regs[0]` is unused and `regs[2]` is the interrupted `sp`
+    pub regs: [u32; 32], // 0
+    pub mepc: u32,       // 128
+    pub mstatus: u32,    // 132
+    pub mcause: u32,     // 136
+    pub mtval: u32,      // 140
+}
+
+pub const TRAP_FRAME_SIZE: u32 = core::mem::size_of::<TrapFrame>() as u32;
+
+/// Frame restored by `_m_trap_entry` instead of the saved one, when not 0.
+/// Cleared on use.
+#[no_mangle]
+pub(crate) static mut M_TRAP_NEXT_FRAME: u32 = 0;
+
+impl TrapFrame {
+    /// Returns true when the trap was taken from U-mode.
+    pub fn is_from_umode(&self) -> bool {
+        self.mstatus & MSTATUS_MPP_MASK == MSTATUS_MPP_UMODE
+    }
+}
+
+global_asm!(r#"
+    .section .text.m_trap_entry, "ax"
+    .align 2
+    .global _m_trap_entry
+_m_trap_entry:
+    csrrw sp, mscratch, sp
+    bnez sp, 1f
+    csrrw sp, mscratch, sp  # mscratch was 0: trapped from M-mode
+1:
+    addi sp, sp, -144
+    sw x1, 4(sp)
+    sw x3, 12(sp)
+    sw x4, 16(sp)
+    sw x5, 20(sp)
+    sw x6, 24(sp)
+    sw x7, 28(sp)
+    sw x8, 32(sp)
+    sw x9, 36(sp)
+    sw x10, 40(sp)
+    sw x11, 44(sp)
+    sw x12, 48(sp)
+    sw x13, 52(sp)
+    sw x14, 56(sp)
+    sw x15, 60(sp)
+    sw x16, 64(sp)
+    sw x17, 68(sp)
+    sw x18, 72(sp)
+    sw x19, 76(sp)
+    sw x20, 80(sp)
+    sw x21, 84(sp)
+    sw x22, 88(sp)
+    sw x23, 92(sp)
+    sw x24, 96(sp)
+    sw x25, 100(sp)
+    sw x26, 104(sp)
+    sw x27, 108(sp)
+    sw x28, 112(sp)
+    sw x29, 116(sp)
+    sw x30, 120(sp)
+    sw x31, 124(sp)
+
+    csrrw t0, mscratch, zero # U-mode sp, or 0 from M-mode
+    bnez t0, 2f
+    addi t0, sp, 144
+2:
+    sw t0, 8(sp)
+    csrr t0, mepc
+    sw t0, 128(sp)
+    csrr t0, mstatus
+    sw t0, 132(sp)
+    csrr t0, mcause
+    sw t0, 136(sp)
+    csrr t0, mtval
+    sw t0, 140(sp)
+
+    mv a0, sp
+    call m_trap_handler
+
+    la t0, M_TRAP_NEXT_FRAME
+    lw t1, 0(t0)
+    beqz t1, 4f
+    sw zero, 0(t0)
+    mv sp, t1          # Switch to another saved context
+4:
+    lw t0, 128(sp)
+    csrw mepc, t0
+    lw t0, 132(sp)
+    csrw mstatus, t0
+    li t1, 0x1800
+    and t0, t0, t1
+    bnez t0, 3f
+    addi t0, sp, 144 # Returning to U-mode, next trap uses this stack
+    csrw mscratch, t0
+3:
+    lw x1, 4(sp)
+    lw x3, 12(sp)
+    lw x4, 16(sp)
+    lw x5, 20(sp)
+    lw x6, 24(sp)
+    lw x7, 28(sp)
+    lw x8, 32(sp)
+    lw x9, 36(sp)
+    lw x10, 40(sp)
+    lw x11, 44(sp)
+    lw x12, 48(sp)
+    lw x13, 52(sp)
+    lw x14, 56(sp)
+    lw x15, 60(sp)
+    lw x16, 64(sp)
+    lw x17, 68(sp)
+    lw x18, 72(sp)
+    lw x19, 76(sp)
+    lw x20, 80(sp)
+    lw x21, 84(sp)
+    lw x22, 88(sp)
+    lw x23, 92(sp)
+    lw x24, 96(sp)
+    lw x25, 100(sp)
+    lw x26, 104(sp)
+    lw x27, 108(sp)
+    lw x28, 112(sp)
+    lw x29, 116(sp)
+    lw x30, 120(sp)
+    lw x31, 124(sp)
+    lw x2, 8(sp)
+    mret
+"#);
+
+/// Installs `_m_trap_entry` in `mtvec` (direct mode) and marks the
+/// hart as running in M-mode.
+pub fn m_trap_init() {
+    extern "C" {
+        fn _m_trap_entry();
+    }
+    unsafe {
+        asm!("csrw mscratch, zero");
+        asm!("csrw mtvec, {}", in(reg) _m_trap_entry as *const () as usize);
+    }
+}
+
+/// Runs `f` with machine interrupts disabled and restores `mstatus.MIE`
+/// to its previous state afterwards, so calls can nest.
+pub fn free<F, R>(f: F) -> R
+where
+    F: FnOnce(CriticalSection) -> R,
+{
+    let mie = disable();
+    let r = f(unsafe { CriticalSection::new() });
+    if mie {
+        unsafe { enable(); }
+    }
+    r
+}
+
+/// Clears `mstatus.MIE`, returns true when interrupts were enabled.
+pub fn disable() -> bool {
+    let mstatus: u32;
+    unsafe { asm!("csrrci {}, mstatus, {}", out(reg) mstatus, const MSTATUS_MIE); }
+    mstatus & MSTATUS_MIE != 0
+}
+
+/// Sets `mstatus.MIE`.
+///
+/// # Safety
+/// Must not be called inside a critical section, see `free`.
+pub unsafe fn enable() {
+    asm!("csrsi mstatus, {}", const MSTATUS_MIE);
+}
+
+/// Enables the machine interrupts `MIE_*` in `mie`.
+pub fn mie_enable(bits: u32) {
+    unsafe { asm!("csrs mie, {}", in(reg) bits); }
+}
+
+/// Disables the machine interrupts `MIE_*` in `mie`.
+pub fn mie_disable(bits: u32) {
+    unsafe { asm!("csrc mie, {}", in(reg) bits); }
+}
+
+#[cfg(feature = "critical-section")]
+mod cs_impl {
+    struct SingleHart;
+    critical_section::set_impl!(SingleHart);
+
+    unsafe impl critical_section::Impl for SingleHart {
+        unsafe fn acquire() -> critical_section::RawRestoreState {
+            super::disable()
+        }
+
+        unsafe fn release(mie: critical_section::RawRestoreState) {
+            if mie {
+                super::enable();
+            }
+        }
+    }
+}
+
+/// Rx watermark interrupts of each uart seen by `process_mexternal_interrupt`
+static UART_RX_EVENTS: Mutex<[Cell<u32>; 2]> = Mutex::new([Cell::new(0), Cell::new(0)]);
+
+pub fn uart_rx_events(instance: u8) -> u32 {
+    free(|cs| UART_RX_EVENTS.borrow(cs).get(instance as usize).map_or(0, Cell::get))
+}
+
+pub fn uart0_rx_events() -> u32 {
+    uart_rx_events(0)
+}
 
 fn process_mexternal_interrupt()
 {
-    unsafe{
-        let claim_mmap_addr = HART0_MMODE_CLAIM as *const u32;
-        let intr_id = claim_mmap_addr.read_volatile();
-
-        match intr_id {
-            3=> { // Uart0 interrupt 
-                let u0pend_mmap_addr = 0x1001_3000 as *const u32;
-                let u0pend = u0pend_mmap_addr.read_volatile();
-
-                if u0pend as u32 & 0x1 == 1 {
-                    // Tx watermark interrupt 
-                } else if u0pend as u32 & 0x2 == 2 {
-                    // Rx watermark interrupt 
-                    let rcvd_count = 0x8001_0000 as *mut u32; // TDB: Based on ELF size 
-                    *rcvd_count = *rcvd_count + 1; // initialized at start 
-
-                    let rcvd_curaddr = 0x8001_0004 as *mut u32;
-                    *rcvd_curaddr = *rcvd_curaddr + 4;
-                }
+    let intr_id = plic::plic_claim(&MMIO);
+
+    match intr_id {
+        0=> {
+            // Already claimed, nothing to do
+        }
+        3 | 4=> { // Uart0 and Uart1 interrupts
+            let instance = (intr_id - 3) as u8;
+            let pend = crate::serial::uart_interrupt(instance);
+
+            if pend & 0x1 == 1 {
+                // Tx watermark interrupt 
+            } else if pend & 0x2 == 2 {
+                // Rx watermark interrupt 
+                free(|cs| {
+                    let events = &UART_RX_EVENTS.borrow(cs)[instance as usize];
+                    events.set(events.get() + 1);
+                });
             }
-            _ => {
-                panic!("invalide");
+        }
+        8..=39=> { // Gpio interrupt
+            crate::dio::dio_gpio_interrupt((intr_id - 8) as u8);
+        }
+        _ => {
+            // No driver for this source, mask it so it does not fire again
+            if let Some(src) = plic::PlicIntrSources::from_id(intr_id) {
+                plic::plic_disable_src_to_interrupt(&MMIO, src);
             }
         }
     }
+    plic::plic_complete(&MMIO, intr_id);
 }
 
-pub fn m_trap_handler()
+#[no_mangle]
+pub extern "C" fn m_trap_handler(frame: &mut TrapFrame)
 {
-    let mtrap_cause: u32;
+    let mtrap_cause: u32 = frame.mcause;
     let mut is_interrupt: bool = false;
 
-    unsafe{
-        asm!("csrr {}, mcause", out(reg) mtrap_cause);
-    }
-
-    if  mtrap_cause >> 31 & TRAP_CAUSE_INTR_BIT_MASk != 0 {
+    if  mtrap_cause & TRAP_CAUSE_INTR_BIT_MASK != 0 {
         is_interrupt = true;
     }
 
-    if (is_interrupt){ 
+    if is_interrupt {
 
     let async_interrupt = mtrap_cause & INTRPT_EXCEP_CODE_MASK;
 
         match  async_interrupt {
-            0..=2u32=> { 
-                panic!("Rsvd trap cause")
-            }
             3=> {
                 // Machine Software interrupt
-            }
-            4..=6u32=> { 
-                panic!("Rsvd trap cause")
+                crate::clint::clint_set_msip(&MMIO, false);
             }
             7=> {
                 // Machine Timer interrupt
-            }
-            8..=10u32=> { 
-                panic!("Rsvd trap cause")
+                crate::timer::timer_interrupt();
             }
             11=> {
                 // Machine External interrupt
+                process_mexternal_interrupt();
+            }
+            0..=31u32=> {
+                // Reserved cause, mask it so it does not fire again
+                mie_disable(1 << async_interrupt);
             }
             _ => {
-                panic!("Rsvd trap cause")
+                // No `mie` bit to mask, ignore it
             }
         }
+
+        // Interrupts are disabled in the trap handler
+        #[cfg(feature = "kernel")]
+        crate::kernel::kernel_schedule(frame, unsafe { CriticalSection::new() });
     }
     else {
         let sync_exception = mtrap_cause & INTRPT_EXCEP_CODE_MASK;
 
+        if frame.is_from_umode() && sync_exception != 8 {
+            // Faults in U-mode end the user task
+            crate::fault::fault_report(frame);
+            crate::umode::umode_kill(frame);
+            return;
+        }
+
         match sync_exception { // Exceptions
             0=> {
                 // Instruction addr mis-aligned 
+                crate::fault::fault_handle(frame);
             }
             1=> {
                 // Instruction access fault
+                crate::fault::fault_handle(frame);
             }
             2=> {
                 // Illegal Instruction
+                crate::fault::fault_handle(frame);
             }
             3=> {
                 // Break point
+                if crate::semihosting::semihosting_trap(frame) {
+                    return;
+                }
+                crate::fault::fault_handle(frame);
             }
             4=> {
                 // Load addr mis-aligned 
+                crate::fault::fault_handle(frame);
             }
             5=>  { 
                 // Load access fault 
+                crate::fault::fault_handle(frame);
             }
             6=>  { 
                 // Store/AMO addr misaligned 
+                crate::fault::fault_handle(frame);
             }
             7=>  { 
                 // Store/AMO access fault 
+                crate::fault::fault_handle(frame);
             }
             8=>  { 
                 // Environment call from U mode  
+                crate::umode::umode_dispatch_syscall(frame);
             }
             9..=10u32=> { 
-                panic!("Rsvd trap cause")
+                // Rsvd trap cause
+                crate::fault::fault_handle(frame);
             }
             11=>  { 
                 // Environment call from M mode  
+                crate::umode::umode_dispatch_syscall(frame);
+            }
+            _ => {
+                // Rsvd trap cause
+                crate::fault::fault_handle(frame);
             }
-            _ => { panic!("Rsvd trap cause") }
         }
     }
 }
\ No newline at end of file
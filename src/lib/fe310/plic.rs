@@ -1,25 +1,17 @@
 
-use core::ptr; // for read/write volatile 
-
-const COUNT_PLIC_INTR_SRCS: usize = 52;
-
-#[repr(C)]
-struct PlicMemMap {
-    rsvd1: u32, // start addr 0x0C00_0000
-    src_priority: [u32; COUNT_PLIC_INTR_SRCS], // start addr 0x0C00_0004
-    rsvd2_blk: [u32; 971], // 0x0C00_00D4
-    pend1: u32, // 0x0C00_1000
-    pend2: u32, // 0x0C00_1004
-    rsvd3_blk: [u32; 1022], // 0x0C00_1008
-    Hart0MmodeIe1: u32, // 0x0C00_2000
-    Hart0MmodeIe2: u32, // 0x0C00_2004
-    rsvd4_blk: [u32; 522238], // 0x0C00_2008
-    Hart0MmodePriThreshold: u32, // 0x0C20_0000
-    Hart0MmodeClaimComplete: u32, // 0x0C20_0004
-}
+//!  FE310-G002 Platform Level Interrupt Controller
+
+use crate::error::HalError;
+use crate::mmio::RegAccess;
+use crate::regs::{plic, Reg};
 
-const FE310_PLIC_MMAP: *mut PlicMemMap = 0x0C00_0000 as *mut PlicMemMap;
+/// PLIC register `$reg`, `$reg[$i]` for a register of an array
+macro_rules! plic_reg {
+    ($r:expr, $reg:ident) => { Reg::<plic::$reg::Spec, _>::new($r, plic::BASE) };
+    ($r:expr, $reg:ident[$i:expr]) => { Reg::<plic::$reg::Spec, _>::at($r, plic::BASE, $i) };
+}
 
+#[allow(non_camel_case_types)]
 pub enum PlicIntrPriorityLevels {
     level0_dont_interrupt = 0,
     level1_highest,
@@ -31,6 +23,9 @@ pub enum PlicIntrPriorityLevels {
     level7_lowest,
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u32)]
 pub enum PlicIntrSources {
     aon_wdog = 1,
     aon_rtc,
@@ -86,40 +81,142 @@ pub enum PlicIntrSources {
     i2c, // 52
 }
 
-pub fn plic_set_priority_threshold (pthreshold: PlicIntrPriorityLevels /* hart: u8 */){
+impl PlicIntrSources {
+    /// Source with interrupt id `id`, as returned by a claim.
+    pub fn from_id(id: u32) -> Option<PlicIntrSources> {
+        if (PlicIntrSources::aon_wdog as u32..=PlicIntrSources::i2c as u32).contains(&id) {
+            // The variants cover every id in the range
+            Some(unsafe { core::mem::transmute::<u32, PlicIntrSources>(id) })
+        } else {
+            None
+        }
+    }
 
-    unsafe {
-        let x = &(*FE310_PLIC_MMAP).Hart0MmodePriThreshold as *const u32;
-        let y = x as *mut u32;
-        ptr::write_volatile(y, pthreshold as u32);
+    /// Source of GPIO pin `pin`, 0 - 31.
+    pub fn gpio(pin: u8) -> Result<PlicIntrSources, HalError> {
+        if pin >= 32 {
+            return Err(HalError::InvalidPin);
+        }
+        PlicIntrSources::from_id(PlicIntrSources::gpio0 as u32 + pin as u32).ok_or(HalError::InvalidPin)
     }
 }
 
-pub fn plic_enable_src_to_interrupt (src: PlicIntrSources) {
+pub fn plic_set_priority_threshold (r: &impl RegAccess, pthreshold: PlicIntrPriorityLevels /* hart: u8 */){
+    plic_reg!(r, threshold).write(|w| w.threshold().bits(pthreshold as u32));
+}
 
-    match src {
-        uart0 => {
-            unsafe {
-                let x = &(*FE310_PLIC_MMAP).Hart0MmodeIe1 as *const u32;
-                let y = x as *mut u32;
-                ptr::write_volatile(y, ptr::read_volatile(x) | PlicIntrSources::uart0 as u32);
-            }
-        }
-        other => { panic!("PANIC ###"); }
-    }
+pub fn plic_enable_src_to_interrupt (r: &impl RegAccess, src: PlicIntrSources) {
+    // Sources 0-31 are in enable[0] and 32-63 in enable[1]
+    let bit = 1 << (src as u32 % 32);
+    plic_reg!(r, enable[src as usize / 32]).modify(|v, w| w.bits(v.bits() | bit));
+}
+
+pub fn plic_disable_src_to_interrupt (r: &impl RegAccess, src: PlicIntrSources) {
+    let bit = 1 << (src as u32 % 32);
+    plic_reg!(r, enable[src as usize / 32]).modify(|v, w| w.bits(v.bits() & !bit));
 }
 
-pub fn plic_set_intr_priority_for_src (src: PlicIntrSources, p: PlicIntrPriorityLevels) {
+pub fn plic_set_intr_priority_for_src (r: &impl RegAccess, src: PlicIntrSources, p: PlicIntrPriorityLevels) {
+    // priority[0] holds the priority of source 1
+    plic_reg!(r, priority[src as usize - 1]).write(|w| w.priority().bits(p as u32));
+}
 
-    match src {
-        uart0 => {
-            unsafe {
-                let ptr = &(*FE310_PLIC_MMAP).src_priority as *const u32;
-                let y = ptr as *mut u32;
-                // *y.offset(PlicIntrSources::uart0 as isize) = p as u32;
-                ptr::write_volatile(y.offset(PlicIntrSources::uart0 as isize), p as u32);
-            }
-        }
-        other => { panic!("PANIC ###"); }
+/// Enable bits of sources `32 * word` to `32 * word + 31`.
+pub fn plic_enabled (r: &impl RegAccess, word: usize) -> u32 {
+    plic_reg!(r, enable[word]).read().bits()
+}
+
+/// Pending bits of sources `32 * word` to `32 * word + 31`.
+pub fn plic_pending (r: &impl RegAccess, word: usize) -> u32 {
+    plic_reg!(r, pending[word]).read().bits()
+}
+
+pub fn plic_priority (r: &impl RegAccess, src: PlicIntrSources) -> u32 {
+    plic_reg!(r, priority[src as usize - 1]).read().priority()
+}
+
+pub fn plic_priority_threshold (r: &impl RegAccess) -> u32 {
+    plic_reg!(r, threshold).read().threshold()
+}
+
+/// Claims the highest priority pending interrupt, 0 when none is pending.
+pub fn plic_claim (r: &impl RegAccess) -> u32 {
+    plic_reg!(r, claim).read().bits()
+}
+
+/// Signals the end of handling interrupt `id` returned by `plic_claim`.
+pub fn plic_complete (r: &impl RegAccess, id: u32) {
+    plic_reg!(r, claim).write(|w| w.bits(id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::mock::MockRegs;
+
+    #[test]
+    fn register_addresses_match_fe310_map() {
+        let r = MockRegs::new();
+        assert_eq!(plic_reg!(&r, priority[0]).addr(), 0x0C00_0004);
+        assert_eq!(plic_reg!(&r, pending[0]).addr(), 0x0C00_1000);
+        assert_eq!(plic_reg!(&r, enable[1]).addr(), 0x0C00_2004);
+        assert_eq!(plic_reg!(&r, threshold).addr(), 0x0C20_0000);
+        assert_eq!(plic_reg!(&r, claim).addr(), 0x0C20_0004);
+    }
+
+    #[test]
+    fn enable_sets_source_bit_in_ie1() {
+        let r = MockRegs::new();
+        r.set(0x0C00_2000, 0x0000_0100);
+        plic_enable_src_to_interrupt(&r, PlicIntrSources::uart0);
+        assert_eq!(r.get(0x0C00_2000), 0x0000_0108);
+        assert_eq!(r.get(0x0C00_2004), 0);
+    }
+
+    #[test]
+    fn sources_above_31_use_ie2() {
+        let r = MockRegs::new();
+        plic_enable_src_to_interrupt(&r, PlicIntrSources::pwm0a);
+        plic_enable_src_to_interrupt(&r, PlicIntrSources::i2c);
+        assert_eq!(r.get(0x0C00_2000), 0);
+        assert_eq!(r.get(0x0C00_2004), (1 << 8) | (1 << 20));
+
+        plic_disable_src_to_interrupt(&r, PlicIntrSources::pwm0a);
+        assert_eq!(r.get(0x0C00_2004), 1 << 20);
+    }
+
+    #[test]
+    fn priority_register_per_source() {
+        let r = MockRegs::new();
+        plic_set_intr_priority_for_src(&r, PlicIntrSources::uart0, PlicIntrPriorityLevels::level7_lowest);
+        plic_set_intr_priority_for_src(&r, PlicIntrSources::aon_wdog, PlicIntrPriorityLevels::level1_highest);
+        assert_eq!(r.writes(0x0C00_000C), [7]);
+        assert_eq!(r.writes(0x0C00_0004), [1]);
+    }
+
+    #[test]
+    fn source_from_id() {
+        assert_eq!(PlicIntrSources::from_id(3), Some(PlicIntrSources::uart0));
+        assert_eq!(PlicIntrSources::from_id(52), Some(PlicIntrSources::i2c));
+        assert_eq!(PlicIntrSources::from_id(0), None);
+        assert_eq!(PlicIntrSources::from_id(53), None);
+        assert_eq!(PlicIntrSources::gpio(17), Ok(PlicIntrSources::gpio17));
+        assert_eq!(PlicIntrSources::gpio(32), Err(HalError::InvalidPin));
+    }
+
+    #[test]
+    fn claim_and_complete_use_same_register() {
+        let r = MockRegs::new();
+        r.push_read(0x0C20_0004, 25);
+        let id = plic_claim(&r);
+        plic_complete(&r, id);
+        assert_eq!(r.writes(0x0C20_0004), [25]);
+    }
+
+    #[test]
+    fn threshold_written() {
+        let r = MockRegs::new();
+        plic_set_priority_threshold(&r, PlicIntrPriorityLevels::level2);
+        assert_eq!(r.writes(0x0C20_0000), [2]);
     }
 }
@@ -0,0 +1,248 @@
+//!  FE310-G002 Register Definitions
+//!
+//! Generated by `build.rs` from `fe310-g002.svd`: a module per
+//! peripheral with its `BASE` address, and in it a module per register
+//! with the `OFFSET` from the base, the `RESET` value when the manual
+//! defines one and a `Field` per bit field. Register arrays like the
+//! PLIC priorities have `COUNT` registers `STRIDE` bytes apart.
+//!
+//! Peripherals of the same kind (`uart1`, `spi2`, `pwm2`) re-export the
+//! registers of the first one with their own `BASE`.
+//!
+//! Each register module also has a `Spec` type, the register is then
+//! accessed through `Reg` with a method per field instead of masks:
+//!
+//! ```ignore
+//! let txctrl = Reg::<uart0::txctrl::Spec, _>::new(&MMIO, uart1::BASE);
+//! txctrl.modify(|_, w| w.txcnt().bits(1).txen().set_bit());
+//! let full = Reg::<uart0::txdata::Spec, _>::new(&MMIO, uart1::BASE).read().full();
+//! ```
+//!
+//! Field values are checked against the width of the field, read-only
+//! registers have no `write` and read-only fields no writer.
+
+use core::marker::PhantomData;
+
+use crate::mmio::{RegAccess, RegAddr};
+
+/// Bit field `width` bits wide starting at bit `offset` of a register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Field {
+    pub offset: u8,
+    pub width: u8,
+}
+
+impl Field {
+    pub const fn new(offset: u8, width: u8) -> Field {
+        Field { offset, width }
+    }
+
+    /// Largest value of the field
+    pub const fn max(self) -> u32 {
+        u32::MAX >> (32 - self.width as u32)
+    }
+
+    /// Bits of the field in the register
+    pub const fn mask(self) -> u32 {
+        self.max() << self.offset
+    }
+
+    /// Value of the field in register value `reg`
+    pub const fn get(self, reg: u32) -> u32 {
+        (reg & self.mask()) >> self.offset
+    }
+
+    /// `reg` with the field set to `v`, bits of `v` beyond the width
+    /// of the field are dropped.
+    pub const fn set(self, reg: u32, v: u32) -> u32 {
+        (reg & !self.mask()) | ((v << self.offset) & self.mask())
+    }
+}
+
+/// Layout of a register, implemented by the generated `Spec` types
+pub trait RegSpec {
+    const OFFSET: u32;
+    /// Value `Reg::write` starts from
+    const RESET: u32 = 0;
+    const COUNT: usize = 1;
+    const STRIDE: u32 = 4;
+}
+
+pub trait Readable: RegSpec {}
+pub trait Writable: RegSpec {}
+
+/// Register `S` of the peripheral at `base`, accessed through `r`.
+pub struct Reg<'a, S, A> {
+    r: &'a A,
+    addr: RegAddr,
+    _spec: PhantomData<S>,
+}
+
+impl<'a, S: RegSpec, A: RegAccess> Reg<'a, S, A> {
+    pub fn new(r: &'a A, base: u32) -> Reg<'a, S, A> {
+        Reg::at(r, base, 0)
+    }
+
+    /// Register `i` of a register array.
+    pub fn at(r: &'a A, base: u32, i: usize) -> Reg<'a, S, A> {
+        assert!(i < S::COUNT, "Register index out of range");
+        Reg { r, addr: base + S::OFFSET + i as u32 * S::STRIDE, _spec: PhantomData }
+    }
+
+    pub fn addr(&self) -> RegAddr {
+        self.addr
+    }
+}
+
+impl<S: Readable, A: RegAccess> Reg<'_, S, A> {
+    pub fn read(&self) -> R<S> {
+        R { bits: self.r.read32(self.addr), _spec: PhantomData }
+    }
+}
+
+impl<S: Writable, A: RegAccess> Reg<'_, S, A> {
+    /// Writes the reset value with the fields set by `f`.
+    pub fn write<F: FnOnce(&mut W<S>) -> &mut W<S>>(&self, f: F) {
+        let mut w = W { bits: S::RESET, _spec: PhantomData };
+        f(&mut w);
+        self.r.write32(self.addr, w.bits);
+    }
+}
+
+impl<S: Readable + Writable, A: RegAccess> Reg<'_, S, A> {
+    /// Writes back the current value with the fields set by `f`.
+    pub fn modify<F: for<'w> FnOnce(&R<S>, &'w mut W<S>) -> &'w mut W<S>>(&self, f: F) {
+        let r = self.read();
+        let mut w = W { bits: r.bits, _spec: PhantomData };
+        f(&r, &mut w);
+        self.r.write32(self.addr, w.bits);
+    }
+}
+
+/// Value read from register `S`
+pub struct R<S> {
+    bits: u32,
+    _spec: PhantomData<S>,
+}
+
+impl<S> R<S> {
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn field(&self, f: Field) -> u32 {
+        f.get(self.bits)
+    }
+}
+
+/// Value to write to register `S`
+pub struct W<S> {
+    bits: u32,
+    _spec: PhantomData<S>,
+}
+
+impl<S> W<S> {
+    /// Sets the whole register, for registers without fields.
+    pub fn bits(&mut self, v: u32) -> &mut W<S> {
+        self.bits = v;
+        self
+    }
+
+    pub fn field(&mut self, f: Field) -> FieldWriter<'_, S> {
+        FieldWriter { w: self, f }
+    }
+}
+
+/// Writer of one field of a `W`
+pub struct FieldWriter<'a, S> {
+    w: &'a mut W<S>,
+    f: Field,
+}
+
+impl<'a, S> FieldWriter<'a, S> {
+    /// Sets the field to `v`, which must fit in the field.
+    pub fn bits(self, v: u32) -> &'a mut W<S> {
+        assert!(v <= self.f.max(), "Value does not fit the register field");
+        self.w.bits = self.f.set(self.w.bits, v);
+        self.w
+    }
+
+    pub fn bit(self, b: bool) -> &'a mut W<S> {
+        self.bits(b as u32)
+    }
+
+    pub fn set_bit(self) -> &'a mut W<S> {
+        self.bits(1)
+    }
+
+    pub fn clear_bit(self) -> &'a mut W<S> {
+        self.bits(0)
+    }
+}
+
+include!(concat!(env!("OUT_DIR"), "/fe310_regs.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::mock::MockRegs;
+
+    #[test]
+    fn field_mask_get_and_set() {
+        let txcnt = uart0::txctrl::TXCNT;
+        assert_eq!(txcnt.mask(), 0x0007_0000);
+        assert_eq!(txcnt.set(0x0000_0003, 5), 0x0005_0003);
+        assert_eq!(txcnt.get(0x0005_0003), 5);
+        assert_eq!(txcnt.set(0xFFFF_FFFF, 0), 0xFFF8_FFFF);
+        assert_eq!(Field::new(0, 32).mask(), u32::MAX);
+    }
+
+    #[test]
+    fn generated_addresses_match_fe310_map() {
+        assert_eq!(clint::BASE + clint::mtime_lo::OFFSET, 0x0200_BFF8);
+        assert_eq!(plic::BASE + plic::threshold::OFFSET, 0x0C20_0000);
+        assert_eq!(plic::priority::COUNT, 52);
+        assert_eq!(gpio0::BASE + gpio0::passthru_low_ie::OFFSET, 0x1001_2048);
+        assert_eq!(uart1::BASE + uart1::div::OFFSET, 0x1002_3018);
+        assert_eq!(spi2::BASE + spi2::ip::OFFSET, 0x1003_4074);
+        assert_eq!(prci::pllcfg::RESET, 0x0006_0DF1);
+        assert_eq!(i2c0::sr::TIP, Field::new(1, 1));
+    }
+
+    #[test]
+    fn modify_sets_named_fields_only() {
+        let r = MockRegs::new();
+        r.set(0x1002_3008, 0x0000_0002);
+
+        let txctrl = Reg::<uart1::txctrl::Spec, _>::new(&r, uart1::BASE);
+        txctrl.modify(|_, w| w.txcnt().bits(7).txen().set_bit());
+        assert_eq!(r.get(0x1002_3008), 0x0007_0003);
+
+        let v = txctrl.read();
+        assert!(v.txen() && v.nstop());
+        assert_eq!(v.txcnt(), 7);
+    }
+
+    #[test]
+    fn write_starts_from_reset_value() {
+        let r = MockRegs::new();
+        r.set(prci::BASE + prci::pllcfg::OFFSET, 0xFFFF_FFFF);
+
+        Reg::<prci::pllcfg::Spec, _>::new(&r, prci::BASE).write(|w| w.pllsel().set_bit());
+        assert_eq!(r.get(0x1000_8008), 0x0007_0DF1);
+    }
+
+    #[test]
+    fn register_array_index() {
+        let r = MockRegs::new();
+        Reg::<plic::priority::Spec, _>::at(&r, plic::BASE, 2).write(|w| w.priority().bits(5));
+        assert_eq!(r.writes(0x0C00_000C), [5]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn value_wider_than_field_panics() {
+        let r = MockRegs::new();
+        Reg::<uart0::rxctrl::Spec, _>::new(&r, uart0::BASE).write(|w| w.rxcnt().bits(8));
+    }
+}
@@ -0,0 +1,156 @@
+//!  FE310-G002 SPI Master Interface
+//!
+//! QSPI0, SPI1 and SPI2 share the register layout of `qspi0`, QSPI0 has
+//! the flash interface registers on top.
+
+use crate::error::HalError;
+use crate::mmio::RegAccess;
+use crate::regs::{qspi0, spi1, spi2, Reg};
+
+/// `csmode`: CS is asserted during each frame
+pub (crate) const SPI_CSMODE_AUTO: u32 = 0;
+/// `csmode`: CS stays asserted between frames
+pub (crate) const SPI_CSMODE_HOLD: u32 = 2;
+
+/// Polls of the FIFO flags before a frame transfer gives up
+const SPI_TIMEOUT_POLLS: u32 = 1 << 16;
+
+/// Register `$reg` of SPI `$instance`
+macro_rules! spi_reg {
+    ($r:expr, $instance:expr, $reg:ident) => { Reg::<qspi0::$reg::Spec, _>::new($r, spi_base($instance)?) }
+}
+
+fn spi_base ( instance: u8) -> Result<u32, HalError> {
+    match instance {
+        0 => Ok(qspi0::BASE),
+        1 => Ok(spi1::BASE),
+        2 => Ok(spi2::BASE),
+        3_u8..=u8::MAX => Err(HalError::InvalidInstance)
+    }
+}
+
+/// Divisor for an SCK of at most `sck_hz` from input clock `clk_hz`:
+/// ``` sck = clk / (2 * (div + 1)) ```
+pub (crate) fn spi_compute_divisor ( clk_hz: u32, sck_hz: u32) -> Result<u32, HalError> {
+    let div = sck_hz.checked_mul(2)
+        .and_then(|d| clk_hz.checked_add(d.checked_sub(1)?)?.checked_div(d))
+        .and_then(|q| q.checked_sub(1))
+        .ok_or(HalError::BaudOutOfRange)?;
+
+    if div > qspi0::sckdiv::DIV.max() {
+        return Err(HalError::BaudOutOfRange);
+    }
+    Ok(div)
+}
+
+/// Sets the SCK divisor, clock polarity and phase, and 8 bit single
+/// lane frames, MSB first.
+pub (crate) fn spi_configure ( r: &impl RegAccess, instance: u8, div: u32, pol: bool, pha: bool) -> Result<(), HalError> {
+    spi_reg!(r, instance, sckdiv).write(|w| w.div().bits(div));
+    spi_reg!(r, instance, sckmode).write(|w| w.pol().bit(pol).pha().bit(pha));
+    spi_reg!(r, instance, fmt).write(|w| w.proto().bits(0).endian().clear_bit().dir().clear_bit().len().bits(8));
+    Ok(())
+}
+
+pub (crate) fn spi_set_csid ( r: &impl RegAccess, instance: u8, cs: u8) -> Result<(), HalError> {
+    spi_reg!(r, instance, csid).write(|w| w.bits(cs as u32));
+    Ok(())
+}
+
+pub (crate) fn spi_set_csmode ( r: &impl RegAccess, instance: u8, mode: u32) -> Result<(), HalError> {
+    spi_reg!(r, instance, csmode).write(|w| w.mode().bits(mode));
+    Ok(())
+}
+
+/// Sends `b` and returns the byte received at the same time.
+fn spi_transfer_byte ( r: &impl RegAccess, instance: u8, b: u8) -> Result<u8, HalError> {
+    let txdata = spi_reg!(r, instance, txdata);
+    let rxdata = spi_reg!(r, instance, rxdata);
+
+    let mut polls = SPI_TIMEOUT_POLLS;
+    while txdata.read().full() {
+        polls = polls.checked_sub(1).ok_or(HalError::Timeout)?;
+    }
+    txdata.write(|w| w.data().bits(b as u32));
+
+    for _ in 0..SPI_TIMEOUT_POLLS {
+        let v = rxdata.read();
+        if !v.empty() {
+            return Ok(v.data() as u8);
+        }
+    }
+    Err(HalError::Timeout)
+}
+
+/// Exchanges `buf` with the device, keeping CS asserted for the whole
+/// transfer. CS is released once the last frame was received.
+pub (crate) fn spi_transfer ( r: &impl RegAccess, instance: u8, buf: &mut [u8]) -> Result<(), HalError> {
+    spi_set_csmode(r, instance, SPI_CSMODE_HOLD)?;
+    let res = buf.iter_mut().try_for_each(|b| {
+        *b = spi_transfer_byte(r, instance, *b)?;
+        Ok(())
+    });
+    spi_set_csmode(r, instance, SPI_CSMODE_AUTO)?;
+    res
+}
+
+/// `spi_transfer` discarding the received bytes.
+pub (crate) fn spi_write ( r: &impl RegAccess, instance: u8, bytes: &[u8]) -> Result<(), HalError> {
+    spi_set_csmode(r, instance, SPI_CSMODE_HOLD)?;
+    let res = bytes.iter().try_for_each(|b| spi_transfer_byte(r, instance, *b).map(|_| ()));
+    spi_set_csmode(r, instance, SPI_CSMODE_AUTO)?;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::mock::MockRegs;
+
+    const SPI1_CSMODE: u32 = 0x1002_4018;
+    const SPI1_FMT: u32 = 0x1002_4040;
+    const SPI1_TXDATA: u32 = 0x1002_4048;
+    const SPI1_RXDATA: u32 = 0x1002_404C;
+
+    #[test]
+    fn divisor_rounds_sck_down() {
+        assert_eq!(spi_compute_divisor(16_000_000, 1_000_000), Ok(7));
+        assert_eq!(spi_compute_divisor(16_000_000, 3_000_000), Ok(2));
+        assert_eq!(spi_compute_divisor(16_000_000, 0), Err(HalError::BaudOutOfRange));
+        assert_eq!(spi_compute_divisor(16_000_000, 1_000), Err(HalError::BaudOutOfRange));
+    }
+
+    #[test]
+    fn configure_sets_mode_and_8_bit_frames() {
+        let r = MockRegs::new();
+        spi_configure(&r, 1, 7, true, true).unwrap();
+        assert_eq!(r.writes(0x1002_4000), [7]);
+        assert_eq!(r.writes(0x1002_4004), [0x3]);
+        assert_eq!(r.writes(SPI1_FMT), [0x0008_0000]);
+    }
+
+    #[test]
+    fn transfer_holds_cs_and_reads_each_frame() {
+        let r = MockRegs::new();
+        r.push_read(SPI1_RXDATA, 0x8000_0000);
+        r.push_read(SPI1_RXDATA, 0x11);
+        r.push_read(SPI1_RXDATA, 0x22);
+
+        let mut buf = [0xA0, 0xA1];
+        spi_transfer(&r, 1, &mut buf).unwrap();
+
+        assert_eq!(buf, [0x11, 0x22]);
+        assert_eq!(r.writes(SPI1_TXDATA), [0xA0, 0xA1]);
+        assert_eq!(r.writes(SPI1_CSMODE), [SPI_CSMODE_HOLD, SPI_CSMODE_AUTO]);
+    }
+
+    #[test]
+    fn missing_frame_times_out_and_releases_cs() {
+        let r = MockRegs::new();
+        r.set(SPI1_RXDATA, 0x8000_0000);
+
+        assert_eq!(spi_write(&r, 1, &[1]), Err(HalError::Timeout));
+        assert_eq!(r.writes(SPI1_CSMODE), [SPI_CSMODE_HOLD, SPI_CSMODE_AUTO]);
+        assert_eq!(spi_write(&r, 3, &[1]), Err(HalError::InvalidInstance));
+    }
+}
@@ -1,225 +1,265 @@
-
 //!  Hifive1-RevB board Uart Interface
-use core::ptr; // for read/write volatile 
-
-#[repr(C)]
-struct UartMmapRegs{
-
-    /// Writing to txdata register, enqueues the character 
-    /// to transmit FIFO if FIFO is able to accept new entries.
-    /// Reading from txdata returns the currrent value of the full flag
-    /// and zero in the data field.
-    /// 
-    /// ```
-    /// --------------------------
-    /// | FULL | RESERVED | DATA |
-    /// --------------------------
-    /// | 31   | 30 : 8 | [7:0]|
-    /// --------------------------
-    /// ```
-    /// 
-    /// `Full` flag indicates whether the FIFO is able to accept new entries.
-    /// When `full` flag is set, writes are ignored. 
-    /// RISC-V `amoor.w` instruction can be used to both read the status 
-    /// and attempt to enqueue data with a non-zero return value 
-    /// indicating the character was NOT accepted. 
-   txdata: u32, /*  Transmit data */
-
-   /// Reading the rxdata register dequeues the data from the receive FIFO
-    /// and returns the value in the data field. The 'empty` flag indicates if
-    /// fifo is empty and when set 'data` field dont have valid data. 
-    /// Writes to `rxdata` register are ignored. 
-    /// 
-    /// ```
-    /// --------------------------
-    /// | EMPTY | RESERVED | DATA |
-    /// --------------------------
-    /// | 31   | [30: 8]  | [7:0]|
-    /// --------------------------
-    /// ```
-    //
-   rxdata: u32, /* Receive data  */
-
-    /// `txctrl` register controls the operation of the transmit channel. 
-    /// The `txen` bit controls weather Tx Channel is active. When
-    /// cleared, transmission of Tx FIFO contents are suppressed, and the 
-    /// `txd` pin is driven high. 
-    /// 
-    /// ```
-    /// ------------------------------------------------------
-    /// | RESERVED | WATERMARK | RESERVED | STOP BITS | ENABLE|
-    /// ------------------------------------------------------
-    /// | [31:19]  | [18:16]   |  [15:2]  |     1     |   0   |
-    /// ------------------------------------------------------
-    /// ```
-    /// The stop bits specifies the number of stop bits, `0` for 1 stop bit
-    /// and `1` for two stop bits. 
-    /// Watermark/txcnt field specifies the watermark interrupt triggers.
-    /// At reset, `txctrl` register is reset to 0. 
-    ///
-   txctrl: u32, /* Tx control */
-
-    /// The RW `rxctrl` register controls the operation of the receive channel. 
-    /// ```
-    /// ------------------------------------------------------
-    /// | RESERVED | WATERMARK | RESERVED | ENABLE|
-    /// ------------------------------------------------------
-    /// | [31:19]  | [18:16]   |  [15:1]  |   0   |
-    /// ------------------------------------------------------
-    /// ```
-    /// The `rxen` bit controls wether Rx channel is active. 
-    /// When cleared, the state of `rxd` pin is ignored and 
-    /// no characters will be enqueued into the Rx FIFO. 
-    /// 
-    /// The watermark field specifies the threshold at which 
-    /// the Rx FIFO watermark interrupt triggers. 
-    /// 
-    /// The `rxctrl` register is reset to 0. 
-    /// Characters are enqueued when a zero (low) start bit 
-    /// is seen. 
-    ///
-   rxctrl: u32, /* Rx Control */
-
-   /// `ie` register controls which UART interrupts are enabled. 
-    /// ```
-    /// ------------------------------------------------------------------
-    /// | RESERVED | Rx WATERMARK INTR ENABLE | Tx WATERMARK INTR ENABLE | 
-    /// ------------------------------------------------------------------
-    /// | [31:2]   |         1                |            0             |
-    /// -----------------------------------------------------------------
-    /// ```
-   ie: u32, /* Interrupt enable */
-
-    /// The `ip` is a read-only register indicating the pending
-    /// interrupt conditions.
-    /// ```
-    /// ------------------------------------------------------------------
-    /// | RESERVED | Rx WATERMARK INTR PEND | Tx WATERMARK INTR PEND | 
-    /// ------------------------------------------------------------------
-    /// | [31:2]   |         1                |            0             |
-    /// -----------------------------------------------------------------
-    /// ```
-   ip: u32, /* Interrupt pending */
-
-   /// The RW `div` register specifies the divisor used by baud rate
-    /// generation for both Tx and Rx Channels. The relationship between
-    /// input clock and baud rate is given by following formula
-    /// 
-    /// ``` baud = input clk / (div + 1) ```
-    /// 
-    /// Input clock is the bus clock `tlclk`. 
-    /// 
-    /// **Example** <br>
-    /// To get baud rate 115200, with default `tlclk`  16Mhz, 
-    /// the `div` register value should be 138. 
-    /// 
-   div: u32, /* Divisor */
-}
-
-const UART0: *mut UartMmapRegs = 0x10013000 as *mut UartMmapRegs; // private to this file 
-const UART1: *mut UartMmapRegs = 0x10023000 as *mut UartMmapRegs; // private to this file
-
-pub (crate) fn uart_set_baud_divisor ( instance: u8, div: u32) {
-    if instance == 0 {
-        unsafe { (*UART0).div = div; }
-    } else if instance == 1 {
-        unsafe { (*UART1).div = div; }
-    }
-}
-
-pub (crate) fn uart_set_stopbits ( instance: u8, sbc: u8) {
-    // ------------------------TXCTRL-------------------------
-    // | RESERVED | WATERMARK | RESERVED | STOP BITS | ENABLE|
-    // ------------------------------------------------------
-    // | [31:19]  | [18:16]   |  [15:2]  |     1     |   0   |
-    // ------------------------------------------------------
-    match  instance {
-        0 =>{ if sbc == 1 {
-                unsafe { (*UART0).txctrl = (*UART0).txctrl & (0xFFFF_FFFD);}
-            } else if sbc == 2 {
-                unsafe { (*UART0).txctrl = (*UART0).txctrl | (0x2u32);}
-            } else {
-                panic!("Invalid stop bits count")
-            }
-        }
+use crate::error::HalError;
+use crate::mmio::RegAccess;
+use crate::regs::{uart0, uart1, Reg};
 
-        1 =>{ if sbc == 1 {
-                unsafe {(*UART1).txctrl = (*UART1).txctrl & (0xFFFF_FFFD);}
-            } else if sbc == 2 {
-                    unsafe {(*UART1).txctrl = (*UART1).txctrl | (0x2u32);}
-            } else {
-                panic!("Invalid stop bits count")
-            }
-        }
-        2_u8..=u8::MAX => panic!("Invalid Uart Instance")
+/// `ie` / `ip` Tx watermark bit, Tx is not interrupt driven
+#[cfg(test)]
+pub (crate) const UART_INTR_TXWM: u32 = 0x1;
+/// `ie` / `ip` Rx watermark bit
+pub (crate) const UART_INTR_RXWM: u32 = 0x2;
+
+/// Polls of a full Tx FIFO before `uart_do_send_byte` gives up, more
+/// than the time to send the 8 characters of the FIFO at 9600 baud
+const UART_TX_TIMEOUT_POLLS: u32 = 1 << 20;
+
+/// Register `$reg` of uart `$instance`, both uarts have the layout of `uart0`
+macro_rules! uart_reg {
+    ($r:expr, $instance:expr, $reg:ident) => { Reg::<uart0::$reg::Spec, _>::new($r, uart_base($instance)?) }
+}
+
+fn uart_base ( instance: u8) -> Result<u32, HalError> {
+    match instance {
+        0 => Ok(uart0::BASE),
+        1 => Ok(uart1::BASE),
+        2_u8..=u8::MAX => Err(HalError::InvalidInstance)
     }
 }
 
-pub (crate) fn uart_set_tx_fifo_depth ( instance: u8, depth: u8) {
+/// Divisor for `baud` from input clock `clk_hz`, rounded to the
+/// nearest integer: ``` div = clk / baud - 1 ```
+pub (crate) fn uart_compute_divisor ( clk_hz: u32, baud: u32) -> Result<u32, HalError> {
+    let div = clk_hz.checked_add(baud / 2)
+        .and_then(|clk| clk.checked_div(baud))
+        .and_then(|q| q.checked_sub(1))
+        .ok_or(HalError::BaudOutOfRange)?;
 
-    // ------------------------TXCTRL-------------------------
-    // | RESERVED | WMARK/TXCNT| RESERVED | STOP BITS | ENABLE|
-    // ------------------------------------------------------
-    // | [31:19]  | [18:16]   |  [15:2]  |     1     |   0   |
-    // -------------------------------------------------------
-    match  instance {
-        0 => unsafe { (*UART0).txctrl = ((*UART0).txctrl & 0xFFF8_FFFF) | ((depth as u32) << 15);}
-        1 => unsafe { (*UART1).txctrl = ((*UART1).txctrl & 0xFFF8_FFFF) | ((depth as u32) << 15);}
-        2_u8..=u8::MAX => panic!("Invalid Uart Instance")
+    if div > uart0::div::DIV.max() {
+        return Err(HalError::BaudOutOfRange);
     }
+    Ok(div)
 }
 
-pub (crate) fn uart_do_send_byte ( instance: u8, b: u8) {
+pub (crate) fn uart_set_baud_divisor ( r: &impl RegAccess, instance: u8, div: u32) -> Result<(), HalError> {
+    if div > uart0::div::DIV.max() {
+        return Err(HalError::BaudOutOfRange);
+    }
+    uart_reg!(r, instance, div).write(|w| w.div().bits(div));
+    Ok(())
+}
 
-    // --------------------------
-    // | FULL | RESERVED | DATA |
-    // --------------------------
-    // | 31   | [30: 8]  | [7:0]|
-    // --------------------------
+pub (crate) fn uart_set_stopbits ( r: &impl RegAccess, instance: u8, sbc: u8) -> Result<(), HalError> {
+    let txctrl = uart_reg!(r, instance, txctrl);
 
-    match  instance {
-        0 => {
-            unsafe { 
-                while ((ptr::read_volatile(&(*UART0).txdata as *const u32) >> 31) == 1) { }
-                (*UART0).txdata = (ptr::read_volatile(&(*UART0).txdata as *const u32) & 0xFFFF_FFF0)| (b as u32);
-            }
-        }
-        1 => {
-            unsafe { 
-                while ((ptr::read_volatile(&(*UART1).txdata as *const u32) >> 31) == 1) {}
-                (*UART1).txdata =  (ptr::read_volatile(&(*UART1).txdata as *const u32) & 0xFFFF_FFF0) | (b as u32);
-            }
+    match sbc {
+        1 => txctrl.modify(|_, w| w.nstop().clear_bit()),
+        2 => txctrl.modify(|_, w| w.nstop().set_bit()),
+        _ => return Err(HalError::Unsupported),
+    }
+    Ok(())
+}
+
+/// The Tx watermark interrupt is pending while the Tx FIFO holds
+/// fewer than `depth` characters, 0 - 7.
+pub (crate) fn uart_set_tx_fifo_depth ( r: &impl RegAccess, instance: u8, depth: u8) -> Result<(), HalError> {
+    if depth as u32 > uart0::txctrl::TXCNT.max() {
+        return Err(HalError::Unsupported);
+    }
+    uart_reg!(r, instance, txctrl).modify(|_, w| w.txcnt().bits(depth as u32));
+    Ok(())
+}
+
+/// Queues `b` for sending, waiting while the Tx FIFO is full.
+pub (crate) fn uart_do_send_byte ( r: &impl RegAccess, instance: u8, b: u8) -> Result<(), HalError> {
+    uart_send_byte_polls(r, instance, b, UART_TX_TIMEOUT_POLLS)
+}
+
+fn uart_send_byte_polls ( r: &impl RegAccess, instance: u8, b: u8, polls: u32) -> Result<(), HalError> {
+    let txdata = uart_reg!(r, instance, txdata);
+
+    let mut left = polls;
+    while txdata.read().full() {
+        if left == 0 {
+            return Err(HalError::Timeout);
         }
-        2_u8..=u8::MAX => panic!("Invalid Uart Instance")
+        left -= 1;
     }
+    txdata.write(|w| w.data().bits(b as u32));
+    Ok(())
+}
+
+pub (crate) fn uart_enable_tx ( r: &impl RegAccess, instance: u8) -> Result<(), HalError> {
+    uart_reg!(r, instance, txctrl).modify(|_, w| w.txen().set_bit());
+    Ok(())
+}
+
+pub (crate) fn uart_disable_tx ( r: &impl RegAccess, instance: u8) -> Result<(), HalError> {
+    uart_reg!(r, instance, txctrl).modify(|_, w| w.txen().clear_bit());
+    Ok(())
+}
+
+pub (crate) fn uart_enable_rx ( r: &impl RegAccess, instance: u8) -> Result<(), HalError> {
+    uart_reg!(r, instance, rxctrl).modify(|_, w| w.rxen().set_bit());
+    Ok(())
 }
 
-pub (crate) fn uart_enable_tx ( instance: u8) {
+pub (crate) fn uart_disable_rx ( r: &impl RegAccess, instance: u8) -> Result<(), HalError> {
+    uart_reg!(r, instance, rxctrl).modify(|_, w| w.rxen().clear_bit());
+    Ok(())
+}
+
+/// The Rx watermark interrupt is pending while the Rx FIFO holds
+/// more than `level` characters, 0 - 7.
+pub (crate) fn uart_set_rx_watermark ( r: &impl RegAccess, instance: u8, level: u8) -> Result<(), HalError> {
+    if level as u32 > uart0::rxctrl::RXCNT.max() {
+        return Err(HalError::Unsupported);
+    }
+    uart_reg!(r, instance, rxctrl).modify(|_, w| w.rxcnt().bits(level as u32));
+    Ok(())
+}
 
-    // ------------------------TXCTRL-------------------------
-    // | RESERVED | WMARK/TXCNT| RESERVED | STOP BITS | ENABLE|
-    // ------------------------------------------------------
-    // | [31:19]  | [18:16]   |  [15:2]  |     1     |   0   |
-    // -------------------------------------------------------
+/// Dequeues a character from the Rx FIFO, `None` when it is empty.
+pub (crate) fn uart_try_receive_byte ( r: &impl RegAccess, instance: u8) -> Result<Option<u8>, HalError> {
+    let rxdata = uart_reg!(r, instance, rxdata).read();
 
-    match  instance {
-        0 => unsafe { (*UART0).txctrl = ptr::read_volatile(&(*UART0).txctrl as *const u32) | 1u32;}
-        1 => unsafe { (*UART1).txctrl = ptr::read_volatile(&(*UART1).txctrl as *const u32) | 1u32;}
-        2_u8..=u8::MAX => panic!("Invalid Uart Instance")
+    if rxdata.empty() {
+        Ok(None)
+    } else {
+        Ok(Some(rxdata.data() as u8))
     }
 }
 
-pub (crate) fn uart_disable_tx ( instance: u8) {
+pub (crate) fn uart_enable_intr ( r: &impl RegAccess, instance: u8, mask: u32) -> Result<(), HalError> {
+    uart_reg!(r, instance, ie).modify(|v, w| w.bits(v.bits() | mask));
+    Ok(())
+}
+
+pub (crate) fn uart_disable_intr ( r: &impl RegAccess, instance: u8, mask: u32) -> Result<(), HalError> {
+    uart_reg!(r, instance, ie).modify(|v, w| w.bits(v.bits() & !mask));
+    Ok(())
+}
 
-    // ------------------------TXCTRL-------------------------
-    // | RESERVED | WMARK/TXCNT| RESERVED | STOP BITS | ENABLE|
-    // ------------------------------------------------------
-    // | [31:19]  | [18:16]   |  [15:2]  |     1     |   0   |
-    // -------------------------------------------------------
+/// Pending `UART_INTR_*` conditions.
+pub (crate) fn uart_pending ( r: &impl RegAccess, instance: u8) -> Result<u32, HalError> {
+    Ok(uart_reg!(r, instance, ip).read().bits())
+}
 
-    match  instance {
-        0 => unsafe { (*UART0).txctrl =  ptr::read_volatile(&(*UART0).txctrl as *const u32) & 0xFFFF_FFFE;}
-        1 => unsafe { (*UART1).txctrl =  ptr::read_volatile(&(*UART1).txctrl as *const u32) & 0xFFFF_FFFE;}
-        2_u8..=u8::MAX => panic!("Invalid Uart Instance")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::mock::MockRegs;
+
+    const UART0_DIV: u32 = 0x1001_3018;
+    const UART0_TXCTRL: u32 = 0x1001_3008;
+    const UART1_TXCTRL: u32 = 0x1002_3008;
+    const UART0_TXDATA: u32 = 0x1001_3000;
+
+    #[test]
+    fn divisor_for_common_bauds_at_16mhz() {
+        assert_eq!(uart_compute_divisor(16_000_000, 115_200), Ok(138));
+        assert_eq!(uart_compute_divisor(16_000_000, 9_600), Ok(1666));
+        assert_eq!(uart_compute_divisor(16_000_000, 1_000_000), Ok(15));
+    }
+
+    #[test]
+    fn divisor_out_of_range() {
+        assert_eq!(uart_compute_divisor(16_000_000, 0), Err(HalError::BaudOutOfRange));
+        assert_eq!(uart_compute_divisor(16_000_000, 40_000_000), Err(HalError::BaudOutOfRange));
+        assert_eq!(uart_compute_divisor(320_000_000, 300), Err(HalError::BaudOutOfRange));
+    }
+
+    #[test]
+    fn baud_divisor_written_to_div_register() {
+        let r = MockRegs::new();
+        uart_set_baud_divisor(&r, 0, 138).unwrap();
+        assert_eq!(r.writes(UART0_DIV), [138]);
+    }
+
+    #[test]
+    fn stop_bits_and_enable_per_instance() {
+        let r = MockRegs::new();
+        uart_set_stopbits(&r, 1, 2).unwrap();
+        uart_enable_tx(&r, 1).unwrap();
+        assert_eq!(r.get(UART1_TXCTRL), 0x3);
+        assert_eq!(r.get(UART0_TXCTRL), 0);
+
+        uart_set_stopbits(&r, 1, 1).unwrap();
+        uart_disable_tx(&r, 1).unwrap();
+        assert_eq!(r.get(UART1_TXCTRL), 0);
+
+        assert_eq!(uart_set_stopbits(&r, 1, 3), Err(HalError::Unsupported));
+    }
+
+    #[test]
+    fn send_byte_waits_while_fifo_full() {
+        let r = MockRegs::new();
+        r.push_read(UART0_TXDATA, 0x8000_0000);
+        r.push_read(UART0_TXDATA, 0x8000_0000);
+
+        uart_do_send_byte(&r, 0, b'W').unwrap();
+        assert_eq!(r.writes(UART0_TXDATA), [b'W' as u32]);
+        assert_eq!(r.ops().len(), 4);
+    }
+
+    #[test]
+    fn send_byte_times_out() {
+        let r = MockRegs::new();
+        r.set(UART0_TXDATA, 0x8000_0000);
+
+        assert_eq!(uart_send_byte_polls(&r, 0, b'W', 3), Err(HalError::Timeout));
+        assert!(r.writes(UART0_TXDATA).is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn tx_watermark_in_bits_18_16() {
+        let r = MockRegs::new();
+        r.set(UART0_TXCTRL, 0x0000_0003);
+        uart_set_tx_fifo_depth(&r, 0, 7).unwrap();
+        assert_eq!(r.get(UART0_TXCTRL), 0x0007_0003);
+
+        uart_set_tx_fifo_depth(&r, 0, 1).unwrap();
+        assert_eq!(r.get(UART0_TXCTRL), 0x0001_0003);
+
+        assert_eq!(uart_set_tx_fifo_depth(&r, 0, 8), Err(HalError::Unsupported));
+    }
+
+    #[test]
+    fn receive_byte_checks_empty_flag() {
+        let r = MockRegs::new();
+        r.push_read(0x1001_3004, 0x8000_0000);
+        r.push_read(0x1001_3004, b'x' as u32);
+
+        assert_eq!(uart_try_receive_byte(&r, 0), Ok(None));
+        assert_eq!(uart_try_receive_byte(&r, 0), Ok(Some(b'x')));
+    }
+
+    #[test]
+    fn rx_watermark_and_enable_in_rxctrl() {
+        let r = MockRegs::new();
+        uart_set_rx_watermark(&r, 1, 3).unwrap();
+        uart_enable_rx(&r, 1).unwrap();
+        assert_eq!(r.get(0x1002_300C), 0x0003_0001);
+
+        uart_set_rx_watermark(&r, 1, 0).unwrap();
+        uart_disable_rx(&r, 1).unwrap();
+        assert_eq!(r.get(0x1002_300C), 0);
+    }
+
+    #[test]
+    fn interrupt_enable_bits() {
+        let r = MockRegs::new();
+        uart_enable_intr(&r, 0, UART_INTR_RXWM | UART_INTR_TXWM).unwrap();
+        uart_disable_intr(&r, 0, UART_INTR_TXWM).unwrap();
+        assert_eq!(r.get(0x1001_3010), UART_INTR_RXWM);
+
+        r.set(0x1001_3014, UART_INTR_RXWM);
+        assert_eq!(uart_pending(&r, 0), Ok(UART_INTR_RXWM));
+    }
+
+    #[test]
+    fn invalid_instance_is_an_error() {
+        let r = MockRegs::new();
+        assert_eq!(uart_enable_tx(&r, 2), Err(HalError::InvalidInstance));
+        assert!(r.ops().is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+//!  FE310-G002 AON Watchdog
+//!
+//! Every write to a watchdog register needs `AON_WDOG_UNLOCK_KEY` in
+//! `wdogkey` first.
+
+use crate::mmio::RegAccess;
+use crate::regs::{aon, Reg};
+
+const AON_WDOG_UNLOCK_KEY: u32 = 0x0051_F15E;
+
+/// AON register `$reg`
+macro_rules! aon_reg {
+    ($r:expr, $reg:ident) => { Reg::<aon::$reg::Spec, _>::new($r, aon::BASE) }
+}
+
+fn wdog_unlock(r: &impl RegAccess) {
+    aon_reg!(r, wdogkey).write(|w| w.bits(AON_WDOG_UNLOCK_KEY));
+}
+
+/// Resets the SoC through the watchdog: it restarts from 0 with a
+/// compare value of 1, the reset follows at its next tick.
+pub (crate) fn wdog_reset(r: &impl RegAccess) {
+    wdog_unlock(r);
+    aon_reg!(r, wdogcfg).write(|w| w);
+    wdog_unlock(r);
+    aon_reg!(r, wdogcount).write(|w| w.wdogcount().bits(0));
+    wdog_unlock(r);
+    aon_reg!(r, wdogcmp0).write(|w| w.wdogcmp0().bits(1));
+    wdog_unlock(r);
+    aon_reg!(r, wdogcfg).write(|w| w.wdogrsten().set_bit().wdogenalways().set_bit());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mmio::mock::{MockRegs, RegOp};
+
+    #[test]
+    fn reset_unlocks_each_write() {
+        let r = MockRegs::new();
+        wdog_reset(&r);
+
+        let key = RegOp::Write(0x1000_001C, AON_WDOG_UNLOCK_KEY);
+        assert_eq!(r.ops(), [
+            key, RegOp::Write(0x1000_0000, 0),
+            key, RegOp::Write(0x1000_0008, 0),
+            key, RegOp::Write(0x1000_0020, 1),
+            key, RegOp::Write(0x1000_0000, 0x1100),
+        ]);
+    }
+}
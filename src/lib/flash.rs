@@ -0,0 +1,161 @@
+//! # QSPI Flash
+//!
+//! Erases and programs the boot flash while the firmware runs from it.
+//! Offsets are from the start of flash, 0x2000_0000.
+//!
+//! ```ignore
+//! let flash = Flash::new(p.qspi0);
+//! flash.erase_sector(0x10_0000)?;
+//! flash.program(0x10_0000, &image)?;
+//! ```
+//!
+//! Each command runs with machine interrupts disabled: a sector erase
+//! takes up to 300 ms in which no interrupt is served.
+
+#[cfg(target_arch = "riscv32")]
+#[path = "fe310/flash.rs"] mod flash_cmd;
+
+use crate::error::HalError;
+use crate::peripherals::Qspi0;
+
+/// Memory mapped address of offset 0
+pub const FLASH_BASE: u32 = 0x2000_0000;
+pub const FLASH_SIZE: u32 = 0x40_0000;
+/// Erase unit
+pub const FLASH_SECTOR_SIZE: u32 = 0x1000;
+/// Program unit, a write does not cross a page
+pub const FLASH_PAGE_SIZE: u32 = 0x100;
+
+#[cfg(target_arch = "riscv32")]
+const FLASH_WRITE_ENABLE: u8 = 0x06;
+const FLASH_PAGE_PROGRAM: u8 = 0x02;
+const FLASH_SECTOR_ERASE: u8 = 0x20;
+
+/// NOR flash: erasing sets a sector to 0xFF, programming only clears
+/// bits.
+pub trait NorFlash {
+    fn read(&self, offset: u32, buf: &mut [u8]) -> Result<(), HalError>;
+    /// Erases the sector at `offset`, a multiple of `FLASH_SECTOR_SIZE`.
+    fn erase_sector(&self, offset: u32) -> Result<(), HalError>;
+    fn program(&self, offset: u32, bytes: &[u8]) -> Result<(), HalError>;
+}
+
+pub struct Flash {
+    _qspi: Qspi0,
+}
+
+impl Flash {
+    pub fn new(qspi: Qspi0) -> Flash {
+        Flash { _qspi: qspi }
+    }
+}
+
+/// Command and 24 bit address of `cmd` at `offset`
+fn flash_addr_cmd(cmd: u8, offset: u32) -> [u8; 4] {
+    [cmd, (offset >> 16) as u8, (offset >> 8) as u8, offset as u8]
+}
+
+/// Splits a write at `offset` at the page boundaries.
+fn flash_pages(offset: u32, bytes: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    let mut offset = offset;
+    let mut rest = bytes;
+    core::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let room = (FLASH_PAGE_SIZE - offset % FLASH_PAGE_SIZE) as usize;
+        let (page, tail) = rest.split_at(room.min(rest.len()));
+        let item = (offset, page);
+        offset += page.len() as u32;
+        rest = tail;
+        Some(item)
+    })
+}
+
+fn flash_check_range(offset: u32, len: usize) -> Result<(), HalError> {
+    match offset.checked_add(len as u32) {
+        Some(end) if end <= FLASH_SIZE => Ok(()),
+        _ => Err(HalError::InvalidAddress),
+    }
+}
+
+/// Write enable, then `cmd` and `data` (in RAM, flash can not be
+/// read during the command).
+#[cfg(target_arch = "riscv32")]
+fn flash_command(cmd: &[u8; 4], data: &[u8]) -> Result<(), HalError> {
+    let wren = [FLASH_WRITE_ENABLE];
+    crate::interrupt::free(|_| unsafe {
+        flash_cmd::_flash_cmd(wren.as_ptr(), wren.len(), core::ptr::null(), 0);
+        flash_cmd::_flash_cmd(cmd.as_ptr(), cmd.len(), data.as_ptr(), data.len());
+    });
+    Ok(())
+}
+
+#[cfg(target_arch = "riscv32")]
+fn flash_read(offset: u32, buf: &mut [u8]) -> Result<(), HalError> {
+    let src = (FLASH_BASE + offset) as usize as *const u8;
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = unsafe { core::ptr::read_volatile(src.add(i)) };
+    }
+    Ok(())
+}
+
+/// No flash on the host
+#[cfg(not(target_arch = "riscv32"))]
+fn flash_command(_cmd: &[u8; 4], _data: &[u8]) -> Result<(), HalError> {
+    Err(HalError::Unsupported)
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+fn flash_read(_offset: u32, _buf: &mut [u8]) -> Result<(), HalError> {
+    Err(HalError::Unsupported)
+}
+
+impl NorFlash for Flash {
+    fn read(&self, offset: u32, buf: &mut [u8]) -> Result<(), HalError> {
+        flash_check_range(offset, buf.len())?;
+        flash_read(offset, buf)
+    }
+
+    fn erase_sector(&self, offset: u32) -> Result<(), HalError> {
+        flash_check_range(offset, FLASH_SECTOR_SIZE as usize)?;
+        if !offset.is_multiple_of(FLASH_SECTOR_SIZE) {
+            return Err(HalError::InvalidAddress);
+        }
+        flash_command(&flash_addr_cmd(FLASH_SECTOR_ERASE, offset), &[])
+    }
+
+    fn program(&self, offset: u32, bytes: &[u8]) -> Result<(), HalError> {
+        flash_check_range(offset, bytes.len())?;
+        // `bytes` may be in flash, copy each page to the stack
+        let mut page = [0u8; FLASH_PAGE_SIZE as usize];
+        for (at, chunk) in flash_pages(offset, bytes) {
+            page[..chunk.len()].copy_from_slice(chunk);
+            flash_command(&flash_addr_cmd(FLASH_PAGE_PROGRAM, at), &page[..chunk.len()])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn writes_are_split_at_page_boundaries() {
+        let bytes = [0u8; 600];
+        let pages: Vec<(u32, usize)> = flash_pages(0x10F0, &bytes).map(|(a, p)| (a, p.len())).collect();
+        assert_eq!(pages, [(0x10F0, 0x10), (0x1100, 0x100), (0x1200, 0x100), (0x1300, 0x48)]);
+        assert_eq!(flash_pages(0, &[]).count(), 0);
+    }
+
+    #[test]
+    fn commands_carry_24_bit_address() {
+        assert_eq!(flash_addr_cmd(FLASH_SECTOR_ERASE, 0x12_3456), [0x20, 0x12, 0x34, 0x56]);
+        assert_eq!(flash_check_range(FLASH_SIZE - 4, 4), Ok(()));
+        assert_eq!(flash_check_range(FLASH_SIZE - 4, 5), Err(HalError::InvalidAddress));
+    }
+}
@@ -0,0 +1,397 @@
+//! # Heap Allocator
+//!
+//! With the `alloc` feature the firmware gets a `#[global_allocator]`
+//! over the `.heap` region of the linker script (`_heap_start` ..
+//! `_heap_end` in DTIM, sized in `build.rs`), so `alloc::vec::Vec`,
+//! `String` and `Box` can be used.
+//!
+//! `Heap` is a first-fit allocator over an address ordered free list.
+//! Free blocks are merged with their neighbours when released. Every
+//! block is a multiple of `HEAP_UNIT` bytes, so splitting a free block
+//! never leaves a fragment too small to hold the list node.
+//!
+//! Allocation and release run with machine interrupts disabled, and
+//! take time linear in the number of free blocks. Keep the allocator
+//! out of interrupt handlers and timing critical paths.
+
+use core::alloc::Layout;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+/// Called with the failed layout before the allocation returns null.
+pub type OomHook = fn(Layout);
+
+/// Free block node, stored at the start of the free block itself.
+struct FreeBlock {
+    size: usize,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// Allocation granularity and minimum block size.
+pub const HEAP_UNIT: usize = mem::size_of::<FreeBlock>();
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeapStats {
+    /// Usable heap size in bytes
+    pub size: usize,
+    /// Bytes in allocated blocks, including rounding
+    pub used: usize,
+    /// Highest `used` seen
+    pub peak: usize,
+    pub allocs: u32,
+    pub frees: u32,
+    /// Allocations that returned null
+    pub failed: u32,
+}
+
+pub struct Heap {
+    free: Option<NonNull<FreeBlock>>,
+    stats: HeapStats,
+    oom_hook: Option<OomHook>,
+}
+
+unsafe impl Send for Heap {}
+
+impl Heap {
+    pub const fn empty() -> Heap {
+        Heap {
+            free: None,
+            stats: HeapStats { size: 0, used: 0, peak: 0, allocs: 0, frees: 0, failed: 0 },
+            oom_hook: None,
+        }
+    }
+
+    /// Hands the memory `start .. start + size` to the heap.
+    ///
+    /// # Safety
+    /// The region must be valid, unused by anything else and live as
+    /// long as the heap. `init` must be called only once.
+    pub unsafe fn init(&mut self, start: usize, size: usize) {
+        let first = align_up(start, HEAP_UNIT);
+        let end = (start + size) & !(HEAP_UNIT - 1);
+
+        if end <= first {
+            return;
+        }
+        self.stats.size = end - first;
+        self.free = None;
+        self.insert_free(first, end - first);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    /// Total bytes in free blocks.
+    pub fn free_bytes(&self) -> usize {
+        self.blocks().map(|(_, size)| size).sum()
+    }
+
+    /// Size of the largest free block, the biggest allocation that can
+    /// still succeed with `HEAP_UNIT` alignment.
+    pub fn largest_free(&self) -> usize {
+        self.blocks().map(|(_, size)| size).max().unwrap_or(0)
+    }
+
+    pub fn set_oom_hook(&mut self, hook: OomHook) {
+        self.oom_hook = Some(hook);
+    }
+
+    /// First-fit allocation, null when no free block fits.
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = block_size(layout);
+        let align = layout.align().max(HEAP_UNIT);
+
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut cur = self.free;
+
+        while let Some(block) = cur {
+            let (addr, block_size, next) = unsafe {
+                let b = block.as_ref();
+                (block.as_ptr() as usize, b.size, b.next)
+            };
+            // A huge layout wraps around the address space, it fits nowhere
+            let fit = checked_align_up(addr, align)
+                .and_then(|start| Some((start, start.checked_add(size)?)))
+                .filter(|(_, end)| *end <= addr + block_size);
+
+            if let Some((start, end)) = fit {
+                // Unlink the block, then return the unused head and tail
+                self.set_next(prev, next);
+                if start > addr {
+                    self.insert_free(addr, start - addr);
+                }
+                if addr + block_size > end {
+                    self.insert_free(end, addr + block_size - end);
+                }
+
+                self.stats.used += size;
+                self.stats.peak = self.stats.peak.max(self.stats.used);
+                self.stats.allocs += 1;
+                return start as *mut u8;
+            }
+            prev = cur;
+            cur = next;
+        }
+
+        self.stats.failed += 1;
+        if let Some(hook) = self.oom_hook {
+            hook(layout);
+        }
+        ptr::null_mut()
+    }
+
+    /// Returns a block from `allocate` with the same `layout`.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `allocate` on this heap for
+    /// `layout` and not released yet.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let size = block_size(layout);
+
+        self.insert_free(ptr as usize, size);
+        self.stats.used -= size;
+        self.stats.frees += 1;
+    }
+
+    /// Inserts a free block in address order, merging it with adjacent blocks.
+    fn insert_free(&mut self, addr: usize, size: usize) {
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut cur = self.free;
+
+        while let Some(block) = cur {
+            if block.as_ptr() as usize > addr {
+                break;
+            }
+            prev = cur;
+            cur = unsafe { block.as_ref().next };
+        }
+
+        let mut node = FreeBlock { size, next: cur };
+
+        // Merge with the following block
+        if let Some(next) = cur {
+            if addr + size == next.as_ptr() as usize {
+                let n = unsafe { next.as_ref() };
+                node.size += n.size;
+                node.next = n.next;
+            }
+        }
+
+        // Merge with the preceding block
+        if let Some(mut p) = prev {
+            let p = unsafe { p.as_mut() };
+            if p_end(p) == addr {
+                p.size += node.size;
+                p.next = node.next;
+                return;
+            }
+        }
+
+        let block = addr as *mut FreeBlock;
+        unsafe { block.write(node) };
+        self.set_next(prev, NonNull::new(block));
+
+        fn p_end(p: &FreeBlock) -> usize {
+            p as *const FreeBlock as usize + p.size
+        }
+    }
+
+    fn set_next(&mut self, prev: Option<NonNull<FreeBlock>>, next: Option<NonNull<FreeBlock>>) {
+        match prev {
+            Some(mut p) => unsafe { p.as_mut().next = next },
+            None => self.free = next,
+        }
+    }
+
+    /// Address and size of every free block, in address order.
+    fn blocks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut cur = self.free;
+        core::iter::from_fn(move || {
+            let block = cur?;
+            let b = unsafe { block.as_ref() };
+            cur = b.next;
+            Some((block.as_ptr() as usize, b.size))
+        })
+    }
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+fn checked_align_up(addr: usize, align: usize) -> Option<usize> {
+    Some(addr.checked_add(align - 1)? & !(align - 1))
+}
+
+/// Size of the block serving `layout`.
+fn block_size(layout: Layout) -> usize {
+    align_up(layout.size().max(1), HEAP_UNIT)
+}
+
+#[cfg(all(target_arch = "riscv32", feature = "alloc"))]
+mod global {
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::cell::{Cell, RefCell};
+    use core::ptr::addr_of;
+
+    use super::{Heap, HeapStats, OomHook};
+    use crate::interrupt::{self, Mutex};
+
+    extern "C" {
+        static _heap_start: u8;
+        static _heap_end: u8;
+    }
+
+    pub struct DtimHeap {
+        heap: Mutex<RefCell<Heap>>,
+        ready: Mutex<Cell<bool>>,
+    }
+
+    #[global_allocator]
+    pub static HEAP: DtimHeap = DtimHeap {
+        heap: Mutex::new(RefCell::new(Heap::empty())),
+        ready: Mutex::new(Cell::new(false)),
+    };
+
+    impl DtimHeap {
+        /// Runs `f` on the heap in a critical section, setting the heap
+        /// up from the linker symbols on first use.
+        pub fn with<R>(&self, f: impl FnOnce(&mut Heap) -> R) -> R {
+            interrupt::free(|cs| {
+                let mut heap = self.heap.borrow_ref_mut(cs);
+                let ready = self.ready.borrow(cs);
+                if !ready.get() {
+                    let start = addr_of!(_heap_start) as usize;
+                    let end = addr_of!(_heap_end) as usize;
+                    unsafe { heap.init(start, end - start); }
+                    ready.set(true);
+                }
+                f(&mut heap)
+            })
+        }
+    }
+
+    unsafe impl GlobalAlloc for DtimHeap {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.with(|h| h.allocate(layout))
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.with(|h| h.deallocate(ptr, layout))
+        }
+    }
+
+    pub fn heap_stats() -> HeapStats {
+        HEAP.with(|h| h.stats())
+    }
+
+    /// Bytes free and the largest free block.
+    pub fn heap_free() -> (usize, usize) {
+        HEAP.with(|h| (h.free_bytes(), h.largest_free()))
+    }
+
+    /// Sets the hook called when an allocation fails. It runs with
+    /// interrupts disabled; log or reset there, `alloc` then reports the
+    /// error through `handle_alloc_error`.
+    pub fn heap_set_oom_hook(hook: OomHook) {
+        HEAP.with(|h| h.set_oom_hook(hook))
+    }
+}
+
+#[cfg(all(target_arch = "riscv32", feature = "alloc"))]
+pub use global::{heap_free, heap_set_oom_hook, heap_stats, DtimHeap, HEAP};
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::cell::Cell;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Heap over a host buffer, aligned to 64 bytes.
+    fn heap_with(size: usize) -> (Heap, Vec<u64>) {
+        let mut buf = vec![0u64; (size + 64) / 8];
+        let start = align_up(buf.as_mut_ptr() as usize, 64);
+        let mut heap = Heap::empty();
+        unsafe { heap.init(start, size) };
+        (heap, buf)
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn alloc_rounds_up_and_tracks_usage() {
+        let (mut heap, _buf) = heap_with(1024);
+
+        let p = heap.allocate(layout(1, 1));
+        assert!(!p.is_null());
+        assert_eq!(heap.stats().used, HEAP_UNIT);
+        assert_eq!(heap.free_bytes(), 1024 - HEAP_UNIT);
+
+        unsafe { heap.deallocate(p, layout(1, 1)) };
+        let s = heap.stats();
+        assert_eq!((s.used, s.peak, s.allocs, s.frees), (0, HEAP_UNIT, 1, 1));
+        assert_eq!(heap.largest_free(), 1024);
+    }
+
+    #[test]
+    fn alloc_honours_alignment() {
+        let (mut heap, _buf) = heap_with(1024);
+
+        let _a = heap.allocate(layout(HEAP_UNIT, HEAP_UNIT));
+        let b = heap.allocate(layout(32, 64));
+        assert_eq!(b as usize % 64, 0);
+
+        // The padding before `b` stays usable
+        let c = heap.allocate(layout(HEAP_UNIT, HEAP_UNIT));
+        assert!((c as usize) < b as usize);
+    }
+
+    #[test]
+    fn free_blocks_coalesce() {
+        let (mut heap, _buf) = heap_with(256);
+        let l = layout(64, 8);
+
+        let a = heap.allocate(l);
+        let b = heap.allocate(l);
+        let c = heap.allocate(l);
+        let d = heap.allocate(l);
+        assert!(heap.allocate(layout(1, 1)).is_null());
+
+        unsafe {
+            heap.deallocate(b, l);
+            heap.deallocate(d, l);
+            assert_eq!(heap.largest_free(), 64);
+            heap.deallocate(c, l);
+            assert_eq!(heap.largest_free(), 192);
+            heap.deallocate(a, l);
+        }
+        assert_eq!(heap.largest_free(), 256);
+        assert!(!heap.allocate(layout(256, 8)).is_null());
+    }
+
+    #[test]
+    fn exhaustion_calls_oom_hook() {
+        std::thread_local!(static FAILED: Cell<usize> = const { Cell::new(0) });
+        fn hook(l: Layout) {
+            FAILED.with(|f| f.set(l.size()));
+        }
+
+        let (mut heap, _buf) = heap_with(128);
+        heap.set_oom_hook(hook);
+
+        assert!(heap.allocate(layout(200, 4)).is_null());
+        assert_eq!(FAILED.with(|f| f.get()), 200);
+        assert_eq!(heap.stats().failed, 1);
+
+        // Layouts reaching past the end of the address space
+        assert!(heap.allocate(layout(isize::MAX as usize - 63, 64)).is_null());
+        assert!(heap.allocate(layout(8, 1 << (usize::BITS - 2))).is_null());
+        assert_eq!(heap.stats().failed, 3);
+    }
+}
@@ -0,0 +1,63 @@
+//! # I2C Master
+//!
+//! ```ignore
+//! let i2c = I2c::new(p.i2c0, I2cConfig { scl_hz: 100_000 });
+//! i2c.mux_pins(pins.pin12, pins.pin13)?;
+//! i2c.configure()?;
+//! i2c.write(0x20, &[0x00, 0xFF])?;
+//! ```
+
+#[path = "fe310/i2c.rs"] mod i2c_master;
+
+use crate::dio::DioPin;
+use crate::error::HalError;
+use crate::mmio::MMIO;
+use crate::peripherals::{I2c0, TLCLK_HZ};
+
+/// SDA and SCL GPIO pins, IOF0
+const I2C_PINS: (u8, u8) = (12, 13);
+
+pub struct I2cConfig {
+    pub scl_hz: u32,
+}
+
+/// Transfers with devices on an I2C bus, addressed with 7 bit addresses.
+pub trait I2cBus: Sync {
+    fn write(&self, addr: u8, bytes: &[u8]) -> Result<(), HalError> {
+        self.write_read(addr, bytes, &mut [])
+    }
+
+    /// Writes `bytes`, then reads `buf` after a repeated start.
+    fn write_read(&self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), HalError>;
+}
+
+pub struct I2c {
+    pub config: I2cConfig,
+}
+
+impl I2c {
+    pub fn new(_i2c: I2c0, config: I2cConfig) -> I2c {
+        I2c { config }
+    }
+
+    /// Routes the controller to GPIO 12 (SDA) and 13 (SCL).
+    pub fn mux_pins(&self, sda: DioPin, scl: DioPin) -> Result<(), HalError> {
+        if (sda.instance(), sda.pin_num(), scl.instance(), scl.pin_num()) != (0, I2C_PINS.0, 0, I2C_PINS.1) {
+            return Err(HalError::InvalidPin);
+        }
+        sda.mux_to_iof(false)?;
+        scl.mux_to_iof(false)
+    }
+
+    pub fn configure(&self) -> Result<(), HalError> {
+        let prer = i2c_master::i2c_compute_prescaler(TLCLK_HZ, self.config.scl_hz)?;
+        i2c_master::i2c_enable(&MMIO, prer);
+        Ok(())
+    }
+}
+
+impl I2cBus for I2c {
+    fn write_read(&self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), HalError> {
+        i2c_master::i2c_write_read(&MMIO, addr, bytes, buf)
+    }
+}
@@ -0,0 +1,245 @@
+//! # Firmware Image Header
+//!
+//! Every image starts with a jump over its header, so the boot ROM and
+//! the loaders still enter the image at its first byte:
+//!
+//! | Offset | Word       |                                           |
+//! |--------|------------|-------------------------------------------|
+//! | 0      | `j`        | `_start`, to the runtime entry            |
+//! | 4      | `magic`    | `IMAGE_MAGIC`                             |
+//! | 8      | `version`  | crate version, `major << 16 \| minor << 8 \| patch` |
+//! | 12     | `length`   | bytes of the image, `.data` included      |
+//! | 16     | `entry`    | address of `_start`                       |
+//! | 20     | `checksum` | CRC-32 of the image with this word as 0   |
+//!
+//! `rt` emits the header and the linker fills in everything but the
+//! checksum, which is written into the binary after `objcopy`:
+//!
+//! ```text
+//! riscv64-unknown-elf-objcopy -O binary led-blink led-blink.bin
+//! cargo image-stamp led-blink.bin
+//! ```
+//!
+//! `cargo image-stamp` also takes the ELF file and runs `objcopy`
+//! itself. The output of `cargo build` is not stamped: its checksum
+//! word is 0, `running_verify` and the boot loaders fail it with
+//! `Checksum`.
+//!
+//! `running_header` returns the header of the running image. Flash
+//! images can be verified while they run, RAM images only before their
+//! `.data` changes.
+
+use crate::update::Crc32;
+
+/// "RVIM"
+pub const IMAGE_MAGIC: u32 = 0x4D49_5652;
+pub const IMAGE_HEADER_OFFSET: usize = 4;
+pub const IMAGE_HEADER_LEN: usize = 20;
+/// Version of the crate the image was built with
+pub const IMAGE_VERSION: u32 = image_version(
+    env!("CARGO_PKG_VERSION_MAJOR"),
+    env!("CARGO_PKG_VERSION_MINOR"),
+    env!("CARGO_PKG_VERSION_PATCH"),
+);
+
+const IMAGE_CHECKSUM_OFFSET: usize = IMAGE_HEADER_OFFSET + 16;
+
+const fn image_version(major: &str, minor: &str, patch: &str) -> u32 {
+    (parse_u8(major) as u32) << 16 | (parse_u8(minor) as u32) << 8 | parse_u8(patch) as u32
+}
+
+const fn parse_u8(s: &str) -> u8 {
+    let b = s.as_bytes();
+    let mut v: u8 = 0;
+    let mut i = 0;
+    while i < b.len() {
+        v = v * 10 + (b[i] - b'0');
+        i += 1;
+    }
+    v
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImageError {
+    /// No header at `IMAGE_HEADER_OFFSET`
+    BadMagic,
+    /// Fewer bytes than the header says
+    Truncated,
+    /// The image does not match its checksum, or was not stamped
+    Checksum,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct ImageHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub length: u32,
+    pub entry: u32,
+    pub checksum: u32,
+}
+
+impl ImageHeader {
+    /// Header of the image starting at `image[0]`.
+    pub fn parse(image: &[u8]) -> Result<ImageHeader, ImageError> {
+        let h = image.get(IMAGE_HEADER_OFFSET..IMAGE_HEADER_OFFSET + IMAGE_HEADER_LEN).ok_or(ImageError::Truncated)?;
+        let word = |i: usize| u32::from_le_bytes([h[i], h[i + 1], h[i + 2], h[i + 3]]);
+        if word(0) != IMAGE_MAGIC {
+            return Err(ImageError::BadMagic);
+        }
+        Ok(ImageHeader { magic: word(0), version: word(4), length: word(8), entry: word(12), checksum: word(16) })
+    }
+
+    /// Major, minor and patch version.
+    pub fn version(&self) -> (u8, u8, u8) {
+        ((self.version >> 16) as u8, (self.version >> 8) as u8, self.version as u8)
+    }
+
+    /// The `length` bytes of `image` the header covers.
+    fn covered<'a>(&self, image: &'a [u8]) -> Result<&'a [u8], ImageError> {
+        let len = self.length as usize;
+        if len < IMAGE_CHECKSUM_OFFSET + 4 {
+            return Err(ImageError::Truncated);
+        }
+        image.get(..len).ok_or(ImageError::Truncated)
+    }
+}
+
+/// CRC-32 of `image` with the checksum word taken as 0.
+fn image_checksum(image: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(&image[..IMAGE_CHECKSUM_OFFSET]);
+    crc.update(&[0; 4]);
+    crc.update(&image[IMAGE_CHECKSUM_OFFSET + 4..]);
+    crc.finish()
+}
+
+/// Checks the header and checksum of the image starting at `image[0]`.
+pub fn image_verify(image: &[u8]) -> Result<ImageHeader, ImageError> {
+    let header = ImageHeader::parse(image)?;
+    if image_checksum(header.covered(image)?) != header.checksum {
+        return Err(ImageError::Checksum);
+    }
+    Ok(header)
+}
+
+/// Checks an image read in pieces with `read(offset, buf)`, e.g. from
+/// flash. Errors of `read` end the check.
+pub fn image_verify_with<E: From<ImageError>>(mut read: impl FnMut(u32, &mut [u8]) -> Result<(), E>) -> Result<ImageHeader, E> {
+    let mut head = [0u8; IMAGE_CHECKSUM_OFFSET + 4];
+    read(0, &mut head)?;
+    let header = ImageHeader::parse(&head)?;
+    if (header.length as usize) < head.len() {
+        return Err(ImageError::Truncated.into());
+    }
+
+    let mut crc = Crc32::new();
+    crc.update(&head[..IMAGE_CHECKSUM_OFFSET]);
+    crc.update(&[0; 4]);
+    let mut buf = [0u8; 256];
+    let mut at = head.len() as u32;
+    while at < header.length {
+        let n = (header.length - at).min(buf.len() as u32) as usize;
+        read(at, &mut buf[..n])?;
+        crc.update(&buf[..n]);
+        at += n as u32;
+    }
+    if crc.finish() != header.checksum {
+        return Err(ImageError::Checksum.into());
+    }
+    Ok(header)
+}
+
+/// Writes the checksum into the header of a binary image, for
+/// `cargo image-stamp`.
+pub fn image_stamp(image: &mut [u8]) -> Result<ImageHeader, ImageError> {
+    let mut header = ImageHeader::parse(image)?;
+    header.checksum = image_checksum(header.covered(image)?);
+    image[IMAGE_CHECKSUM_OFFSET..IMAGE_CHECKSUM_OFFSET + 4].copy_from_slice(&header.checksum.to_le_bytes());
+    Ok(header)
+}
+
+#[cfg(all(target_arch = "riscv32", feature = "rt"))]
+extern "C" {
+    /// Emitted by `rt`, at `IMAGE_HEADER_OFFSET` of the image
+    static _image_header: ImageHeader;
+}
+
+/// Header of the running image.
+#[cfg(all(target_arch = "riscv32", feature = "rt"))]
+pub fn running_header() -> ImageHeader {
+    unsafe { core::ptr::read_volatile(core::ptr::addr_of!(_image_header)) }
+}
+
+/// Checks the running image against its checksum.
+#[cfg(all(target_arch = "riscv32", feature = "rt"))]
+pub fn running_verify() -> Result<ImageHeader, ImageError> {
+    let header = running_header();
+    let start = core::ptr::addr_of!(_image_header) as usize - IMAGE_HEADER_OFFSET;
+    let image = unsafe { core::slice::from_raw_parts(start as *const u8, header.length as usize) };
+    image_verify(image)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    fn image(len: u32) -> Vec<u8> {
+        let mut b = Vec::from(0x0140_006F_u32.to_le_bytes());
+        for w in [IMAGE_MAGIC, IMAGE_VERSION, len, 0x2001_0018, 0] {
+            b.extend(w.to_le_bytes());
+        }
+        b.resize(len as usize, 0x5A);
+        b
+    }
+
+    #[test]
+    fn version_comes_from_cargo_toml() {
+        assert_eq!(IMAGE_VERSION, 0x00_00_0D);
+        assert_eq!(image_version("1", "20", "255"), 0x01_14_FF);
+    }
+
+    #[test]
+    fn stamped_image_verifies() {
+        let mut img = image(64);
+        assert_eq!(image_verify(&img), Err(ImageError::Checksum));
+
+        let header = image_stamp(&mut img).unwrap();
+        assert_eq!(header.version(), (0, 0, 13));
+        assert_eq!(header.entry, 0x2001_0018);
+        assert_eq!(image_verify(&img), Ok(header));
+
+        // XMODEM padding after the image is not covered
+        img.extend([0x1A; 64]);
+        assert_eq!(image_verify(&img), Ok(header));
+        img[40] ^= 1;
+        assert_eq!(image_verify(&img), Err(ImageError::Checksum));
+    }
+
+    #[test]
+    fn image_read_in_pieces_verifies() {
+        let mut img = image(600);
+        image_stamp(&mut img).unwrap();
+        let read = |img: &[u8], at: u32, buf: &mut [u8]| -> Result<(), ImageError> {
+            let at = at as usize;
+            buf.copy_from_slice(img.get(at..at + buf.len()).ok_or(ImageError::Truncated)?);
+            Ok(())
+        };
+
+        assert_eq!(image_verify_with(|at, buf| read(&img, at, buf)), image_verify(&img));
+        assert_eq!(image_verify_with(|at, buf| read(&img[..599], at, buf)), Err(ImageError::Truncated));
+        img[599] ^= 1;
+        assert_eq!(image_verify_with(|at, buf| read(&img, at, buf)), Err(ImageError::Checksum));
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let img = image(64);
+        assert_eq!(image_verify(&img[..32]), Err(ImageError::Truncated));
+        assert_eq!(ImageHeader::parse(&img[..12]), Err(ImageError::Truncated));
+        assert_eq!(ImageHeader::parse(&[0; 64]), Err(ImageError::BadMagic));
+    }
+}
@@ -0,0 +1,549 @@
+//! # Preemptive Task Kernel
+//!
+//! Fixed priority preemptive scheduling of statically allocated tasks,
+//! enabled with the `kernel` feature. Each task has its own stack and
+//! runs in M-mode with interrupts enabled.
+//!
+//! A task is switched out only on a trap: the `TrapFrame` saved by
+//! `_m_trap_entry` on the task stack is its context, and the scheduler
+//! resumes another task by making `_m_trap_entry` restore that task's
+//! frame instead. Scheduling happens after every interrupt:
+//!
+//! - the machine timer tick (`KERNEL_TICK_MS`) wakes sleeping tasks and
+//!   rotates tasks of equal priority
+//! - the machine software interrupt is raised by `kernel_yield`, e.g.
+//!   when a task blocks or wakes a task of higher priority
+//! - any other interrupt may have woken a task through a primitive
+//!
+//! The highest priority ready task runs. Priority 0 is the idle task,
+//! which is the code that called `kernel_start`.
+//!
+//! ```ignore
+//! static mut STACK: [u32; 256] = [0; 256];
+//!
+//! extern "C" fn worker(arg: u32) { loop { SEM.take(); ... } }
+//!
+//! kernel_spawn(worker, 0, 1, unsafe { &mut *addr_of_mut!(STACK) })?;
+//! kernel_start();
+//! ```
+//!
+//! Blocking primitives: `Semaphore`, `Mutex` and `MessageQueue`. Their
+//! `give`, `try_send` and `try_recv` can be used from interrupt handlers.
+//! `Mutex` does no priority inheritance.
+
+use core::arch::asm;
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::ops::{Deref, DerefMut};
+
+use crate::clint;
+use crate::interrupt::{self, CriticalSection, TrapFrame, MIE_MSIE, MSTATUS_MPIE,
+    MSTATUS_MPP_MMODE, REG_A0, REG_RA, REG_SP, TRAP_FRAME_SIZE};
+use crate::mmio::MMIO;
+use crate::swtimer::SoftTimer;
+
+pub type TaskId = usize;
+pub type TaskPriority = u8;
+pub type TaskEntry = extern "C" fn(arg: u32);
+
+/// Tasks including the idle task, one bit each in a wait list
+pub const KERNEL_MAX_TASKS: usize = 16;
+pub const KERNEL_TICK_MS: u32 = 10;
+/// Smallest task stack, in words
+pub const KERNEL_MIN_STACK: usize = 128;
+
+const IDLE_TASK: TaskId = 0;
+
+static TICK_TIMER: SoftTimer = SoftTimer::new(kernel_tick);
+
+#[derive(Debug)]
+pub enum KernelError {
+    NoFreeTask,
+    InvalidPriority,
+    InvalidStack,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum TaskState {
+    Ready,
+    Blocked,
+    /// Ready again at this tick
+    Sleeping(u64),
+    Finished,
+}
+
+#[derive(Clone, Copy)]
+struct Tcb {
+    /// Saved `TrapFrame` while switched out
+    frame: u32,
+    prio: TaskPriority,
+    state: TaskState,
+}
+
+struct Kernel {
+    tasks: [Option<Tcb>; KERNEL_MAX_TASKS],
+    current: TaskId,
+    running: bool,
+    ticks: u64,
+    /// Rotate tasks of equal priority at the next schedule
+    rotate: bool,
+}
+
+static KERNEL: interrupt::Mutex<RefCell<Kernel>> = interrupt::Mutex::new(RefCell::new(Kernel {
+    tasks: [None; KERNEL_MAX_TASKS],
+    current: IDLE_TASK,
+    running: false,
+    ticks: 0,
+    rotate: false,
+}));
+
+impl Kernel {
+    fn ready(&self, id: TaskId) -> Option<TaskPriority> {
+        match self.tasks[id] {
+            Some(t) if t.state == TaskState::Ready => Some(t.prio),
+            _ => None,
+        }
+    }
+
+    /// Highest priority ready task. The current task keeps running
+    /// unless a tick asked to rotate tasks of its priority.
+    fn pick_next(&self) -> TaskId {
+        let best = (0..KERNEL_MAX_TASKS).filter_map(|i| self.ready(i)).max().unwrap_or(0);
+        let cur = self.current;
+
+        if self.ready(cur) == Some(best) && !self.rotate {
+            return cur;
+        }
+        (1..=KERNEL_MAX_TASKS)
+            .map(|off| (cur + off) % KERNEL_MAX_TASKS)
+            .find(|&i| self.ready(i) == Some(best))
+            .unwrap_or(IDLE_TASK)
+    }
+
+    /// Marks the current task blocked and adds it to `waiters`.
+    fn block_current(&mut self, waiters: &Cell<u32>) {
+        assert!(self.running && self.current != IDLE_TASK, "Blocking outside a task");
+        waiters.set(waiters.get() | 1 << self.current);
+        if let Some(t) = self.tasks[self.current].as_mut() {
+            t.state = TaskState::Blocked;
+        }
+    }
+
+    /// Readies the highest priority task of `waiters`. Returns true when
+    /// it has a higher priority than the current task.
+    fn wake_one(&mut self, waiters: &Cell<u32>) -> bool {
+        let bits = waiters.get();
+        let woken = (0..KERNEL_MAX_TASKS)
+            .filter(|i| bits & (1 << i) != 0)
+            .max_by_key(|&i| self.tasks[i].map_or(0, |t| t.prio));
+
+        let Some(id) = woken else { return false };
+        waiters.set(bits & !(1 << id));
+
+        let cur_prio = self.tasks[self.current].map_or(0, |t| t.prio);
+        match self.tasks[id].as_mut() {
+            Some(t) if t.state == TaskState::Blocked => {
+                t.state = TaskState::Ready;
+                t.prio > cur_prio
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Creates a task running `entry(arg)` on `stack` with priority `prio`,
+/// 1 (lowest) to 255. Returning from `entry` ends the task.
+pub fn kernel_spawn(entry: TaskEntry, arg: u32, prio: TaskPriority,
+                    stack: &'static mut [u32]) -> Result<TaskId, KernelError> {
+    if prio == 0 {
+        return Err(KernelError::InvalidPriority);
+    }
+    if stack.len() < KERNEL_MIN_STACK {
+        return Err(KernelError::InvalidStack);
+    }
+
+    let top = (stack.as_mut_ptr() as u32 + (stack.len() * 4) as u32) & !0xF;
+    let frame_addr = top - TRAP_FRAME_SIZE;
+
+    interrupt::free(|cs| {
+        let mut k = KERNEL.borrow_ref_mut(cs);
+        let id = (1..KERNEL_MAX_TASKS).find(|&i| k.tasks[i].is_none_or(|t| t.state == TaskState::Finished))
+            .ok_or(KernelError::NoFreeTask)?;
+
+        // First switch to the task "returns" from a trap into `entry`
+        let frame = unsafe { &mut *(frame_addr as *mut TrapFrame) };
+        frame.regs = [0; 32];
+        frame.regs[REG_RA] = kernel_task_exit as *const () as u32;
+        frame.regs[REG_SP] = top;
+        frame.regs[REG_A0] = arg;
+        frame.mepc = entry as *const () as u32;
+        frame.mstatus = MSTATUS_MPP_MMODE | MSTATUS_MPIE;
+        frame.mcause = 0;
+        frame.mtval = 0;
+
+        k.tasks[id] = Some(Tcb { frame: frame_addr, prio, state: TaskState::Ready });
+        Ok(id)
+    })
+}
+
+/// Starts scheduling. The caller becomes the idle task, which sleeps in
+/// `wfi` whenever no other task is ready. Needs `interrupt::m_trap_init`.
+pub fn kernel_start() -> ! {
+    interrupt::free(|cs| {
+        let mut k = KERNEL.borrow_ref_mut(cs);
+        k.tasks[IDLE_TASK] = Some(Tcb { frame: 0, prio: 0, state: TaskState::Ready });
+        k.current = IDLE_TASK;
+        k.running = true;
+    });
+
+    TICK_TIMER.start_periodic_ms(KERNEL_TICK_MS);
+    interrupt::mie_enable(MIE_MSIE);
+    unsafe { interrupt::enable(); }
+    kernel_yield();
+
+    loop {
+        unsafe { asm!("wfi"); }
+    }
+}
+
+/// Asks for a reschedule through the machine software interrupt. Takes
+/// effect once interrupts are enabled.
+pub fn kernel_yield() {
+    clint::clint_set_msip(&MMIO, true);
+}
+
+pub fn kernel_current() -> TaskId {
+    interrupt::free(|cs| KERNEL.borrow_ref_mut(cs).current)
+}
+
+/// Ticks since `kernel_start`.
+pub fn kernel_ticks() -> u64 {
+    interrupt::free(|cs| KERNEL.borrow_ref_mut(cs).ticks)
+}
+
+/// Blocks the current task for `ticks` kernel ticks.
+pub fn kernel_sleep(ticks: u64) {
+    let until = interrupt::free(|cs| KERNEL.borrow_ref_mut(cs).ticks + ticks);
+
+    while kernel_ticks() < until {
+        interrupt::free(|cs| {
+            let mut k = KERNEL.borrow_ref_mut(cs);
+            let cur = k.current;
+            assert!(k.running && cur != IDLE_TASK, "Sleeping outside a task");
+            if let Some(t) = k.tasks[cur].as_mut() {
+                t.state = TaskState::Sleeping(until);
+            }
+        });
+        kernel_yield();
+    }
+}
+
+pub fn kernel_sleep_ms(ms: u32) {
+    kernel_sleep(ms.div_ceil(KERNEL_TICK_MS) as u64);
+}
+
+extern "C" fn kernel_task_exit() {
+    interrupt::free(|cs| {
+        let mut k = KERNEL.borrow_ref_mut(cs);
+        let cur = k.current;
+        if let Some(t) = k.tasks[cur].as_mut() {
+            t.state = TaskState::Finished;
+        }
+    });
+    loop {
+        kernel_yield();
+    }
+}
+
+/// Machine timer tick, from `timer::timer_interrupt`.
+fn kernel_tick(_: &'static SoftTimer) {
+    interrupt::free(|cs| {
+        let mut k = KERNEL.borrow_ref_mut(cs);
+        k.ticks += 1;
+        let now = k.ticks;
+
+        for t in k.tasks.iter_mut().flatten() {
+            if matches!(t.state, TaskState::Sleeping(until) if until <= now) {
+                t.state = TaskState::Ready;
+            }
+        }
+        k.rotate = true;
+    });
+}
+
+/// Called at the end of every interrupt trap with the saved frame of the
+/// interrupted code. Switches to the task that should run next.
+pub(crate) fn kernel_schedule(frame: &mut TrapFrame, cs: CriticalSection) {
+    let mut k = KERNEL.borrow_ref_mut(cs);
+
+    // U-mode code keeps its M-mode trap stack in `mscratch`, not switched
+    if !k.running || frame.is_from_umode() {
+        return;
+    }
+
+    let cur = k.current;
+    if let Some(t) = k.tasks[cur].as_mut() {
+        t.frame = frame as *mut TrapFrame as u32;
+    }
+
+    let next = k.pick_next();
+    k.rotate = false;
+    if next != cur {
+        k.current = next;
+        if let Some(t) = k.tasks[next] {
+            unsafe { interrupt::M_TRAP_NEXT_FRAME = t.frame; }
+        }
+    }
+}
+
+/// Counting semaphore.
+pub struct Semaphore {
+    state: interrupt::Mutex<SemState>,
+}
+
+struct SemState {
+    count: Cell<u32>,
+    max: u32,
+    waiters: Cell<u32>,
+}
+
+impl Semaphore {
+    pub const fn new(initial: u32, max: u32) -> Semaphore {
+        Semaphore {
+            state: interrupt::Mutex::new(SemState { count: Cell::new(initial), max, waiters: Cell::new(0) }),
+        }
+    }
+
+    /// Takes a unit, blocking while the count is 0.
+    pub fn take(&self) {
+        while !self.take_or_block() {
+            kernel_yield();
+        }
+    }
+
+    pub fn try_take(&self) -> bool {
+        interrupt::free(|cs| {
+            let s = self.state.borrow(cs);
+            let ok = s.count.get() > 0;
+            if ok {
+                s.count.set(s.count.get() - 1);
+            }
+            ok
+        })
+    }
+
+    /// Returns a unit and wakes a waiting task. Can be used from interrupts.
+    pub fn give(&self) {
+        let preempt = interrupt::free(|cs| {
+            let s = self.state.borrow(cs);
+            if s.count.get() < s.max {
+                s.count.set(s.count.get() + 1);
+            }
+            KERNEL.borrow_ref_mut(cs).wake_one(&s.waiters)
+        });
+        if preempt {
+            kernel_yield();
+        }
+    }
+
+    fn take_or_block(&self) -> bool {
+        interrupt::free(|cs| {
+            let s = self.state.borrow(cs);
+            if s.count.get() > 0 {
+                s.count.set(s.count.get() - 1);
+                return true;
+            }
+            KERNEL.borrow_ref_mut(cs).block_current(&s.waiters);
+            false
+        })
+    }
+}
+
+/// Mutual exclusion between tasks, blocking while another task holds it.
+/// Not usable from interrupt handlers.
+pub struct Mutex<T> {
+    owner: interrupt::Mutex<Cell<Option<TaskId>>>,
+    waiters: interrupt::Mutex<Cell<u32>>,
+    data: UnsafeCell<T>,
+}
+
+// Only the owning task accesses `data`
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex {
+            owner: interrupt::Mutex::new(Cell::new(None)),
+            waiters: interrupt::Mutex::new(Cell::new(0)),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            let locked = interrupt::free(|cs| {
+                let owner = self.owner.borrow(cs);
+                let mut k = KERNEL.borrow_ref_mut(cs);
+                if owner.get().is_none() {
+                    owner.set(Some(k.current));
+                    return true;
+                }
+                k.block_current(self.waiters.borrow(cs));
+                false
+            });
+            if locked {
+                return MutexGuard { m: self };
+            }
+            kernel_yield();
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        interrupt::free(|cs| {
+            let owner = self.owner.borrow(cs);
+            if owner.get().is_some() {
+                return None;
+            }
+            owner.set(Some(KERNEL.borrow_ref_mut(cs).current));
+            Some(MutexGuard { m: self })
+        })
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    m: &'a Mutex<T>,
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.m.data.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.m.data.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let preempt = interrupt::free(|cs| {
+            self.m.owner.borrow(cs).set(None);
+            KERNEL.borrow_ref_mut(cs).wake_one(self.m.waiters.borrow(cs))
+        });
+        if preempt {
+            kernel_yield();
+        }
+    }
+}
+
+/// Fixed capacity message queue between tasks and interrupt handlers.
+pub struct MessageQueue<T, const N: usize> {
+    state: interrupt::Mutex<RefCell<QueueState<T, N>>>,
+}
+
+struct QueueState<T, const N: usize> {
+    buf: [Option<T>; N],
+    head: usize,
+    len: usize,
+    senders: Cell<u32>,
+    receivers: Cell<u32>,
+}
+
+impl<T, const N: usize> MessageQueue<T, N> {
+    pub const fn new() -> MessageQueue<T, N> {
+        MessageQueue {
+            state: interrupt::Mutex::new(RefCell::new(QueueState {
+                buf: [const { None }; N],
+                head: 0,
+                len: 0,
+                senders: Cell::new(0),
+                receivers: Cell::new(0),
+            })),
+        }
+    }
+
+    /// Appends `msg`, blocking while the queue is full.
+    pub fn send(&self, msg: T) {
+        let mut msg = msg;
+        loop {
+            match self.send_or_block(msg, true) {
+                Ok(()) => return,
+                Err(m) => msg = m,
+            }
+            kernel_yield();
+        }
+    }
+
+    /// Appends `msg` or gives it back when the queue is full. Can be
+    /// used from interrupts.
+    pub fn try_send(&self, msg: T) -> Result<(), T> {
+        self.send_or_block(msg, false)
+    }
+
+    /// Removes the oldest message, blocking while the queue is empty.
+    pub fn recv(&self) -> T {
+        loop {
+            if let Some(msg) = self.recv_or_block(true) {
+                return msg;
+            }
+            kernel_yield();
+        }
+    }
+
+    /// Removes the oldest message if any. Can be used from interrupts.
+    pub fn try_recv(&self) -> Option<T> {
+        self.recv_or_block(false)
+    }
+
+    fn send_or_block(&self, msg: T, block: bool) -> Result<(), T> {
+        let (res, preempt) = interrupt::free(|cs| {
+            let mut q = self.state.borrow_ref_mut(cs);
+            let mut k = KERNEL.borrow_ref_mut(cs);
+            if q.len == N {
+                if block {
+                    k.block_current(&q.senders);
+                }
+                return (Err(msg), false);
+            }
+            let tail = (q.head + q.len) % N;
+            q.buf[tail] = Some(msg);
+            q.len += 1;
+            (Ok(()), k.wake_one(&q.receivers))
+        });
+        if preempt {
+            kernel_yield();
+        }
+        res
+    }
+
+    fn recv_or_block(&self, block: bool) -> Option<T> {
+        let (msg, preempt) = interrupt::free(|cs| {
+            let mut q = self.state.borrow_ref_mut(cs);
+            let mut k = KERNEL.borrow_ref_mut(cs);
+            if q.len == 0 {
+                if block {
+                    k.block_current(&q.receivers);
+                }
+                return (None, false);
+            }
+            let head = q.head;
+            let msg = q.buf[head].take();
+            q.head = (head + 1) % N;
+            q.len -= 1;
+            (msg, k.wake_one(&q.senders))
+        });
+        if preempt {
+            kernel_yield();
+        }
+        msg
+    }
+}
+
+impl<T, const N: usize> Default for MessageQueue<T, N> {
+    fn default() -> MessageQueue<T, N> {
+        MessageQueue::new()
+    }
+}
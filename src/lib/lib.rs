@@ -1,11 +1,54 @@
-
 //! # Hifive1-RevB board Library
 //!
 //! The Hifive1-RevB board Library provides the essential
 //! interface for using peripherals in board.
+//!
+//! Modules using RISC-V CSRs or assembly are only built for the
+//! target. The rest also builds on the host for `cargo test-host`.
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub mod mmio;
+pub mod error;
+pub mod peripherals;
 pub mod dio;
 pub mod serial;
-pub mod pmp;
\ No newline at end of file
+pub mod i2c;
+pub mod spi;
+pub mod expander;
+pub mod heap;
+pub mod sync;
+pub mod queue;
+pub mod event;
+pub mod swtimer;
+pub mod xmodem;
+pub mod flash;
+pub mod update;
+pub mod image;
+pub mod pmp;
+pub mod monitor;
+
+#[path = "fe310/regs.rs"] pub mod regs;
+#[path = "fe310/plic.rs"] pub mod plic;
+#[path = "fe310/clint.rs"] pub mod clint;
+#[path = "fe310/wdog.rs"] pub (crate) mod wdog;
+
+#[cfg(target_arch = "riscv32")]
+pub mod umode;
+#[cfg(target_arch = "riscv32")]
+pub mod fault;
+#[cfg(target_arch = "riscv32")]
+pub mod semihosting;
+
+#[cfg(target_arch = "riscv32")]
+pub mod executor;
+#[cfg(target_arch = "riscv32")]
+pub mod timer;
+#[cfg(all(target_arch = "riscv32", feature = "kernel"))]
+pub mod kernel;
+
+#[cfg(target_arch = "riscv32")]
+#[path = "fe310/interrupt.rs"] pub mod interrupt;
+
+#[cfg(all(target_arch = "riscv32", feature = "rt"))]
+pub mod rt;
@@ -0,0 +1,128 @@
+//! # Register Access
+//!
+//! Drivers in `fe310/` read and write peripheral registers through
+//! `RegAccess` instead of dereferencing raw pointers. On target `Mmio`
+//! does volatile accesses; host unit tests use `mock::MockRegs`, which
+//! records every access and lets a test script register values.
+
+use core::ptr;
+
+pub type RegAddr = u32;
+
+pub trait RegAccess {
+    fn read32(&self, addr: RegAddr) -> u32;
+    fn write32(&self, addr: RegAddr, v: u32);
+
+    /// Read-modify-write of the register at `addr`.
+    fn modify32<F: FnOnce(u32) -> u32>(&self, addr: RegAddr, f: F) {
+        let v = self.read32(addr);
+        self.write32(addr, f(v));
+    }
+}
+
+/// Volatile memory mapped register access. It reaches every address,
+/// so drivers keep it to themselves; see `Mmio::new`.
+pub struct Mmio(());
+
+/// Register access of the drivers
+pub (crate) static MMIO: Mmio = Mmio(());
+
+impl Mmio {
+    /// # Safety
+    ///
+    /// The caller reads and writes registers behind the back of the
+    /// drivers owning them, e.g. in a debug monitor.
+    pub const unsafe fn new() -> Mmio {
+        Mmio(())
+    }
+}
+
+impl RegAccess for Mmio {
+    fn read32(&self, addr: RegAddr) -> u32 {
+        unsafe { ptr::read_volatile(addr as usize as *const u32) }
+    }
+
+    fn write32(&self, addr: RegAddr, v: u32) {
+        unsafe { ptr::write_volatile(addr as usize as *mut u32, v) }
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    extern crate std;
+
+    use std::cell::RefCell;
+    use std::collections::{HashMap, VecDeque};
+    use std::vec::Vec;
+
+    use super::{RegAccess, RegAddr};
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum RegOp {
+        Read(RegAddr, u32),
+        Write(RegAddr, u32),
+    }
+
+    /// Register file backed by a map. Registers read as 0 until
+    /// written or set, values queued with `push_read` are returned
+    /// first, one per read.
+    #[derive(Default)]
+    pub struct MockRegs {
+        regs: RefCell<HashMap<RegAddr, u32>>,
+        reads: RefCell<HashMap<RegAddr, VecDeque<u32>>>,
+        log: RefCell<Vec<RegOp>>,
+    }
+
+    impl MockRegs {
+        pub fn new() -> MockRegs {
+            MockRegs::default()
+        }
+
+        /// Sets the value of a register without logging an access.
+        pub fn set(&self, addr: RegAddr, v: u32) {
+            self.regs.borrow_mut().insert(addr, v);
+        }
+
+        /// Current value of a register.
+        pub fn get(&self, addr: RegAddr) -> u32 {
+            *self.regs.borrow().get(&addr).unwrap_or(&0)
+        }
+
+        /// Queues `v` to be returned by the next read of `addr`, e.g.
+        /// to model a status flag that clears after some polls.
+        pub fn push_read(&self, addr: RegAddr, v: u32) {
+            self.reads.borrow_mut().entry(addr).or_default().push_back(v);
+        }
+
+        /// All accesses in order.
+        pub fn ops(&self) -> Vec<RegOp> {
+            self.log.borrow().clone()
+        }
+
+        /// Values written to `addr`, in order.
+        pub fn writes(&self, addr: RegAddr) -> Vec<u32> {
+            self.log.borrow().iter().filter_map(|op| match op {
+                RegOp::Write(a, v) if *a == addr => Some(*v),
+                _ => None,
+            }).collect()
+        }
+
+        pub fn clear_log(&self) {
+            self.log.borrow_mut().clear();
+        }
+    }
+
+    impl RegAccess for MockRegs {
+        fn read32(&self, addr: RegAddr) -> u32 {
+            let scripted = self.reads.borrow_mut().get_mut(&addr).and_then(|q| q.pop_front());
+            let v = scripted.unwrap_or_else(|| self.get(addr));
+            self.log.borrow_mut().push(RegOp::Read(addr, v));
+            v
+        }
+
+        fn write32(&self, addr: RegAddr, v: u32) {
+            self.regs.borrow_mut().insert(addr, v);
+            self.log.borrow_mut().push(RegOp::Write(addr, v));
+        }
+    }
+}
@@ -0,0 +1,585 @@
+//! # UART Monitor
+//!
+//! Command shell for bring-up and field debugging over a terminal:
+//!
+//! ```text
+//! > peek 0x10012000 4
+//! 10012000: 00010000 00010000 00000000 00000000
+//! > gpio 5 out
+//! > gpio 5 high
+//! ```
+//!
+//! `help` lists the commands. Applications add their own with
+//! `Monitor::register`:
+//!
+//! ```ignore
+//! fn led(args: &mut MonitorArgs, out: &mut dyn fmt::Write) -> Result<(), MonitorError> { ... }
+//!
+//! // Safety: the monitor pokes registers the drivers own
+//! let mmio = unsafe { Mmio::new() };
+//! let mut monitor = Monitor::new(&mmio);
+//! monitor.register(MonitorCommand { name: "led", usage: "on|off", help: "user LED", run: led })?;
+//! monitor.run(&mut uart);
+//! ```
+//!
+//! `peek` and `poke` access any address, a bad one raises an access
+//! fault like any other load or store.
+
+use core::fmt;
+use core::str::SplitWhitespace;
+
+use crate::dio::gpio;
+use crate::error::HalError;
+use crate::mmio::RegAccess;
+use crate::plic::{self, PlicIntrSources};
+use crate::regs::{gpio0, prci, Reg};
+
+pub const MONITOR_LINE_LEN: usize = 80;
+/// Commands an application can register
+pub const MONITOR_MAX_COMMANDS: usize = 8;
+/// Default `boot` address, where the boot ROM starts the application
+pub const MONITOR_BOOT_ADDR: u32 = 0x2001_0000;
+
+const MONITOR_PEEK_MAX_WORDS: u32 = 64;
+const MONITOR_PROMPT: &str = "> ";
+/// HFROSC frequency with `hfroscdiv` 0, before trimming
+const HFROSC_HZ: u32 = 72_000_000;
+/// HiFive1 Rev B crystal
+const HFXOSC_HZ: u32 = 16_000_000;
+
+pub type MonitorArgs<'a> = SplitWhitespace<'a>;
+pub type MonitorFn = fn(&mut MonitorArgs, &mut dyn fmt::Write) -> Result<(), MonitorError>;
+
+#[derive(Debug, PartialEq)]
+pub enum MonitorError {
+    /// Missing, extra or unknown arguments
+    Usage,
+    /// An argument is not a number or out of range
+    BadNumber,
+    UnknownCommand,
+    /// A command with this name exists
+    Duplicate,
+    /// `MONITOR_MAX_COMMANDS` are registered
+    Full,
+    /// Writing the output failed
+    Output,
+    Hal(HalError),
+}
+
+impl From<HalError> for MonitorError {
+    fn from(e: HalError) -> MonitorError {
+        MonitorError::Hal(e)
+    }
+}
+
+impl From<fmt::Error> for MonitorError {
+    fn from(_: fmt::Error) -> MonitorError {
+        MonitorError::Output
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MonitorCommand {
+    pub name: &'static str,
+    /// Arguments, shown by `help` and on usage errors
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: MonitorFn,
+}
+
+/// Name, usage and help of the built-in commands
+const BUILTINS: [(&str, &str, &str); 8] = [
+    ("help", "", "list the commands"),
+    ("peek", "<addr> [words]", "read memory or registers"),
+    ("poke", "<addr> <value>", "write a memory or register word"),
+    ("gpio", "<pin> [high|low|in|out|pullup|dio|iof0|iof1]", "show or set a GPIO pin"),
+    ("plic", "", "interrupt sources, enables and threshold"),
+    ("clock", "", "clock configuration"),
+    ("reset", "", "reset the SoC through the watchdog"),
+    ("boot", "[addr]", "jump to an image, 0x20010000 by default"),
+];
+
+pub struct Monitor<'a, R: RegAccess> {
+    r: &'a R,
+    line: [u8; MONITOR_LINE_LEN],
+    len: usize,
+    /// The last byte was a CR, a following LF ends no line
+    after_cr: bool,
+    commands: [Option<MonitorCommand>; MONITOR_MAX_COMMANDS],
+}
+
+impl<'a, R: RegAccess> Monitor<'a, R> {
+    pub fn new(r: &'a R) -> Monitor<'a, R> {
+        Monitor { r, line: [0; MONITOR_LINE_LEN], len: 0, after_cr: false, commands: [None; MONITOR_MAX_COMMANDS] }
+    }
+
+    pub fn register(&mut self, cmd: MonitorCommand) -> Result<(), MonitorError> {
+        let exists = BUILTINS.iter().any(|b| b.0 == cmd.name)
+            || self.commands.iter().flatten().any(|c| c.name == cmd.name);
+        if exists {
+            return Err(MonitorError::Duplicate);
+        }
+        let free = self.commands.iter_mut().find(|c| c.is_none()).ok_or(MonitorError::Full)?;
+        *free = Some(cmd);
+        Ok(())
+    }
+
+    pub fn prompt(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        out.write_str(MONITOR_PROMPT)
+    }
+
+    /// Handles a byte from the terminal: echoes it, edits the line with
+    /// backspace and runs the line on CR or LF.
+    pub fn input(&mut self, b: u8, out: &mut dyn fmt::Write) -> fmt::Result {
+        let after_cr = self.after_cr;
+        self.after_cr = b == b'\r';
+
+        match b {
+            b'\n' if after_cr => Ok(()),
+            b'\r' | b'\n' => {
+                out.write_str("\r\n")?;
+                let mut line = [0u8; MONITOR_LINE_LEN];
+                let len = self.len;
+                line[..len].copy_from_slice(&self.line[..len]);
+                self.len = 0;
+                // Only printable ASCII is stored
+                self.execute(core::str::from_utf8(&line[..len]).unwrap_or(""), out)?;
+                self.prompt(out)
+            }
+            0x08 | 0x7F if self.len > 0 => {
+                self.len -= 1;
+                out.write_str("\x08 \x08")
+            }
+            0x20..=0x7E if self.len < MONITOR_LINE_LEN => {
+                self.line[self.len] = b;
+                self.len += 1;
+                out.write_char(b as char)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs a command line and reports errors on `out`.
+    pub fn execute(&mut self, line: &str, out: &mut dyn fmt::Write) -> fmt::Result {
+        let mut args = line.split_whitespace();
+        let Some(name) = args.next() else {
+            return Ok(());
+        };
+
+        match self.run_command(name, &mut args, out) {
+            Ok(()) => Ok(()),
+            Err(MonitorError::Output) => Err(fmt::Error),
+            Err(MonitorError::Usage) => write!(out, "usage: {} {}\r\n", name, self.usage(name)),
+            Err(e) => write!(out, "error: {:?}\r\n", e),
+        }
+    }
+
+    fn usage(&self, name: &str) -> &'static str {
+        BUILTINS.iter().find(|b| b.0 == name).map(|b| b.1)
+            .or_else(|| self.commands.iter().flatten().find(|c| c.name == name).map(|c| c.usage))
+            .unwrap_or("")
+    }
+
+    fn run_command(&mut self, name: &str, args: &mut MonitorArgs, out: &mut dyn fmt::Write) -> Result<(), MonitorError> {
+        match name {
+            "help" => self.help(out),
+            "peek" => monitor_peek(self.r, args, out),
+            "poke" => monitor_poke(self.r, args),
+            "gpio" => monitor_gpio(self.r, args, out),
+            "plic" => monitor_plic(self.r, out),
+            "clock" => monitor_clock(self.r, out),
+            "reset" => monitor_reset(self.r),
+            "boot" => monitor_boot(args),
+            _ => {
+                let cmd = self.commands.iter().flatten().find(|c| c.name == name).ok_or(MonitorError::UnknownCommand)?;
+                (cmd.run)(args, out)
+            }
+        }
+    }
+
+    fn help(&self, out: &mut dyn fmt::Write) -> Result<(), MonitorError> {
+        let app = self.commands.iter().flatten().map(|c| (c.name, c.usage, c.help));
+        for (name, usage, help) in BUILTINS.into_iter().chain(app) {
+            write!(out, "{} {:<w$} {}\r\n", name, usage, help, w = 46usize.saturating_sub(name.len()))?;
+        }
+        Ok(())
+    }
+
+    /// Reads the terminal on `uart` and runs the commands, forever.
+    #[cfg(target_arch = "riscv32")]
+    pub fn run<U: crate::serial::DoReceiveByte + fmt::Write>(&mut self, uart: &mut U) -> ! {
+        let _ = self.prompt(uart);
+        loop {
+            if let Ok(Some(b)) = uart.do_receive_byte() {
+                let _ = self.input(b, uart);
+            }
+        }
+    }
+}
+
+/// Number in decimal or, with `0x`, hexadecimal.
+fn monitor_parse(arg: Option<&str>) -> Result<u32, MonitorError> {
+    let s = arg.ok_or(MonitorError::Usage)?;
+    let v = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    v.map_err(|_| MonitorError::BadNumber)
+}
+
+fn monitor_no_more(args: &mut MonitorArgs) -> Result<(), MonitorError> {
+    match args.next() {
+        Some(_) => Err(MonitorError::Usage),
+        None => Ok(()),
+    }
+}
+
+fn monitor_word_addr(arg: Option<&str>) -> Result<u32, MonitorError> {
+    let addr = monitor_parse(arg)?;
+    if addr % 4 != 0 {
+        return Err(MonitorError::BadNumber);
+    }
+    Ok(addr)
+}
+
+fn monitor_peek(r: &impl RegAccess, args: &mut MonitorArgs, out: &mut dyn fmt::Write) -> Result<(), MonitorError> {
+    let addr = monitor_word_addr(args.next())?;
+    let words = match args.next() {
+        Some(n) => monitor_parse(Some(n))?,
+        None => 1,
+    };
+    monitor_no_more(args)?;
+    if words == 0 || words > MONITOR_PEEK_MAX_WORDS || addr.checked_add(4 * (words - 1)).is_none() {
+        return Err(MonitorError::BadNumber);
+    }
+
+    for i in 0..words {
+        let a = addr + 4 * i;
+        if i % 4 == 0 {
+            write!(out, "{:08x}:", a)?;
+        }
+        write!(out, " {:08x}", r.read32(a))?;
+        if i % 4 == 3 || i == words - 1 {
+            out.write_str("\r\n")?;
+        }
+    }
+    Ok(())
+}
+
+fn monitor_poke(r: &impl RegAccess, args: &mut MonitorArgs) -> Result<(), MonitorError> {
+    let addr = monitor_word_addr(args.next())?;
+    let v = monitor_parse(args.next())?;
+    monitor_no_more(args)?;
+    r.write32(addr, v);
+    Ok(())
+}
+
+fn monitor_gpio(r: &impl RegAccess, args: &mut MonitorArgs, out: &mut dyn fmt::Write) -> Result<(), MonitorError> {
+    let pin = monitor_parse(args.next())?;
+    if pin >= 32 {
+        return Err(HalError::InvalidPin.into());
+    }
+    let p = pin as u8;
+    let action = args.next();
+    monitor_no_more(args)?;
+
+    match action {
+        None => {
+            let bit = |v: u32| (v >> pin) & 1;
+            write!(out, "gpio {}: in {} out {} input_en {} output_en {} pue {} iof_en {} iof_sel {}\r\n",
+                pin,
+                bit(r.read32(gpio0::BASE + gpio0::input_val::OFFSET)),
+                bit(r.read32(gpio0::BASE + gpio0::output_val::OFFSET)),
+                bit(r.read32(gpio0::BASE + gpio0::input_en::OFFSET)),
+                bit(r.read32(gpio0::BASE + gpio0::output_en::OFFSET)),
+                bit(r.read32(gpio0::BASE + gpio0::pue::OFFSET)),
+                bit(r.read32(gpio0::BASE + gpio0::iof_en::OFFSET)),
+                bit(r.read32(gpio0::BASE + gpio0::iof_sel::OFFSET)))?;
+        }
+        Some("high") => gpio::set_high(r, p),
+        Some("low") => gpio::set_low(r, p),
+        Some("in") => {
+            gpio::disable_outlet(r, p);
+            gpio::enable_inlet(r, p);
+        }
+        Some("out") => gpio::enable_outlet(r, p),
+        Some("pullup") => gpio::enable_pullup(r, p),
+        Some("dio") => gpio::set_as_dio(r, p),
+        Some("iof0") | Some("iof1") => {
+            gpio::select_iof_func(r, p, action == Some("iof1"));
+            gpio::set_as_iof(r, p);
+        }
+        Some(_) => return Err(MonitorError::Usage),
+    }
+    Ok(())
+}
+
+fn monitor_plic(r: &impl RegAccess, out: &mut dyn fmt::Write) -> Result<(), MonitorError> {
+    let enabled = [plic::plic_enabled(r, 0), plic::plic_enabled(r, 1)];
+    let pending = [plic::plic_pending(r, 0), plic::plic_pending(r, 1)];
+    write!(out, "threshold {}\r\n", plic::plic_priority_threshold(r))?;
+
+    for id in PlicIntrSources::aon_wdog as u32..=PlicIntrSources::i2c as u32 {
+        let (word, bit) = (id as usize / 32, 1 << (id % 32));
+        if (enabled[word] | pending[word]) & bit == 0 {
+            continue;
+        }
+        let Some(src) = PlicIntrSources::from_id(id) else {
+            continue;
+        };
+        write!(out, "{:2} {:<10} priority {}{}{}\r\n",
+            id,
+            // Debug names the variant
+            DebugName(src),
+            plic::plic_priority(r, src),
+            if enabled[word] & bit != 0 { " enabled" } else { "" },
+            if pending[word] & bit != 0 { " pending" } else { "" })?;
+    }
+    Ok(())
+}
+
+/// Pads the `Debug` output of a source, which `Debug` alone does not.
+struct DebugName(PlicIntrSources);
+
+impl fmt::Display for DebugName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut name = [0u8; 16];
+        let mut w = SliceWriter { buf: &mut name, len: 0 };
+        fmt::write(&mut w, format_args!("{:?}", self.0))?;
+        let len = w.len;
+        f.pad(core::str::from_utf8(&name[..len]).unwrap_or("?"))
+    }
+}
+
+struct SliceWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf.get_mut(self.len..end).ok_or(fmt::Error)?.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// `hfclk` from the PRCI configuration, HFROSC untrimmed.
+/// In 64 bits: a misconfigured PLL fed from HFROSC goes past 4 GHz.
+fn monitor_hfclk_hz(r: &impl RegAccess) -> u64 {
+    let hfrosccfg = Reg::<prci::hfrosccfg::Spec, _>::new(r, prci::BASE).read();
+    let pllcfg = Reg::<prci::pllcfg::Spec, _>::new(r, prci::BASE).read();
+    let plloutdiv = Reg::<prci::plloutdiv::Spec, _>::new(r, prci::BASE).read();
+
+    let hfrosc = (HFROSC_HZ / (hfrosccfg.hfroscdiv() + 1)) as u64;
+    if !pllcfg.pllsel() {
+        return hfrosc;
+    }
+    let pll_ref = if pllcfg.pllrefsel() { HFXOSC_HZ as u64 } else { hfrosc };
+    let pll = if pllcfg.pllbypass() {
+        pll_ref
+    } else {
+        // ref / R * F / Q, with R = r + 1, F = 2 (f + 1), Q = 2^q
+        (pll_ref / (pllcfg.pllr() as u64 + 1) * 2 * (pllcfg.pllf() as u64 + 1)) >> pllcfg.pllq()
+    };
+    if plloutdiv.plloutdivby1() {
+        pll
+    } else {
+        pll / (2 * (plloutdiv.plloutdiv() as u64 + 1))
+    }
+}
+
+fn monitor_clock(r: &impl RegAccess, out: &mut dyn fmt::Write) -> Result<(), MonitorError> {
+    let pllcfg = Reg::<prci::pllcfg::Spec, _>::new(r, prci::BASE).read();
+    let source = match (pllcfg.pllsel(), pllcfg.pllrefsel(), pllcfg.pllbypass()) {
+        (false, _, _) => "hfrosc",
+        (true, true, true) => "hfxosc",
+        (true, false, true) => "hfrosc",
+        (true, true, false) => "pll from hfxosc",
+        (true, false, false) => "pll from hfrosc",
+    };
+    write!(out, "hfclk {} Hz, {}\r\n", monitor_hfclk_hz(r), source)?;
+    write!(out, "hfrosccfg {:08x} hfxosccfg {:08x} pllcfg {:08x} plloutdiv {:08x}\r\n",
+        r.read32(prci::BASE + prci::hfrosccfg::OFFSET),
+        r.read32(prci::BASE + prci::hfxosccfg::OFFSET),
+        pllcfg.bits(),
+        r.read32(prci::BASE + prci::plloutdiv::OFFSET))?;
+    write!(out, "mtime {} at 32768 Hz\r\n", crate::clint::clint_read_mtime(r))?;
+    Ok(())
+}
+
+fn monitor_reset(r: &impl RegAccess) -> Result<(), MonitorError> {
+    crate::wdog::wdog_reset(r);
+
+    #[cfg(target_arch = "riscv32")]
+    loop {
+        unsafe { core::arch::asm!("wfi"); }
+    }
+    #[cfg(not(target_arch = "riscv32"))]
+    Ok(())
+}
+
+fn monitor_boot(args: &mut MonitorArgs) -> Result<(), MonitorError> {
+    let addr = match args.next() {
+        Some(a) => monitor_parse(Some(a))?,
+        None => MONITOR_BOOT_ADDR,
+    };
+    monitor_no_more(args)?;
+    if addr % 2 != 0 {
+        return Err(MonitorError::BadNumber);
+    }
+    monitor_jump(addr)
+}
+
+#[cfg(target_arch = "riscv32")]
+fn monitor_jump(addr: u32) -> Result<(), MonitorError> {
+    crate::interrupt::disable();
+    unsafe {
+        core::arch::asm!("fence.i");
+        let entry: extern "C" fn() -> ! = core::mem::transmute(addr as usize);
+        entry()
+    }
+}
+
+/// Nothing to boot on the host
+#[cfg(not(target_arch = "riscv32"))]
+fn monitor_jump(_addr: u32) -> Result<(), MonitorError> {
+    Err(HalError::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::mmio::mock::MockRegs;
+    use std::string::String;
+
+    fn type_line(m: &mut Monitor<MockRegs>, line: &str) -> String {
+        let mut out = String::new();
+        for b in line.bytes() {
+            m.input(b, &mut out).unwrap();
+        }
+        out
+    }
+
+    fn led(args: &mut MonitorArgs, out: &mut dyn fmt::Write) -> Result<(), MonitorError> {
+        match args.next() {
+            Some("on") => write!(out, "led on\r\n").map_err(Into::into),
+            _ => Err(MonitorError::Usage),
+        }
+    }
+
+    const LED: MonitorCommand = MonitorCommand { name: "led", usage: "on|off", help: "user LED", run: led };
+
+    #[test]
+    fn line_editing_and_peek() {
+        let r = MockRegs::new();
+        r.set(0x1001_2000, 0xABCD);
+        r.set(0x1001_2004, 0x1);
+        let mut m = Monitor::new(&r);
+
+        let out = type_line(&mut m, "peek 0x10012001\x7f0 2x\x08\r\n");
+        assert_eq!(out, "peek 0x10012001\x08 \x080 2x\x08 \x08\r\n10012000: 0000abcd 00000001\r\n> ");
+        assert_eq!(type_line(&mut m, "peek 3\r"), "peek 3\r\nerror: BadNumber\r\n> ");
+        assert_eq!(type_line(&mut m, "peek\r"), "peek\r\nusage: peek <addr> [words]\r\n> ");
+    }
+
+    #[test]
+    fn poke_and_gpio_write_registers() {
+        let r = MockRegs::new();
+        let mut m = Monitor::new(&r);
+        let mut out = String::new();
+
+        m.execute("poke 0x80000010 305419896", &mut out).unwrap();
+        m.execute("gpio 5 out", &mut out).unwrap();
+        m.execute("gpio 5 high", &mut out).unwrap();
+        m.execute("gpio 32 high", &mut out).unwrap();
+        m.execute("gpio 5 sideways", &mut out).unwrap();
+
+        assert_eq!(r.writes(0x8000_0010), [0x1234_5678]);
+        assert_eq!(r.get(0x1001_2008), 1 << 5);
+        assert_eq!(r.get(0x1001_200C), 1 << 5);
+        assert_eq!(out, "error: Hal(InvalidPin)\r\nusage: gpio <pin> [high|low|in|out|pullup|dio|iof0|iof1]\r\n");
+
+        out.clear();
+        m.execute("gpio 5", &mut out).unwrap();
+        assert_eq!(out, "gpio 5: in 0 out 1 input_en 0 output_en 1 pue 0 iof_en 0 iof_sel 0\r\n");
+    }
+
+    #[test]
+    fn registered_commands_run_and_show_in_help() {
+        let r = MockRegs::new();
+        let mut m = Monitor::new(&r);
+        m.register(LED).unwrap();
+        assert_eq!(m.register(LED).err(), Some(MonitorError::Duplicate));
+        assert_eq!(m.register(MonitorCommand { name: "peek", ..LED }).err(), Some(MonitorError::Duplicate));
+
+        let mut out = String::new();
+        m.execute("led on", &mut out).unwrap();
+        m.execute("led", &mut out).unwrap();
+        m.execute("blink", &mut out).unwrap();
+        assert_eq!(out, "led on\r\nusage: led on|off\r\nerror: UnknownCommand\r\n");
+
+        out.clear();
+        m.execute("help", &mut out).unwrap();
+        assert!(out.starts_with("help "));
+        assert!(out.ends_with("led on|off                                      user LED\r\n"));
+
+        for name in ["a", "b", "c", "d", "e", "f", "g"] {
+            m.register(MonitorCommand { name, ..LED }).unwrap();
+        }
+        assert_eq!(m.register(MonitorCommand { name: "h", ..LED }).err(), Some(MonitorError::Full));
+    }
+
+    #[test]
+    fn help_lists_long_names() {
+        let r = MockRegs::new();
+        let mut m = Monitor::new(&r);
+        let name = "a_command_name_longer_than_the_usage_column_is_wide";
+        m.register(MonitorCommand { name, ..LED }).unwrap();
+
+        let mut out = String::new();
+        m.execute("help", &mut out).unwrap();
+        assert!(out.ends_with("a_command_name_longer_than_the_usage_column_is_wide on|off user LED\r\n"));
+    }
+
+    #[test]
+    fn reset_starts_the_watchdog() {
+        let r = MockRegs::new();
+        let mut m = Monitor::new(&r);
+        m.execute("reset", &mut String::new()).unwrap();
+
+        assert_eq!(r.writes(0x1000_0020), [1]);
+        assert_eq!(r.writes(0x1000_0000), [0, 0x1100]);
+    }
+
+    #[test]
+    fn clock_and_plic_status() {
+        let r = MockRegs::new();
+        // HFROSC reset configuration, then PLL bypassed from HFXOSC
+        r.set(0x1000_8000, 0x4010_0004);
+        assert_eq!(monitor_hfclk_hz(&r), 14_400_000);
+        r.set(0x1000_8008, 0x0007_0DF1);
+        r.set(0x1000_800C, 0x100);
+        assert_eq!(monitor_hfclk_hz(&r), 16_000_000);
+        // 16 MHz / 2 * 64 / 2 = 256 MHz, divided by 2
+        r.set(0x1000_8008, 0x0003_05F1);
+        r.set(0x1000_800C, 0);
+        assert_eq!(monitor_hfclk_hz(&r), 128_000_000);
+        // 72 MHz * 128 from an undivided HFROSC, out of spec
+        r.set(0x1000_8000, 0x4000_0000);
+        r.set(0x1000_8008, 0x0001_03F0);
+        r.set(0x1000_800C, 0x100);
+        assert_eq!(monitor_hfclk_hz(&r), 9_216_000_000);
+
+        r.set(0x0C00_2000, 1 << 3);
+        r.set(0x0C00_1000, 1 << 3);
+        r.set(0x0C00_000C, 7);
+        r.set(0x0C20_0000, 1);
+        let mut out = String::new();
+        Monitor::new(&r).execute("plic", &mut out).unwrap();
+        assert_eq!(out, "threshold 1\r\n 3 uart0      priority 7 enabled pending\r\n");
+    }
+}
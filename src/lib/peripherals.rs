@@ -0,0 +1,103 @@
+//! # Peripheral Ownership
+//!
+//! `Peripherals::take` returns a handle for every peripheral of the
+//! FE310, once. Drivers take the handle by value, so a peripheral is
+//! configured by a single owner:
+//!
+//! ```ignore
+//! let p = Peripherals::take().unwrap();
+//! let pins = p.gpio.split();
+//! let uart = Uart::new(p.uart0, config);
+//! ```
+//!
+//! The handles are zero sized. `Peripherals::take` returns `None` when
+//! called again.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::regs;
+
+/// Peripheral clock `tlclk` at reset, input clock of the UART, SPI and
+/// I2C controllers
+pub const TLCLK_HZ: u32 = 16_000_000;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+macro_rules! peripherals {
+    ($($(#[$doc:meta])* $field:ident: $name:ident = $base:path,)*) => {
+        $(
+            $(#[$doc])*
+            pub struct $name {
+                _0: (),
+            }
+
+            impl $name {
+                pub const BASE: u32 = $base;
+            }
+        )*
+
+        /// All peripherals, see `Peripherals::take`.
+        pub struct Peripherals {
+            $(pub $field: $name,)*
+        }
+
+        impl Peripherals {
+            /// # Safety
+            /// Creates handles regardless of `take`, the caller makes
+            /// sure no other owner of a peripheral uses it.
+            pub unsafe fn steal() -> Peripherals {
+                TAKEN.store(true, Ordering::Relaxed);
+                Peripherals {
+                    $($field: $name { _0: () },)*
+                }
+            }
+        }
+    };
+}
+
+peripherals! {
+    /// General Purpose Input/Output, pins 0 - 31
+    gpio: Gpio = regs::gpio0::BASE,
+    uart0: Uart0 = regs::uart0::BASE,
+    uart1: Uart1 = regs::uart1::BASE,
+    /// Platform Level Interrupt Controller
+    plic: Plic = regs::plic::BASE,
+    /// Core Local Interruptor
+    clint: Clint = regs::clint::BASE,
+    pwm0: Pwm0 = regs::pwm0::BASE,
+    pwm1: Pwm1 = regs::pwm1::BASE,
+    pwm2: Pwm2 = regs::pwm2::BASE,
+    /// SPI of the boot flash
+    qspi0: Qspi0 = regs::qspi0::BASE,
+    spi1: Spi1 = regs::spi1::BASE,
+    spi2: Spi2 = regs::spi2::BASE,
+    i2c0: I2c0 = regs::i2c0::BASE,
+    /// Power, Reset, Clock, Interrupt
+    prci: Prci = regs::prci::BASE,
+    /// Always-On domain
+    aon: Aon = regs::aon::BASE,
+}
+
+impl Peripherals {
+    /// Returns the peripherals on the first call, `None` afterwards.
+    pub fn take() -> Option<Peripherals> {
+        if TAKEN.swap(true, Ordering::AcqRel) {
+            None
+        } else {
+            Some(unsafe { Peripherals::steal() })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_returns_peripherals_once() {
+        let p = Peripherals::take();
+        assert!(p.is_some());
+        assert!(Peripherals::take().is_none());
+        assert_eq!(Uart1::BASE, 0x1002_3000);
+    }
+}
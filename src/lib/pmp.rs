@@ -1,10 +1,37 @@
-#![no_std]
-#![no_main]
+//! # Physical Memory Protection
+//!
+//! FE310-G002 E31 core implements 8 PMP entries. Each entry has
+//! an address register `pmpaddrN` and a config byte in `pmpcfg0`
+//! (entries 0..3) or `pmpcfg1` (entries 4..7).
+//!
+//! ```text
+//! ---------------------------------------------
+//! |  L  | RESERVED |   A   |  X  |  W  |  R  |
+//! ---------------------------------------------
+//! |  7  |  [6:5]   | [4:3] |  2  |  1  |  0  |
+//! ---------------------------------------------
+//! ```
 
+#[cfg(target_arch = "riscv32")]
 use core::arch::asm;
 
 pub type PmpRegionStartAddr = u32;
 pub type PmpRegionSize = u32;
+pub type PmpEntryIndex = u8;
+
+pub const PMP_ENTRY_COUNT: u8 = 8;
+
+const PMPCFG_R: u8 = 0x01;
+const PMPCFG_W: u8 = 0x02;
+const PMPCFG_X: u8 = 0x04;
+const PMPCFG_A_SHIFT: u8 = 3;
+const PMPCFG_A_MASK: u8 = 0x18;
+const PMPCFG_L: u8 = 0x80;
+
+const PMPCFG_A_OFF: u8 = 0;
+const PMPCFG_A_TOR: u8 = 1;
+const PMPCFG_A_NA4: u8 = 2;
+const PMPCFG_A_NAPOT: u8 = 3;
 
 pub enum PmpRegionAddrMatchMode{
     PmpDisabled,
@@ -32,29 +59,598 @@ pub struct PmpRegion {
 }
 
 impl PmpRegion {
-    pub fn pmp_configure_region(&self) {
-        match &(*self).amm {
 
-            PmpDisabled => {
-                unsafe {
-                    asm!("nop");
+    /// Programs PMP `entry` with this region and updates `status`.
+    ///
+    /// TOR uses `pmpaddr[entry - 1]` as the bottom of the range, so
+    /// it is written with `start` as well and must not hold an active
+    /// region. For entry 0 the bottom is address 0 and `start` must be 0.
+    pub fn pmp_configure_region(&mut self, entry: PmpEntryIndex) {
+
+        if entry >= PMP_ENTRY_COUNT || pmp_entry_is_locked(entry) {
+            self.status = PmpRegionState::CreationFailed;
+            return;
+        }
+
+        if matches!(self.amm, PmpRegionAddrMatchMode::PmpDisabled) {
+            pmp_write_cfg(entry, PMPCFG_A_OFF << PMPCFG_A_SHIFT);
+            self.status = PmpRegionState::Disabled;
+            return;
+        }
+
+        let Some(enc) = pmp_encode(&self.amm, self.start, self.sz, entry) else {
+            self.status = PmpRegionState::CreationFailed;
+            return;
+        };
+        if let Some(base) = enc.base {
+            // The bottom of the range must not move another region
+            if pmp_entry_is_locked(entry - 1) || pmp_read_cfg(entry - 1) & PMPCFG_A_MASK != 0 {
+                self.status = PmpRegionState::CreationFailed;
+                return;
+            }
+            pmp_write_addr(entry - 1, base);
+        }
+        pmp_write_addr(entry, enc.addr);
+        let a = enc.a;
+
+        let mut cfg = a << PMPCFG_A_SHIFT;
+        if self.r { cfg |= PMPCFG_R; }
+        if self.w { cfg |= PMPCFG_W; }
+        if self.x { cfg |= PMPCFG_X; }
+        if self.l { cfg |= PMPCFG_L; }
+
+        pmp_write_cfg(entry, cfg);
+
+        // WARL fields: read back to confirm the hart accepted the config
+        if pmp_read_cfg(entry) == cfg {
+            self.status = PmpRegionState::Active;
+        } else {
+            self.status = PmpRegionState::CreationFailed;
+        }
+    }
+}
+
+/// `pmpaddr` values and `A` field of a region
+#[derive(Debug, PartialEq)]
+struct PmpEncoding {
+    a: u8,
+    /// `pmpaddr` of the region's entry
+    addr: u32,
+    /// `pmpaddr` of the entry below, the bottom of a TOR range
+    base: Option<u32>,
+}
+
+/// Encodes a region at `start` of `sz` bytes for PMP `entry`. `None`
+/// when the mode can not express it or the region is disabled.
+fn pmp_encode(amm: &PmpRegionAddrMatchMode, start: u32, sz: u32, entry: PmpEntryIndex) -> Option<PmpEncoding> {
+    match amm {
+        PmpRegionAddrMatchMode::PmpDisabled => None,
+        PmpRegionAddrMatchMode::TopOfRange => {
+            let end = start as u64 + sz as u64;
+            if sz == 0 || start & 0x3 != 0 || end & 0x3 != 0 {
+                return None;
+            }
+            if entry == 0 && start != 0 {
+                return None;
+            }
+            let base = if entry > 0 { Some(start >> 2) } else { None };
+            Some(PmpEncoding { a: PMPCFG_A_TOR, addr: (end >> 2) as u32, base })
+        }
+        PmpRegionAddrMatchMode::NatAlignedFourByte => {
+            if sz != 4 || start & 0x3 != 0 {
+                return None;
+            }
+            Some(PmpEncoding { a: PMPCFG_A_NA4, addr: start >> 2, base: None })
+        }
+        PmpRegionAddrMatchMode::NatAlignedPwrOfTwo => {
+            if sz < 8 || !sz.is_power_of_two() || start & (sz - 1) != 0 {
+                return None;
+            }
+            // Trailing ones in pmpaddr encode the size: (sz/2 - 1) >> 2
+            Some(PmpEncoding { a: PMPCFG_A_NAPOT, addr: (start | ((sz >> 1) - 1)) >> 2, base: None })
+        }
+    }
+}
+
+/// `[start, end)` byte addresses matched with mode `a` and `pmpaddr`
+/// `addr`, `below` being the `pmpaddr` of the entry below (0 for entry 0).
+fn pmp_decode_bounds(a: u8, addr: u32, below: u32) -> (u64, u64) {
+    let addr = addr as u64;
+
+    match a {
+        PMPCFG_A_TOR => ((below as u64) << 2, addr << 2),
+        PMPCFG_A_NA4 => (addr << 2, (addr << 2) + 4),
+        PMPCFG_A_NAPOT => {
+            let t = (addr as u32).trailing_ones() as u64;
+            let sz = 1u64 << (t + 3);
+            let start = (addr & !((1u64 << (t + 1)) - 1)) << 2;
+            (start, start + sz)
+        }
+        _ => (0, 0),
+    }
+}
+
+/// Returns the config byte of PMP `entry`.
+pub (crate) fn pmp_read_cfg(entry: PmpEntryIndex) -> u8 {
+    let shift = (entry % 4) * 8;
+    ((pmp_read_cfg_reg(entry / 4) >> shift) & 0xFF) as u8
+}
+
+/// Returns true when the `L` bit of PMP `entry` is set. Locked
+/// entries can only be cleared by a reset.
+pub (crate) fn pmp_entry_is_locked(entry: PmpEntryIndex) -> bool {
+    pmp_read_cfg(entry) & PMPCFG_L != 0
+}
+
+/// Returns the address matching mode field of PMP `entry`.
+pub (crate) fn pmp_read_addr_match_mode(entry: PmpEntryIndex) -> PmpRegionAddrMatchMode {
+    match (pmp_read_cfg(entry) & PMPCFG_A_MASK) >> PMPCFG_A_SHIFT {
+        PMPCFG_A_TOR => PmpRegionAddrMatchMode::TopOfRange,
+        PMPCFG_A_NA4 => PmpRegionAddrMatchMode::NatAlignedFourByte,
+        PMPCFG_A_NAPOT => PmpRegionAddrMatchMode::NatAlignedPwrOfTwo,
+        _ => PmpRegionAddrMatchMode::PmpDisabled,
+    }
+}
+
+fn pmp_write_cfg(entry: PmpEntryIndex, cfg: u8) {
+    let shift = (entry % 4) * 8;
+    let reg = pmp_read_cfg_reg(entry / 4);
+    let reg = (reg & !(0xFF << shift)) | ((cfg as u32) << shift);
+    pmp_write_cfg_reg(entry / 4, reg);
+}
+
+#[cfg(target_arch = "riscv32")]
+fn pmp_read_cfg_reg(n: u8) -> u32 {
+    let v: u32;
+    unsafe {
+        match n {
+            0 => asm!("csrr {}, pmpcfg0", out(reg) v),
+            1 => asm!("csrr {}, pmpcfg1", out(reg) v),
+            _ => panic!("Invalid pmpcfg register"),
+        }
+    }
+    v
+}
+
+#[cfg(target_arch = "riscv32")]
+fn pmp_write_cfg_reg(n: u8, v: u32) {
+    unsafe {
+        match n {
+            0 => asm!("csrw pmpcfg0, {}", in(reg) v),
+            1 => asm!("csrw pmpcfg1, {}", in(reg) v),
+            _ => panic!("Invalid pmpcfg register"),
+        }
+    }
+}
+
+/// Returns `pmpaddrN`, the region address shifted right by 2.
+#[cfg(target_arch = "riscv32")]
+pub (crate) fn pmp_read_addr(entry: PmpEntryIndex) -> u32 {
+    let v: u32;
+    unsafe {
+        match entry {
+            0 => asm!("csrr {}, pmpaddr0", out(reg) v),
+            1 => asm!("csrr {}, pmpaddr1", out(reg) v),
+            2 => asm!("csrr {}, pmpaddr2", out(reg) v),
+            3 => asm!("csrr {}, pmpaddr3", out(reg) v),
+            4 => asm!("csrr {}, pmpaddr4", out(reg) v),
+            5 => asm!("csrr {}, pmpaddr5", out(reg) v),
+            6 => asm!("csrr {}, pmpaddr6", out(reg) v),
+            7 => asm!("csrr {}, pmpaddr7", out(reg) v),
+            _ => panic!("Invalid pmp entry"),
+        }
+    }
+    v
+}
+
+#[cfg(target_arch = "riscv32")]
+fn pmp_write_addr(entry: PmpEntryIndex, v: u32) {
+    unsafe {
+        match entry {
+            0 => asm!("csrw pmpaddr0, {}", in(reg) v),
+            1 => asm!("csrw pmpaddr1, {}", in(reg) v),
+            2 => asm!("csrw pmpaddr2, {}", in(reg) v),
+            3 => asm!("csrw pmpaddr3, {}", in(reg) v),
+            4 => asm!("csrw pmpaddr4, {}", in(reg) v),
+            5 => asm!("csrw pmpaddr5, {}", in(reg) v),
+            6 => asm!("csrw pmpaddr6, {}", in(reg) v),
+            7 => asm!("csrw pmpaddr7, {}", in(reg) v),
+            _ => panic!("Invalid pmp entry"),
+        }
+    }
+}
+
+/// No PMP on the host, entries read as off
+#[cfg(all(not(target_arch = "riscv32"), not(test)))]
+fn pmp_read_cfg_reg(_n: u8) -> u32 {
+    0
+}
+
+#[cfg(all(not(target_arch = "riscv32"), not(test)))]
+fn pmp_write_cfg_reg(_n: u8, _v: u32) {}
+
+#[cfg(all(not(target_arch = "riscv32"), not(test)))]
+pub (crate) fn pmp_read_addr(_entry: PmpEntryIndex) -> u32 {
+    0
+}
+
+#[cfg(all(not(target_arch = "riscv32"), not(test)))]
+fn pmp_write_addr(_entry: PmpEntryIndex, _v: u32) {}
+
+// Host tests get a pmpcfg/pmpaddr file per test thread. Like the
+// hardware, writes to locked entries are ignored.
+#[cfg(all(not(target_arch = "riscv32"), test))]
+std::thread_local! {
+    static PMP_CFG: core::cell::Cell<[u32; 2]> = const { core::cell::Cell::new([0; 2]) };
+    static PMP_ADDR: core::cell::Cell<[u32; 8]> = const { core::cell::Cell::new([0; 8]) };
+}
+
+#[cfg(all(not(target_arch = "riscv32"), test))]
+fn pmp_read_cfg_reg(n: u8) -> u32 {
+    PMP_CFG.with(|c| c.get()[n as usize])
+}
+
+#[cfg(all(not(target_arch = "riscv32"), test))]
+fn pmp_write_cfg_reg(n: u8, v: u32) {
+    PMP_CFG.with(|c| {
+        let mut regs = c.get();
+        let mut locked = 0u32;
+        for shift in [0, 8, 16, 24] {
+            if (regs[n as usize] >> shift) as u8 & PMPCFG_L != 0 {
+                locked |= 0xFF << shift;
+            }
+        }
+        regs[n as usize] = (regs[n as usize] & locked) | (v & !locked);
+        c.set(regs);
+    });
+}
+
+#[cfg(all(not(target_arch = "riscv32"), test))]
+pub (crate) fn pmp_read_addr(entry: PmpEntryIndex) -> u32 {
+    PMP_ADDR.with(|a| a.get()[entry as usize])
+}
+
+#[cfg(all(not(target_arch = "riscv32"), test))]
+fn pmp_write_addr(entry: PmpEntryIndex, v: u32) {
+    if pmp_entry_is_locked(entry) {
+        return;
+    }
+    PMP_ADDR.with(|a| {
+        let mut regs = a.get();
+        regs[entry as usize] = v;
+        a.set(regs);
+    });
+}
+
+#[derive(Debug)]
+pub enum PmpError {
+    /// Entry index of 8 or more
+    InvalidEntry,
+    InvalidRegion,
+    NoFreeEntry,
+    Overlap,
+    EntryLocked,
+    EntryNotAllocated,
+    ConfigFailed,
+}
+
+#[derive(Clone, Copy)]
+enum PmpSlot {
+    Free,
+    /// Entry holding a region covering `[start, end)`
+    Region { start: u64, end: u64, cfg: u8 },
+    /// Entry whose `pmpaddr` is only the bottom of the TOR region above it
+    TorBase,
+    /// Disabled entry with `L` set, unusable until reset
+    Locked,
+}
+
+/// Book keeping for the 8 PMP entries so that several subsystems
+/// can request protection without overwriting each others entries.
+pub struct PmpManager {
+    slots: [PmpSlot; PMP_ENTRY_COUNT as usize],
+}
+
+impl PmpManager {
+
+    /// Creates a manager from the current hardware state. Entries that
+    /// are already active or locked (e.g. by a boot loader) stay in use.
+    pub fn new() -> PmpManager {
+        let mut m = PmpManager { slots: [PmpSlot::Free; PMP_ENTRY_COUNT as usize] };
+
+        for entry in 0..PMP_ENTRY_COUNT {
+            let cfg = pmp_read_cfg(entry);
+            let (start, end) = pmp_read_bounds(entry);
+
+            if cfg & PMPCFG_A_MASK != 0 {
+                m.slots[entry as usize] = PmpSlot::Region { start, end, cfg };
+                if matches!(pmp_read_addr_match_mode(entry), PmpRegionAddrMatchMode::TopOfRange) && entry > 0
+                    && matches!(m.slots[entry as usize - 1], PmpSlot::Free) {
+                    m.slots[entry as usize - 1] = PmpSlot::TorBase;
                 }
+            } else if cfg & PMPCFG_L != 0 {
+                m.slots[entry as usize] = PmpSlot::Locked;
             }
-            TopOfRange => {
-                unsafe {
-                    asm!("nop");
+        }
+        m
+    }
+
+    /// Finds free entries for `region`, programs it and returns the entry
+    /// index. A TOR region takes two entries unless it starts at 0.
+    ///
+    /// Overlapping an existing region is only allowed when neither
+    /// of them is locked and both grant the same permissions.
+    pub fn allocate(&mut self, region: &mut PmpRegion) -> Result<PmpEntryIndex, PmpError> {
+
+        if matches!(region.amm, PmpRegionAddrMatchMode::PmpDisabled) || region.sz == 0 {
+            return Err(PmpError::InvalidRegion);
+        }
+
+        let start = region.start as u64;
+        let end = start + region.sz as u64;
+        let perms = pmp_region_perms(region);
+
+        for slot in self.slots.iter() {
+            if let PmpSlot::Region { start: s, end: e, cfg } = *slot {
+                let overlaps = start < e && s < end;
+                let compatible = !region.l && cfg & PMPCFG_L == 0
+                    && cfg & (PMPCFG_R | PMPCFG_W | PMPCFG_X) == perms;
+                if overlaps && !compatible {
+                    return Err(PmpError::Overlap);
                 }
             }
-            NatAlignedFourByte => {
-                unsafe {
-                    asm!("nop");
+        }
+
+        let entry = match region.amm {
+            PmpRegionAddrMatchMode::TopOfRange if region.start == 0 && self.is_free(0) => 0,
+            PmpRegionAddrMatchMode::TopOfRange => {
+                match (1..PMP_ENTRY_COUNT).find(|i| self.is_free(i - 1) && self.is_free(*i)) {
+                    Some(i) => i,
+                    None => return Err(PmpError::NoFreeEntry),
                 }
             }
-            NatAlignedPwrOfTwo => {
-                unsafe {
-                    asm!("nop");
+            _ => {
+                match (0..PMP_ENTRY_COUNT).find(|i| self.is_free(*i)) {
+                    Some(i) => i,
+                    None => return Err(PmpError::NoFreeEntry),
                 }
             }
+        };
+
+        region.pmp_configure_region(entry);
+        if !matches!(region.status, PmpRegionState::Active) {
+            return Err(PmpError::ConfigFailed);
+        }
+
+        if matches!(region.amm, PmpRegionAddrMatchMode::TopOfRange) && entry > 0 {
+            self.slots[entry as usize - 1] = PmpSlot::TorBase;
+        }
+        self.slots[entry as usize] = PmpSlot::Region { start, end, cfg: pmp_read_cfg(entry) };
+
+        Ok(entry)
+    }
+
+    /// Disables the region at `entry` and releases its entries.
+    pub fn free(&mut self, entry: PmpEntryIndex) -> Result<(), PmpError> {
+
+        if entry >= PMP_ENTRY_COUNT {
+            return Err(PmpError::EntryNotAllocated);
+        }
+        if !matches!(self.slots[entry as usize], PmpSlot::Region { .. }) {
+            return Err(PmpError::EntryNotAllocated);
+        }
+        if pmp_entry_is_locked(entry) {
+            return Err(PmpError::EntryLocked);
+        }
+
+        let is_tor = matches!(pmp_read_addr_match_mode(entry), PmpRegionAddrMatchMode::TopOfRange);
+
+        pmp_write_cfg(entry, PMPCFG_A_OFF << PMPCFG_A_SHIFT);
+        self.slots[entry as usize] = PmpSlot::Free;
+
+        if is_tor && entry > 0 && matches!(self.slots[entry as usize - 1], PmpSlot::TorBase) {
+            self.slots[entry as usize - 1] = PmpSlot::Free;
+        }
+        Ok(())
+    }
+
+    /// Returns true when `entry` is neither allocated nor locked.
+    pub fn is_free(&self, entry: PmpEntryIndex) -> bool {
+        entry < PMP_ENTRY_COUNT && matches!(self.slots[entry as usize], PmpSlot::Free)
+    }
+
+    /// Number of entries not yet allocated.
+    pub fn free_count(&self) -> u8 {
+        self.slots.iter().filter(|s| matches!(s, PmpSlot::Free)).count() as u8
+    }
+
+    /// Reads back the region programmed at `entry` from the CSRs, for debugging.
+    pub fn read_region(&self, entry: PmpEntryIndex) -> Result<PmpRegion, PmpError> {
+        pmp_read_region(entry)
+    }
+}
+
+impl Default for PmpManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decodes PMP `entry` from `pmpcfgN`/`pmpaddrN` into a `PmpRegion`.
+/// Sizes that do not fit in 32 bits are reported as `u32::MAX`.
+pub fn pmp_read_region(entry: PmpEntryIndex) -> Result<PmpRegion, PmpError> {
+    if entry >= PMP_ENTRY_COUNT {
+        return Err(PmpError::InvalidEntry);
+    }
+    let cfg = pmp_read_cfg(entry);
+    let (start, end) = pmp_read_bounds(entry);
+    let amm = pmp_read_addr_match_mode(entry);
+    let status = match amm {
+        PmpRegionAddrMatchMode::PmpDisabled => PmpRegionState::Disabled,
+        _ => PmpRegionState::Active,
+    };
+
+    Ok(PmpRegion {
+        start: start as u32,
+        sz: core::cmp::min(end.saturating_sub(start), u32::MAX as u64) as u32,
+        amm,
+        r: cfg & PMPCFG_R != 0,
+        w: cfg & PMPCFG_W != 0,
+        x: cfg & PMPCFG_X != 0,
+        l: cfg & PMPCFG_L != 0,
+        status,
+    })
+}
+
+/// Returns `[start, end)` byte addresses matched by PMP `entry`.
+fn pmp_read_bounds(entry: PmpEntryIndex) -> (u64, u64) {
+    let a = (pmp_read_cfg(entry) & PMPCFG_A_MASK) >> PMPCFG_A_SHIFT;
+    let below = if entry == 0 { 0 } else { pmp_read_addr(entry - 1) };
+    pmp_decode_bounds(a, pmp_read_addr(entry), below)
+}
+
+fn pmp_region_perms(region: &PmpRegion) -> u8 {
+    let mut perms = 0;
+    if region.r { perms |= PMPCFG_R; }
+    if region.w { perms |= PMPCFG_W; }
+    if region.x { perms |= PMPCFG_X; }
+    perms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enc(amm: PmpRegionAddrMatchMode, start: u32, sz: u32, entry: PmpEntryIndex) -> Option<PmpEncoding> {
+        pmp_encode(&amm, start, sz, entry)
+    }
+
+    #[test]
+    fn tor_writes_bottom_to_entry_below() {
+        let e = enc(PmpRegionAddrMatchMode::TopOfRange, 0x8000_0000, 0x1000, 2).unwrap();
+        assert_eq!(e, PmpEncoding { a: PMPCFG_A_TOR, addr: 0x2000_0400, base: Some(0x2000_0000) });
+        assert_eq!(pmp_decode_bounds(e.a, e.addr, e.base.unwrap()), (0x8000_0000, 0x8000_1000));
+
+        // Entry 0 starts at address 0, up to the top of memory
+        let e = enc(PmpRegionAddrMatchMode::TopOfRange, 0, 0x100, 0).unwrap();
+        assert_eq!((e.addr, e.base), (0x40, None));
+        let e = enc(PmpRegionAddrMatchMode::TopOfRange, 0xFFFF_F000, 0x1000, 1).unwrap();
+        assert_eq!(pmp_decode_bounds(e.a, e.addr, e.base.unwrap()), (0xFFFF_F000, 0x1_0000_0000));
+
+        assert_eq!(enc(PmpRegionAddrMatchMode::TopOfRange, 0x100, 0x100, 0), None);
+        assert_eq!(enc(PmpRegionAddrMatchMode::TopOfRange, 0x102, 0x100, 1), None);
+        assert_eq!(enc(PmpRegionAddrMatchMode::TopOfRange, 0x100, 0x101, 1), None);
+        assert_eq!(enc(PmpRegionAddrMatchMode::TopOfRange, 0x100, 0, 1), None);
+    }
+
+    #[test]
+    fn na4_covers_one_word() {
+        let e = enc(PmpRegionAddrMatchMode::NatAlignedFourByte, 0x1000_0000, 4, 3).unwrap();
+        assert_eq!(e, PmpEncoding { a: PMPCFG_A_NA4, addr: 0x0400_0000, base: None });
+        assert_eq!(pmp_decode_bounds(e.a, e.addr, 0), (0x1000_0000, 0x1000_0004));
+
+        assert_eq!(enc(PmpRegionAddrMatchMode::NatAlignedFourByte, 0x1000_0000, 8, 3), None);
+        assert_eq!(enc(PmpRegionAddrMatchMode::NatAlignedFourByte, 0x1000_0002, 4, 3), None);
+    }
+
+    #[test]
+    fn napot_size_is_in_trailing_ones() {
+        let e = enc(PmpRegionAddrMatchMode::NatAlignedPwrOfTwo, 0x8000_0000, 0x4000, 0).unwrap();
+        assert_eq!(e, PmpEncoding { a: PMPCFG_A_NAPOT, addr: 0x2000_07FF, base: None });
+        assert_eq!(pmp_decode_bounds(e.a, e.addr, 0), (0x8000_0000, 0x8000_4000));
+
+        let e = enc(PmpRegionAddrMatchMode::NatAlignedPwrOfTwo, 0x1000, 8, 0).unwrap();
+        assert_eq!(e.addr, 0x400);
+        assert_eq!(pmp_decode_bounds(e.a, e.addr, 0), (0x1000, 0x1008));
+
+        assert_eq!(enc(PmpRegionAddrMatchMode::NatAlignedPwrOfTwo, 0x8000_1000, 0x4000, 0), None);
+        assert_eq!(enc(PmpRegionAddrMatchMode::NatAlignedPwrOfTwo, 0x1000, 12, 0), None);
+        assert_eq!(enc(PmpRegionAddrMatchMode::NatAlignedPwrOfTwo, 0x1000, 4, 0), None);
+    }
+
+    fn region(amm: PmpRegionAddrMatchMode, start: u32, sz: u32, perms: &str, l: bool) -> PmpRegion {
+        PmpRegion {
+            start, sz, amm,
+            r: perms.contains('r'),
+            w: perms.contains('w'),
+            x: perms.contains('x'),
+            l,
+            status: PmpRegionState::Created,
         }
     }
-}
\ No newline at end of file
+
+    fn na4(start: u32, perms: &str) -> PmpRegion {
+        region(PmpRegionAddrMatchMode::NatAlignedFourByte, start, 4, perms, false)
+    }
+
+    #[test]
+    fn tor_region_takes_two_entries() {
+        let mut m = PmpManager::new();
+        let mut r = region(PmpRegionAddrMatchMode::TopOfRange, 0x8000_0000, 0x1000, "rw", false);
+        assert_eq!(m.allocate(&mut r).unwrap(), 1);
+        assert!(!m.is_free(0) && !m.is_free(1));
+        assert_eq!(m.free_count(), 6);
+        assert_eq!((pmp_read_addr(0), pmp_read_addr(1)), (0x2000_0000, 0x2000_0400));
+
+        let back = m.read_region(1).unwrap();
+        assert_eq!((back.start, back.sz, back.r, back.w, back.x), (0x8000_0000, 0x1000, true, true, false));
+
+        m.free(1).unwrap();
+        assert_eq!(m.free_count(), PMP_ENTRY_COUNT);
+        assert!(matches!(pmp_read_addr_match_mode(1), PmpRegionAddrMatchMode::PmpDisabled));
+        assert!(matches!(m.free(1), Err(PmpError::EntryNotAllocated)));
+    }
+
+    #[test]
+    fn runs_out_of_entries() {
+        let mut m = PmpManager::new();
+        for i in 0..PMP_ENTRY_COUNT as u32 {
+            assert_eq!(m.allocate(&mut na4(0x1000 + i * 4, "r")).unwrap(), i as u8);
+        }
+        assert_eq!(m.free_count(), 0);
+        assert!(matches!(m.allocate(&mut na4(0x2000, "r")), Err(PmpError::NoFreeEntry)));
+
+        // A TOR region needs a second free entry below it
+        m.free(7).unwrap();
+        let mut r = region(PmpRegionAddrMatchMode::TopOfRange, 0x3000, 0x100, "r", false);
+        assert!(matches!(m.allocate(&mut r), Err(PmpError::NoFreeEntry)));
+    }
+
+    #[test]
+    fn overlaps_need_same_perms_and_no_lock() {
+        let mut m = PmpManager::new();
+        let mut ram = region(PmpRegionAddrMatchMode::NatAlignedPwrOfTwo, 0x8000_0000, 0x4000, "rw", false);
+        m.allocate(&mut ram).unwrap();
+        assert!(matches!(m.allocate(&mut na4(0x8000_0100, "r")), Err(PmpError::Overlap)));
+        assert!(m.allocate(&mut na4(0x8000_0100, "rw")).is_ok());
+
+        let mut boot = region(PmpRegionAddrMatchMode::NatAlignedPwrOfTwo, 0x2000_0000, 0x1000, "rx", true);
+        m.allocate(&mut boot).unwrap();
+        assert!(matches!(m.allocate(&mut na4(0x2000_0100, "rx")), Err(PmpError::Overlap)));
+        let mut locked = region(PmpRegionAddrMatchMode::NatAlignedFourByte, 0x8000_0200, 4, "rw", true);
+        assert!(matches!(m.allocate(&mut locked), Err(PmpError::Overlap)));
+        assert_eq!(m.free_count(), PMP_ENTRY_COUNT - 3);
+    }
+
+    #[test]
+    fn entry_locked_before_new_is_never_used() {
+        pmp_write_cfg(2, PMPCFG_L);
+        let mut m = PmpManager::new();
+        assert!(!m.is_free(2));
+        assert_eq!(m.free_count(), PMP_ENTRY_COUNT - 1);
+
+        let mut entries = std::vec::Vec::new();
+        while let Ok(entry) = m.allocate(&mut na4(0x1000 + entries.len() as u32 * 4, "r")) {
+            entries.push(entry);
+        }
+        assert_eq!(entries, [0, 1, 3, 4, 5, 6, 7]);
+        assert!(matches!(m.free(2), Err(PmpError::EntryNotAllocated)));
+        assert_eq!(pmp_read_cfg(2), PMPCFG_L);
+    }
+
+    #[test]
+    fn disabled_regions_do_not_encode() {
+        assert_eq!(enc(PmpRegionAddrMatchMode::PmpDisabled, 0x1000, 0x1000, 0), None);
+        assert_eq!(pmp_decode_bounds(PMPCFG_A_OFF, 0x2000_07FF, 0), (0, 0));
+    }
+}
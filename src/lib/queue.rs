@@ -0,0 +1,215 @@
+//! # Single Producer Single Consumer Queue
+//!
+//! Fixed capacity ring buffer for handing data from an interrupt
+//! handler to the main loop, or back, without a critical section. The
+//! producer only writes `tail` and the consumer only writes `head`, so
+//! both ends can run concurrently; neither ever blocks.
+//!
+//! ```ignore
+//! static RX: Queue<u8, 64> = Queue::new();
+//!
+//! let (mut tx, mut rx) = RX.split().unwrap();
+//! // move `tx` to the UART ISR, poll `rx.dequeue()` in the main loop
+//! ```
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Ring buffer of `N` elements, `N` must be a power of two.
+pub struct Queue<T, const N: usize> {
+    buf: [UnsafeCell<MaybeUninit<T>>; N],
+    /// Free running count of dequeued elements
+    head: AtomicUsize,
+    /// Free running count of enqueued elements
+    tail: AtomicUsize,
+    split: AtomicBool,
+}
+
+// Each slot is accessed by one end at a time, handed over through head/tail
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T, const N: usize> Queue<T, N> {
+    const CAPACITY_OK: () = assert!(N.is_power_of_two(), "Queue capacity must be a power of two");
+
+    pub const fn new() -> Queue<T, N> {
+        let () = Self::CAPACITY_OK;
+        Queue {
+            buf: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            split: AtomicBool::new(false),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the two ends of the queue, `None` when it was split before.
+    pub fn split(&self) -> Option<(Producer<'_, T, N>, Consumer<'_, T, N>)> {
+        if self.split.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        Some((Producer { q: self, _0: PhantomData }, Consumer { q: self, _0: PhantomData }))
+    }
+}
+
+impl<T, const N: usize> Default for Queue<T, N> {
+    fn default() -> Queue<T, N> {
+        Queue::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            unsafe { self.buf[head % N].get_mut().assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// Writing end of a `Queue`.
+pub struct Producer<'a, T, const N: usize> {
+    q: &'a Queue<T, N>,
+    // Not Sync, only one context may hold the producer
+    _0: PhantomData<*const ()>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Producer<'_, T, N> {}
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    /// Appends `value`, or gives it back when the queue is full.
+    pub fn enqueue(&mut self, value: T) -> Result<(), T> {
+        let tail = self.q.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.q.head.load(Ordering::Acquire)) == N {
+            return Err(value);
+        }
+        unsafe { (*self.q.buf[tail % N].get()).write(value) };
+        self.q.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.q.len() == N
+    }
+}
+
+/// Reading end of a `Queue`.
+pub struct Consumer<'a, T, const N: usize> {
+    q: &'a Queue<T, N>,
+    _0: PhantomData<*const ()>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Consumer<'_, T, N> {}
+
+impl<T, const N: usize> Consumer<'_, T, N> {
+    /// Removes the oldest element.
+    pub fn dequeue(&mut self) -> Option<T> {
+        let head = self.q.head.load(Ordering::Relaxed);
+        if head == self.q.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*self.q.buf[head % N].get()).assume_init_read() };
+        self.q.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.q.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.q.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::rc::Rc;
+    use std::thread;
+
+    #[test]
+    fn fifo_order_and_full() {
+        let q: Queue<u8, 4> = Queue::new();
+        let (mut tx, mut rx) = q.split().unwrap();
+        assert!(q.split().is_none());
+
+        for b in 1..=4 {
+            tx.enqueue(b).unwrap();
+        }
+        assert!(tx.is_full());
+        assert_eq!(tx.enqueue(5), Err(5));
+
+        assert_eq!(rx.dequeue(), Some(1));
+        tx.enqueue(5).unwrap();
+        assert_eq!((2..=5).map(|_| rx.dequeue().unwrap()).collect::<std::vec::Vec<_>>(), [2, 3, 4, 5]);
+        assert_eq!(rx.dequeue(), None);
+    }
+
+    #[test]
+    fn indices_wrap() {
+        let q: Queue<u32, 2> = Queue::new();
+        q.head.store(usize::MAX, Ordering::Relaxed);
+        q.tail.store(usize::MAX, Ordering::Relaxed);
+        let (mut tx, mut rx) = q.split().unwrap();
+
+        tx.enqueue(1).unwrap();
+        tx.enqueue(2).unwrap();
+        assert_eq!(tx.enqueue(3), Err(3));
+        assert_eq!((rx.dequeue(), rx.dequeue(), rx.dequeue()), (Some(1), Some(2), None));
+    }
+
+    #[test]
+    fn drops_remaining_elements() {
+        let v = Rc::new(());
+        {
+            let q: Queue<Rc<()>, 4> = Queue::new();
+            let (mut tx, _rx) = q.split().unwrap();
+            tx.enqueue(v.clone()).unwrap();
+            tx.enqueue(v.clone()).unwrap();
+            assert_eq!(Rc::strong_count(&v), 3);
+        }
+        assert_eq!(Rc::strong_count(&v), 1);
+    }
+
+    #[test]
+    fn concurrent_producer_and_consumer() {
+        static Q: Queue<u32, 8> = Queue::new();
+        let (mut tx, mut rx) = Q.split().unwrap();
+
+        let producer = thread::spawn(move || {
+            for i in 0..1000 {
+                while tx.enqueue(i).is_err() {
+                    thread::yield_now();
+                }
+            }
+        });
+        for i in 0..1000 {
+            let v = loop {
+                if let Some(v) = rx.dequeue() {
+                    break v;
+                }
+                thread::yield_now();
+            };
+            assert_eq!(v, i);
+        }
+        producer.join().unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+//! # Runtime Entry
+//!
+//! `_start` is placed in `.entry` and is the first code run after the
+//! boot ROM jumps to the image. It jumps over the image header, see
+//! `image`, then before any Rust code runs it sets `sp`
+//! to `_stack_start`, copies `.data` from its load address `_sidata`
+//! and zeroes `.bss`, using the symbols of the linker script generated
+//! by `build.rs`. It then calls the application `main`, which must be
+//! `#[no_mangle] extern "C" fn main() -> !`.
+
+use core::arch::global_asm;
+
+use crate::image::{IMAGE_MAGIC, IMAGE_VERSION};
+
+global_asm!(r#"
+    .section .entry, "ax"
+    .global _start
+_start:
+    .option push
+    .option norvc
+    j 6f                    # 4 bytes, the header follows
+    .option pop
+
+    .section .image_header, "a"
+    .align 2
+    .global _image_header
+_image_header:
+    .word {magic}
+    .word {version}
+    .word _image_len
+    .word _start
+    .word 0                 # checksum, written by cargo image-stamp
+
+    .section .entry.rt, "ax"
+6:
+    la sp, _stack_start
+
+    la t0, _sidata
+    la t1, _sdata
+    la t2, _edata
+1:
+    bgeu t1, t2, 2f
+    lw t3, 0(t0)
+    sw t3, 0(t1)
+    addi t0, t0, 4
+    addi t1, t1, 4
+    j 1b
+2:
+    la t1, _sbss
+    la t2, _ebss
+3:
+    bgeu t1, t2, 4f
+    sw zero, 0(t1)
+    addi t1, t1, 4
+    j 3b
+4:
+    call main
+5:
+    j 5b
+"#,
+    magic = const IMAGE_MAGIC,
+    version = const IMAGE_VERSION,
+);
@@ -0,0 +1,156 @@
+//! # RISC-V Semihosting
+//!
+//! Console output, clock and exit through the debug host (OpenOCD with
+//! `arm semihosting enable`, or QEMU with `-semihosting`). No peripheral
+//! needs to be configured.
+//!
+//! A call is the uncompressed sequence below, with the operation in `a0`
+//! and a parameter in `a1`. The result is returned in `a0`.
+//!
+//! ```text
+//! slli x0, x0, 0x1f
+//! ebreak
+//! srai x0, x0, 7
+//! ```
+//!
+//! Without a debug host the `ebreak` traps to M-mode. `m_trap_handler`
+//! recognises the sequence and returns `SEMIHOSTING_NO_HOST` instead of
+//! treating it as a breakpoint fault.
+
+use core::arch::asm;
+use core::fmt;
+use core::ptr;
+use core::ptr::addr_of;
+
+use crate::flash::{FLASH_BASE, FLASH_SIZE};
+use crate::interrupt::{TrapFrame, REG_A0};
+
+extern "C" {
+    static _ram_start: u8;
+    static _ram_end: u8;
+}
+
+pub const SYS_WRITEC: u32 = 0x03;
+pub const SYS_WRITE0: u32 = 0x04;
+pub const SYS_CLOCK: u32 = 0x10;
+pub const SYS_EXIT: u32 = 0x18;
+pub const SYS_EXIT_EXTENDED: u32 = 0x20;
+
+/// `SYS_EXIT` reason for a normal application exit
+const ADP_STOPPED_APPLICATION_EXIT: u32 = 0x20026;
+
+/// Returned by every call when no debug host handled it.
+pub const SEMIHOSTING_NO_HOST: u32 = u32::MAX;
+
+const SLLI_X0_X0_0X1F: u32 = 0x01f0_1013;
+const SRAI_X0_X0_7: u32 = 0x4070_5013;
+
+/// Issues semihosting operation `op` with parameter `param`.
+#[inline(never)]
+pub fn semihosting_call(op: u32, param: u32) -> u32 {
+    let ret: u32;
+    unsafe {
+        asm!(
+            ".option push",
+            ".option norvc",
+            "slli x0, x0, 0x1f",
+            "ebreak",
+            "srai x0, x0, 7",
+            ".option pop",
+            inlateout("a0") op => ret,
+            in("a1") param,
+        );
+    }
+    ret
+}
+
+/// Writes a single byte to the host console.
+pub fn semihosting_write_char(c: u8) {
+    semihosting_call(SYS_WRITEC, &c as *const u8 as u32);
+}
+
+/// Writes `s` to the host console. `SYS_WRITE0` needs NUL terminated
+/// strings, so `s` is sent in chunks through a stack buffer.
+pub fn semihosting_write_str(s: &str) {
+    let mut buf = [0u8; 65];
+
+    for chunk in s.as_bytes().chunks(buf.len() - 1) {
+        buf[..chunk.len()].copy_from_slice(chunk);
+        buf[chunk.len()] = 0;
+        semihosting_call(SYS_WRITE0, buf.as_ptr() as u32);
+    }
+}
+
+/// Centiseconds since the program started, `None` without a host.
+pub fn semihosting_clock() -> Option<u32> {
+    match semihosting_call(SYS_CLOCK, 0) {
+        SEMIHOSTING_NO_HOST => None,
+        cs => Some(cs),
+    }
+}
+
+/// Ends the session with exit status `code`.
+///
+/// On RV32 `SYS_EXIT` only reports success or failure, so the status is
+/// sent with `SYS_EXIT_EXTENDED` and `SYS_EXIT` is the fallback for hosts
+/// that do not support it. Halts when there is no host.
+pub fn semihosting_exit(code: u32) -> ! {
+    let block: [u32; 2] = [ADP_STOPPED_APPLICATION_EXIT, code];
+    semihosting_call(SYS_EXIT_EXTENDED, block.as_ptr() as u32);
+
+    if code == 0 {
+        semihosting_call(SYS_EXIT, ADP_STOPPED_APPLICATION_EXIT);
+    } else {
+        semihosting_call(SYS_EXIT, 0);
+    }
+
+    loop {
+        unsafe { asm!("wfi"); }
+    }
+}
+
+/// `core::fmt::Write` adaptor for `write!` to the host console.
+pub struct SemihostingWriter;
+
+impl fmt::Write for SemihostingWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        semihosting_write_str(s);
+        Ok(())
+    }
+}
+
+/// Called from `m_trap_handler` for breakpoint exceptions. When the
+/// `ebreak` is part of a semihosting call, fails the call and returns
+/// true, otherwise returns false.
+pub(crate) fn semihosting_trap(frame: &mut TrapFrame) -> bool {
+    let epc = frame.mepc;
+
+    // The whole sequence must be in the flash or RAM holding the ebreak
+    let Some((start, end)) = semihosting_memory(epc) else {
+        return false;
+    };
+    if epc - start < 4 || end - epc < 8 {
+        return false;
+    }
+    if read_instr(epc - 4) != SLLI_X0_X0_0X1F || read_instr(epc + 4) != SRAI_X0_X0_7 {
+        return false;
+    }
+
+    frame.regs[REG_A0] = SEMIHOSTING_NO_HOST;
+    frame.mepc = epc + 4;
+    true
+}
+
+/// Bounds of the flash or RAM holding `addr`, `None` elsewhere.
+fn semihosting_memory(addr: u32) -> Option<(u32, u32)> {
+    let ram = (addr_of!(_ram_start) as u32, addr_of!(_ram_end) as u32);
+    [(FLASH_BASE, FLASH_BASE + FLASH_SIZE), ram].into_iter()
+        .find(|&(start, end)| start <= addr && addr < end)
+}
+
+/// Reads a 32 bit instruction that may only be 2 byte aligned.
+fn read_instr(addr: u32) -> u32 {
+    let lo = unsafe { ptr::read_volatile(addr as *const u16) } as u32;
+    let hi = unsafe { ptr::read_volatile((addr + 2) as *const u16) } as u32;
+    lo | (hi << 16)
+}
@@ -1,7 +1,16 @@
 
-#[path = "fe310/gpio.rs"] mod gpio;
 #[path = "fe310/uart.rs"] mod uart;
 
+use core::fmt;
+
+use crate::dio::{DioPin, DioPinNum};
+use crate::error::HalError;
+use crate::mmio::MMIO;
+use crate::peripherals::{Uart0, Uart1, TLCLK_HZ};
+
+/// Tx and Rx GPIO pins of each uart, IOF0
+const UART_PINS: [(DioPinNum, DioPinNum); 2] = [(17, 16), (18, 23)];
+
 pub type UartBaud = u32;
 pub type UartInstance = u8;
 
@@ -11,6 +20,7 @@ pub enum SerialDeviceType {
     I2c,
 }
 
+#[derive(Clone, Copy)]
 pub enum UartBitCount {
     One,
     Two,
@@ -29,49 +39,215 @@ pub struct UartConfig {
 }
 
 pub struct Uart {
-    pub instance: UartInstance,
+    instance: UartInstance,
     pub config: UartConfig,
 }
 
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for crate::peripherals::Uart0 {}
+    impl Sealed for crate::peripherals::Uart1 {}
+}
+
+/// Uart peripheral handles from `Peripherals::take`. Sealed, other
+/// types can not stand in for a handle.
+pub trait UartPeripheral: sealed::Sealed {
+    const INSTANCE: UartInstance;
+}
+
+impl UartPeripheral for Uart0 {
+    const INSTANCE: UartInstance = 0;
+}
+
+impl UartPeripheral for Uart1 {
+    const INSTANCE: UartInstance = 1;
+}
+
+impl Uart {
+    /// Driver of the uart owned by handle `uart`.
+    pub fn new<U: UartPeripheral>(_uart: U, config: UartConfig) -> Uart {
+        Uart { instance: U::INSTANCE, config }
+    }
+
+    pub fn instance(&self) -> UartInstance {
+        self.instance
+    }
+
+    /// Routes the uart to pin `tx` and, when given, pin `rx`. UART0 is on
+    /// GPIO 17 (Tx) and 16 (Rx), UART1 on GPIO 18 (Tx) and 23 (Rx), other
+    /// pins fail with `InvalidPin`. The pins stay with the uart.
+    pub fn mux_pins(&self, tx: DioPin, rx: Option<DioPin>) -> Result<(), HalError> {
+        let (tx_num, rx_num) = UART_PINS[self.instance as usize];
+
+        if tx.instance() != 0 || tx.pin_num() != tx_num {
+            return Err(HalError::InvalidPin);
+        }
+        if rx.as_ref().is_some_and(|p| p.instance() != 0 || p.pin_num() != rx_num) {
+            return Err(HalError::InvalidPin);
+        }
+
+        for p in core::iter::once(tx).chain(rx) {
+            p.mux_to_iof(false)?;
+        }
+        Ok(())
+    }
+}
+
 pub trait Configure {
-    fn configure(&self) {}
+    fn configure(&self) -> Result<(), HalError> { Ok(()) }
 }
 
 pub trait EnableTx {
-    fn enable_tx(&self) {}
+    fn enable_tx(&self) -> Result<(), HalError> { Ok(()) }
 }
 
 pub trait DisableTx {
-    fn disable_tx (&self) {}
+    fn disable_tx (&self) -> Result<(), HalError> { Ok(()) }
 }
 
 pub trait DoSendByte {
-    fn do_send_byte(&self, b: u8) {}
+    fn do_send_byte(&self, b: u8) -> Result<(), HalError>;
+}
+
+pub trait EnableRx {
+    fn enable_rx(&self) -> Result<(), HalError> { Ok(()) }
+}
+
+pub trait DisableRx {
+    fn disable_rx (&self) -> Result<(), HalError> { Ok(()) }
+}
+
+pub trait DoReceiveByte {
+    /// Next received byte, `None` when nothing was received.
+    fn do_receive_byte(&self) -> Result<Option<u8>, HalError> { Ok(None) }
 }
 
 impl Configure for Uart {
-    fn configure(&self){
-        uart::uart_set_baud_divisor((*self).instance, 138);
-        uart::uart_set_stopbits ( (*self).instance, 1);
-        uart::uart_set_tx_fifo_depth( (*self).instance, 7);
+    /// Fails with `Unsupported` for two start bits, the uart always
+    /// sends one.
+    fn configure(&self) -> Result<(), HalError> {
+        let stop_bits = match (self.config.start_bits, self.config.stop_bits) {
+            (UartBitCount::Two, _) => return Err(HalError::Unsupported),
+            (UartBitCount::One, UartBitCount::One) => 1,
+            (UartBitCount::One, UartBitCount::Two) => 2,
+        };
+
+        let div = uart::uart_compute_divisor(TLCLK_HZ, self.config.baud)?;
+        uart::uart_set_baud_divisor(&MMIO, self.instance, div)?;
+        uart::uart_set_stopbits(&MMIO, self.instance, stop_bits)?;
+        uart::uart_set_tx_fifo_depth(&MMIO, self.instance, 7)
     }
 }
 
 
 impl DoSendByte for Uart {
-    fn do_send_byte(&self, b: u8){
-        uart::uart_do_send_byte ( (*self).instance, b);
+    fn do_send_byte(&self, b: u8) -> Result<(), HalError> {
+        uart::uart_do_send_byte(&MMIO, self.instance, b)
+    }
+}
+
+/// `write!` to the uart, a send error ends the write.
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().try_for_each(|b| self.do_send_byte(b).map_err(|_| fmt::Error))
     }
 }
 
 impl EnableTx for Uart {
-    fn enable_tx (&self){
-        uart::uart_enable_tx ( (*self).instance);
+    fn enable_tx (&self) -> Result<(), HalError> {
+        uart::uart_enable_tx(&MMIO, self.instance)
     }
 }
 
 impl DisableTx for Uart {
-    fn disable_tx (&self){
-        uart::uart_disable_tx ( (*self).instance);
+    fn disable_tx (&self) -> Result<(), HalError> {
+        uart::uart_disable_tx(&MMIO, self.instance)
     }
-}
\ No newline at end of file
+}
+
+impl EnableRx for Uart {
+    fn enable_rx (&self) -> Result<(), HalError> {
+        uart::uart_enable_rx(&MMIO, self.instance)
+    }
+}
+
+impl DisableRx for Uart {
+    fn disable_rx (&self) -> Result<(), HalError> {
+        uart::uart_disable_rx(&MMIO, self.instance)
+    }
+}
+
+impl DoReceiveByte for Uart {
+    fn do_receive_byte(&self) -> Result<Option<u8>, HalError> {
+        uart::uart_try_receive_byte(&MMIO, self.instance)
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+pub use self::rx_async::UartRead;
+#[cfg(target_arch = "riscv32")]
+pub(crate) use self::rx_async::uart_interrupt;
+
+#[cfg(target_arch = "riscv32")]
+mod rx_async {
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+
+    use super::{uart, Uart, UartInstance};
+    use crate::executor::WakerSlot;
+    use crate::interrupt::{self, MIE_MEIE};
+    use crate::mmio::MMIO;
+    use crate::plic::{self, PlicIntrPriorityLevels, PlicIntrSources};
+
+    static RX_WAKERS: [WakerSlot; 2] = [WakerSlot::new(), WakerSlot::new()];
+
+    impl Uart {
+        /// Waits for the next received byte. Rx must be enabled.
+        pub fn read(&self) -> UartRead {
+            UartRead { instance: self.instance }
+        }
+    }
+
+    /// Future returned by `Uart::read`.
+    pub struct UartRead {
+        instance: UartInstance,
+    }
+
+    impl Future for UartRead {
+        type Output = u8;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u8> {
+            // Instances come from `Uart`, they are valid
+            if let Ok(Some(b)) = uart::uart_try_receive_byte(&MMIO, self.instance) {
+                return Poll::Ready(b);
+            }
+
+            RX_WAKERS[self.instance as usize].register(cx.waker());
+
+            // Interrupt as soon as the Rx FIFO is not empty
+            let src = if self.instance == 0 { PlicIntrSources::uart0 } else { PlicIntrSources::uart1 };
+            plic::plic_set_intr_priority_for_src(&MMIO, src, PlicIntrPriorityLevels::level1_highest);
+            plic::plic_enable_src_to_interrupt(&MMIO, src);
+            let _ = uart::uart_set_rx_watermark(&MMIO, self.instance, 0);
+            let _ = uart::uart_enable_intr(&MMIO, self.instance, uart::UART_INTR_RXWM);
+            interrupt::mie_enable(MIE_MEIE);
+
+            Poll::Pending
+        }
+    }
+
+    /// PLIC interrupt of uart `instance`, returns the pending conditions.
+    /// The Rx watermark interrupt stays pending until the FIFO is read,
+    /// so it is disabled here and enabled again by the next poll.
+    pub(crate) fn uart_interrupt(instance: UartInstance) -> u32 {
+        let ip = uart::uart_pending(&MMIO, instance).unwrap_or(0);
+
+        if ip & uart::UART_INTR_RXWM != 0 {
+            let _ = uart::uart_disable_intr(&MMIO, instance, uart::UART_INTR_RXWM);
+            RX_WAKERS[instance as usize].wake();
+        }
+        ip
+    }
+}
@@ -0,0 +1,130 @@
+//! # SPI Master
+//!
+//! ```ignore
+//! let spi = Spi::new(p.spi1, SpiConfig { sck_hz: 1_000_000, mode: SpiMode::Mode0, cs: 0 });
+//! spi.mux_pins(pins.pin5, pins.pin3, None, Some(pins.pin2))?;
+//! spi.configure()?;
+//! spi.write(&[0x55])?;
+//! ```
+
+#[path = "fe310/spi.rs"] mod spi_master;
+
+use crate::dio::{DioPin, DioPinNum};
+use crate::error::HalError;
+use crate::mmio::MMIO;
+use crate::peripherals::{Spi1, Spi2, TLCLK_HZ};
+
+pub type SpiInstance = u8;
+
+/// SCK, MOSI, MISO and CS0 - CS3 GPIO pins of SPI1, IOF0
+const SPI1_PINS: (DioPinNum, DioPinNum, DioPinNum, [DioPinNum; 4]) = (5, 3, 4, [2, 8, 9, 10]);
+
+/// Clock polarity and phase, `Mode0` samples on the rising edge of an
+/// idle low SCK.
+#[derive(Clone, Copy)]
+pub enum SpiMode {
+    Mode0,
+    Mode1,
+    Mode2,
+    Mode3,
+}
+
+pub struct SpiConfig {
+    /// Highest SCK frequency of the device
+    pub sck_hz: u32,
+    pub mode: SpiMode,
+    /// Chip select line, 0 - 3
+    pub cs: u8,
+}
+
+/// Transfers with the device selected on an SPI bus. CS is asserted
+/// for the duration of each call.
+pub trait SpiBus: Sync {
+    fn write(&self, bytes: &[u8]) -> Result<(), HalError>;
+    /// Sends `buf` and replaces it with the received bytes.
+    fn transfer(&self, buf: &mut [u8]) -> Result<(), HalError>;
+}
+
+pub struct Spi {
+    instance: SpiInstance,
+    pub config: SpiConfig,
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for crate::peripherals::Spi1 {}
+    impl Sealed for crate::peripherals::Spi2 {}
+}
+
+/// SPI peripheral handles from `Peripherals::take`. Sealed, other
+/// types can not stand in for a handle.
+pub trait SpiPeripheral: sealed::Sealed {
+    const INSTANCE: SpiInstance;
+}
+
+impl SpiPeripheral for Spi1 {
+    const INSTANCE: SpiInstance = 1;
+}
+
+impl SpiPeripheral for Spi2 {
+    const INSTANCE: SpiInstance = 2;
+}
+
+impl Spi {
+    pub fn new<S: SpiPeripheral>(_spi: S, config: SpiConfig) -> Spi {
+        Spi { instance: S::INSTANCE, config }
+    }
+
+    pub fn instance(&self) -> SpiInstance {
+        self.instance
+    }
+
+    /// Routes SPI1 to GPIO 5 (SCK), 3 (MOSI), 4 (MISO) and the pin of
+    /// `config.cs`: GPIO 2, 8, 9 or 10. SPI2 has no pins on the FE310-G002.
+    pub fn mux_pins(&self, sck: DioPin, mosi: DioPin, miso: Option<DioPin>, cs: Option<DioPin>) -> Result<(), HalError> {
+        if self.instance != 1 {
+            return Err(HalError::Unsupported);
+        }
+        let (sck_num, mosi_num, miso_num, cs_nums) = SPI1_PINS;
+        let cs_num = *cs_nums.get(self.config.cs as usize).ok_or(HalError::InvalidPin)?;
+
+        let expected = [(Some(&sck), sck_num), (Some(&mosi), mosi_num), (miso.as_ref(), miso_num), (cs.as_ref(), cs_num)];
+        for (p, n) in expected {
+            if p.is_some_and(|p| p.instance() != 0 || p.pin_num() != n) {
+                return Err(HalError::InvalidPin);
+            }
+        }
+
+        for p in [Some(sck), Some(mosi), miso, cs].into_iter().flatten() {
+            p.mux_to_iof(false)?;
+        }
+        Ok(())
+    }
+
+    pub fn configure(&self) -> Result<(), HalError> {
+        let (pol, pha) = match self.config.mode {
+            SpiMode::Mode0 => (false, false),
+            SpiMode::Mode1 => (false, true),
+            SpiMode::Mode2 => (true, false),
+            SpiMode::Mode3 => (true, true),
+        };
+        if self.config.cs > 3 {
+            return Err(HalError::Unsupported);
+        }
+
+        let div = spi_master::spi_compute_divisor(TLCLK_HZ, self.config.sck_hz)?;
+        spi_master::spi_configure(&MMIO, self.instance, div, pol, pha)?;
+        spi_master::spi_set_csid(&MMIO, self.instance, self.config.cs)
+    }
+}
+
+impl SpiBus for Spi {
+    fn write(&self, bytes: &[u8]) -> Result<(), HalError> {
+        spi_master::spi_write(&MMIO, self.instance, bytes)
+    }
+
+    fn transfer(&self, buf: &mut [u8]) -> Result<(), HalError> {
+        spi_master::spi_transfer(&MMIO, self.instance, buf)
+    }
+}
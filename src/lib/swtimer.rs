@@ -0,0 +1,237 @@
+//! # Software Timers
+//!
+//! Any number of one-shot and periodic timers share the single
+//! `mtimecmp` of the hart. Each `SoftTimer` is a static node of an
+//! intrusive list sorted by deadline; `timer` programs `mtimecmp` with
+//! the head of the list and calls the callbacks of expired timers from
+//! the machine timer interrupt.
+//!
+//! ```ignore
+//! static BLINK: SoftTimer = SoftTimer::new(blink);
+//!
+//! fn blink(_: &'static SoftTimer) { led.toggle(); }
+//!
+//! BLINK.start_periodic_ms(500);
+//! ```
+//!
+//! Callbacks run in the interrupt handler and may start or stop timers,
+//! including their own.
+
+use core::cell::Cell;
+
+use crate::sync::{CriticalSection, Mutex};
+
+pub type SoftTimerCallback = fn(&'static SoftTimer);
+
+pub struct SoftTimer {
+    callback: SoftTimerCallback,
+    node: Mutex<Cell<TimerNode>>,
+}
+
+#[derive(Clone, Copy)]
+struct TimerNode {
+    deadline: u64,
+    /// 0 for one-shot timers
+    period: u64,
+    active: bool,
+    next: Option<&'static SoftTimer>,
+}
+
+impl SoftTimer {
+    pub const fn new(callback: SoftTimerCallback) -> SoftTimer {
+        SoftTimer {
+            callback,
+            node: Mutex::new(Cell::new(TimerNode { deadline: 0, period: 0, active: false, next: None })),
+        }
+    }
+
+    pub fn callback(&self) -> SoftTimerCallback {
+        self.callback
+    }
+
+    /// True while the timer is in a list.
+    pub fn is_active(&self, cs: CriticalSection) -> bool {
+        self.get(cs).active
+    }
+
+    /// Next expiry of an active timer.
+    pub fn deadline(&self, cs: CriticalSection) -> u64 {
+        self.get(cs).deadline
+    }
+
+    fn get(&self, cs: CriticalSection) -> TimerNode {
+        self.node.borrow(cs).get()
+    }
+
+    fn set(&self, cs: CriticalSection, n: TimerNode) {
+        self.node.borrow(cs).set(n)
+    }
+}
+
+/// Active timers sorted by deadline, timers with equal deadlines in the
+/// order they were inserted.
+pub struct TimerList {
+    head: Mutex<Cell<Option<&'static SoftTimer>>>,
+}
+
+impl TimerList {
+    pub const fn new() -> TimerList {
+        TimerList { head: Mutex::new(Cell::new(None)) }
+    }
+
+    /// Arms `t` to expire at `deadline`, then every `period` ticks if
+    /// `period` is not 0. An active timer is rearmed.
+    pub fn insert(&self, cs: CriticalSection, t: &'static SoftTimer, deadline: u64, period: u64) {
+        self.remove(cs, t);
+
+        let mut prev: Option<&'static SoftTimer> = None;
+        let mut cur = self.head.borrow(cs).get();
+        while let Some(c) = cur {
+            if c.get(cs).deadline > deadline {
+                break;
+            }
+            prev = cur;
+            cur = c.get(cs).next;
+        }
+
+        t.set(cs, TimerNode { deadline, period, active: true, next: cur });
+        self.link(cs, prev, Some(t));
+    }
+
+    /// Disarms `t`, returns false when it was not active.
+    pub fn remove(&self, cs: CriticalSection, t: &'static SoftTimer) -> bool {
+        if !t.is_active(cs) {
+            return false;
+        }
+
+        let mut prev: Option<&'static SoftTimer> = None;
+        let mut cur = self.head.borrow(cs).get();
+        while let Some(c) = cur {
+            if core::ptr::eq(c, t) {
+                let n = t.get(cs);
+                self.link(cs, prev, n.next);
+                t.set(cs, TimerNode { active: false, next: None, ..n });
+                return true;
+            }
+            prev = cur;
+            cur = c.get(cs).next;
+        }
+        false
+    }
+
+    /// Deadline of the first timer to expire.
+    pub fn next_deadline(&self, cs: CriticalSection) -> Option<u64> {
+        self.head.borrow(cs).get().map(|t| t.deadline(cs))
+    }
+
+    /// Removes the first timer expired at `now`. A periodic timer is put
+    /// back at its next deadline after `now`, skipping missed periods.
+    /// The caller runs the callback.
+    pub fn pop_expired(&self, cs: CriticalSection, now: u64) -> Option<&'static SoftTimer> {
+        let t = self.head.borrow(cs).get()?;
+        let n = t.get(cs);
+        if n.deadline > now {
+            return None;
+        }
+
+        self.remove(cs, t);
+        // Periodic timers have a non zero period
+        if let Some(missed) = (now - n.deadline).checked_div(n.period) {
+            self.insert(cs, t, n.deadline + (missed + 1) * n.period, n.period);
+        }
+        Some(t)
+    }
+
+    fn link(&self, cs: CriticalSection, prev: Option<&'static SoftTimer>, next: Option<&'static SoftTimer>) {
+        match prev {
+            Some(p) => p.set(cs, TimerNode { next, ..p.get(cs) }),
+            None => self.head.borrow(cs).set(next),
+        }
+    }
+}
+
+impl Default for TimerList {
+    fn default() -> TimerList {
+        TimerList::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    fn nop(_: &'static SoftTimer) {}
+
+    fn timer() -> &'static SoftTimer {
+        Box::leak(Box::new(SoftTimer::new(nop)))
+    }
+
+    fn cs() -> CriticalSection<'static> {
+        unsafe { CriticalSection::new() }
+    }
+
+    fn expire_all(list: &TimerList, now: u64) -> Vec<*const SoftTimer> {
+        core::iter::from_fn(|| list.pop_expired(cs(), now)).map(|t| t as *const _).collect()
+    }
+
+    #[test]
+    fn expire_in_deadline_order() {
+        let list = TimerList::new();
+        let (a, b, c) = (timer(), timer(), timer());
+
+        list.insert(cs(), a, 30, 0);
+        list.insert(cs(), b, 10, 0);
+        list.insert(cs(), c, 20, 0);
+        assert_eq!(list.next_deadline(cs()), Some(10));
+
+        assert_eq!(expire_all(&list, 25), [b as *const _, c as *const _]);
+        assert!(!b.is_active(cs()));
+        assert_eq!(list.next_deadline(cs()), Some(30));
+    }
+
+    #[test]
+    fn equal_deadlines_keep_insertion_order() {
+        let list = TimerList::new();
+        let (a, b) = (timer(), timer());
+
+        list.insert(cs(), a, 5, 0);
+        list.insert(cs(), b, 5, 0);
+        assert_eq!(expire_all(&list, 5), [a as *const _, b as *const _]);
+    }
+
+    #[test]
+    fn remove_and_rearm() {
+        let list = TimerList::new();
+        let (a, b) = (timer(), timer());
+
+        list.insert(cs(), a, 10, 0);
+        list.insert(cs(), b, 20, 0);
+        assert!(list.remove(cs(), a));
+        assert!(!list.remove(cs(), a));
+        assert_eq!(list.next_deadline(cs()), Some(20));
+
+        list.insert(cs(), b, 5, 0);
+        assert_eq!(list.next_deadline(cs()), Some(5));
+        assert_eq!(expire_all(&list, 100), [b as *const _]);
+        assert_eq!(list.next_deadline(cs()), None);
+    }
+
+    #[test]
+    fn periodic_timer_is_rescheduled() {
+        let list = TimerList::new();
+        let p = timer();
+
+        list.insert(cs(), p, 100, 100);
+        assert_eq!(expire_all(&list, 100), [p as *const _]);
+        assert_eq!(p.deadline(cs()), 200);
+
+        // Two periods missed: runs once, next deadline after `now`
+        assert_eq!(expire_all(&list, 420), [p as *const _]);
+        assert_eq!(p.deadline(cs()), 500);
+        assert!(p.is_active(cs()));
+    }
+}
@@ -0,0 +1,90 @@
+//! # Interrupt Safe Shared State
+//!
+//! Data shared between interrupt handlers and the main loop is kept in
+//! a `Mutex`, which only hands out a reference inside a critical
+//! section. On target the token comes from `interrupt::free`, which
+//! runs a closure with machine interrupts disabled. The FE310 has a
+//! single hart, so that is enough to make the access exclusive.
+//!
+//! ```ignore
+//! static RX_COUNT: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+//!
+//! interrupt::free(|cs| RX_COUNT.borrow(cs).set(RX_COUNT.borrow(cs).get() + 1));
+//! ```
+
+use core::cell::{RefCell, RefMut, UnsafeCell};
+use core::marker::PhantomData;
+
+/// Proof that interrupts are disabled for the lifetime `'cs`.
+#[derive(Clone, Copy)]
+pub struct CriticalSection<'cs> {
+    _0: PhantomData<&'cs ()>,
+}
+
+impl<'cs> CriticalSection<'cs> {
+    /// # Safety
+    /// Interrupts must stay disabled while the token exists, e.g. the
+    /// caller is an interrupt handler or has cleared `mstatus.MIE`.
+    pub unsafe fn new() -> CriticalSection<'cs> {
+        CriticalSection { _0: PhantomData }
+    }
+}
+
+/// Value that is only accessible inside a critical section.
+pub struct Mutex<T> {
+    inner: UnsafeCell<T>,
+}
+
+// Accesses are serialised by the critical section token
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex { inner: UnsafeCell::new(value) }
+    }
+
+    /// Shared reference for the duration of the critical section. Use
+    /// `Cell` or `RefCell` inside the mutex for mutable state.
+    pub fn borrow<'cs>(&'cs self, _cs: CriticalSection<'cs>) -> &'cs T {
+        unsafe { &*self.inner.get() }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<T> Mutex<RefCell<T>> {
+    /// Mutable borrow of the `RefCell`, panics if it is already borrowed.
+    pub fn borrow_ref_mut<'cs>(&'cs self, cs: CriticalSection<'cs>) -> RefMut<'cs, T> {
+        self.borrow(cs).borrow_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    #[test]
+    fn mutex_gives_access_with_token() {
+        static COUNT: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+        let cs = unsafe { CriticalSection::new() };
+        COUNT.borrow(cs).set(COUNT.borrow(cs).get() + 2);
+        assert_eq!(COUNT.borrow(cs).get(), 2);
+    }
+
+    #[test]
+    fn mutex_refcell_borrow_mut() {
+        let m = Mutex::new(RefCell::new([0u8; 4]));
+        let cs = unsafe { CriticalSection::new() };
+
+        m.borrow_ref_mut(cs)[1] = 7;
+        assert_eq!(m.into_inner().into_inner(), [0, 7, 0, 0]);
+    }
+}
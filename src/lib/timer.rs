@@ -0,0 +1,193 @@
+//! # Timers
+//!
+//! Time is counted in `mtime` ticks of the CLINT (`clint::MTIME_HZ`).
+//! Two kinds of timers share `mtimecmp`, which is programmed with the
+//! earliest deadline of both:
+//!
+//! - `SoftTimer` callbacks, one-shot or periodic, see `swtimer`
+//! - `Timer`, a future that completes at a deadline. Pending futures
+//!   are kept in a table of `TIMER_SLOTS` entries.
+//!
+//! The machine timer interrupt wakes the expired futures and runs the
+//! callbacks of the expired soft timers.
+
+use core::cell::RefCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use crate::clint;
+use crate::interrupt::{self, CriticalSection, Mutex, MIE_MTIE};
+use crate::mmio::MMIO;
+use crate::swtimer::{SoftTimer, TimerList};
+
+/// Timers that can be pending at the same time
+pub const TIMER_SLOTS: usize = 8;
+
+struct TimerSlot {
+    deadline: u64,
+    waker: Option<Waker>,
+}
+
+static SLOTS: Mutex<RefCell<[Option<TimerSlot>; TIMER_SLOTS]>> =
+    Mutex::new(RefCell::new([const { None }; TIMER_SLOTS]));
+
+static SOFT_TIMERS: TimerList = TimerList::new();
+
+/// Current `mtime`.
+pub fn timer_now() -> u64 {
+    clint::clint_read_mtime(&MMIO)
+}
+
+/// Future completing once `mtime` reaches its deadline.
+pub struct Timer {
+    deadline: u64,
+    slot: Option<usize>,
+}
+
+impl Timer {
+    pub fn at(deadline: u64) -> Timer {
+        Timer { deadline, slot: None }
+    }
+
+    pub fn after_ticks(ticks: u64) -> Timer {
+        Timer::at(timer_now() + ticks)
+    }
+
+    pub fn after(ms: u32) -> Timer {
+        Timer::after_ticks(clint::clint_ms_to_ticks(ms))
+    }
+
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    fn release(&mut self) {
+        if let Some(i) = self.slot.take() {
+            interrupt::free(|cs| release_slot(cs, i));
+        }
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if timer_now() >= self.deadline {
+            self.release();
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        let slot = interrupt::free(|cs| {
+            let mut slots = SLOTS.borrow_ref_mut(cs);
+            let i = self.slot.or_else(|| slots.iter().position(|s| s.is_none()))?;
+            slots[i] = Some(TimerSlot { deadline, waker: Some(cx.waker().clone()) });
+            timer_program(cs, &slots);
+            Some(i)
+        });
+
+        match slot {
+            Some(i) => self.slot = Some(i),
+            // All slots in use, poll again
+            None => cx.waker().wake_by_ref(),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+fn release_slot(cs: CriticalSection, i: usize) {
+    let mut slots = SLOTS.borrow_ref_mut(cs);
+    slots[i] = None;
+    timer_program(cs, &slots);
+}
+
+impl SoftTimer {
+    /// Runs the callback once, `ticks` from now.
+    pub fn start_once(&'static self, ticks: u64) {
+        self.start_at(timer_now() + ticks, 0);
+    }
+
+    /// Runs the callback every `period` ticks, first `period` from now.
+    pub fn start_periodic(&'static self, period: u64) {
+        assert!(period > 0, "Timer period must not be 0");
+        self.start_at(timer_now() + period, period);
+    }
+
+    pub fn start_once_ms(&'static self, ms: u32) {
+        self.start_once(clint::clint_ms_to_ticks(ms));
+    }
+
+    pub fn start_periodic_ms(&'static self, ms: u32) {
+        self.start_periodic(clint::clint_ms_to_ticks(ms));
+    }
+
+    /// Arms the timer to expire at `deadline`, then every `period` ticks
+    /// if `period` is not 0. Restarts an active timer.
+    pub fn start_at(&'static self, deadline: u64, period: u64) {
+        interrupt::free(|cs| {
+            SOFT_TIMERS.insert(cs, self, deadline, period);
+            timer_program(cs, &SLOTS.borrow_ref_mut(cs));
+        });
+    }
+
+    /// Disarms the timer, returns false when it was not active.
+    pub fn stop(&'static self) -> bool {
+        interrupt::free(|cs| {
+            let removed = SOFT_TIMERS.remove(cs, self);
+            timer_program(cs, &SLOTS.borrow_ref_mut(cs));
+            removed
+        })
+    }
+
+    pub fn active(&'static self) -> bool {
+        interrupt::free(|cs| self.is_active(cs))
+    }
+}
+
+/// Sets `mtimecmp` to the earliest deadline of a soft timer or a future
+/// with a waker, or disables the timer interrupt when there is none.
+fn timer_program(cs: CriticalSection, slots: &[Option<TimerSlot>; TIMER_SLOTS]) {
+    let soft = SOFT_TIMERS.next_deadline(cs);
+    let next = slots.iter().flatten().filter(|s| s.waker.is_some()).map(|s| s.deadline).chain(soft).min();
+
+    match next {
+        Some(deadline) => {
+            clint::clint_set_mtimecmp(&MMIO, deadline);
+            interrupt::mie_enable(MIE_MTIE);
+        }
+        None => {
+            interrupt::mie_disable(MIE_MTIE);
+            clint::clint_set_mtimecmp(&MMIO, u64::MAX);
+        }
+    }
+}
+
+/// Machine timer interrupt: wakes the expired futures and runs the
+/// callbacks of the expired soft timers.
+pub(crate) fn timer_interrupt() {
+    let now = timer_now();
+
+    interrupt::free(|cs| {
+        for s in SLOTS.borrow_ref_mut(cs).iter_mut().flatten() {
+            if s.deadline <= now {
+                if let Some(w) = s.waker.take() {
+                    w.wake();
+                }
+            }
+        }
+    });
+
+    // One at a time, a callback may start or stop timers
+    while let Some(t) = interrupt::free(|cs| SOFT_TIMERS.pop_expired(cs, now)) {
+        (t.callback())(t);
+    }
+
+    interrupt::free(|cs| timer_program(cs, &SLOTS.borrow_ref_mut(cs)));
+}
@@ -0,0 +1,267 @@
+//! # User Mode Execution
+//!
+//! Runs a function in U-mode on its own stack. The function can only
+//! touch memory granted by PMP rules and reaches the HAL through `ecall`.
+//!
+//! Syscall ABI: number in `a7`, arguments in `a0` - `a6`, result in `a0`.
+
+use core::arch::{asm, global_asm};
+
+use crate::interrupt::{TrapFrame, TRAP_FRAME_SIZE, MSTATUS_MPIE, MSTATUS_MPP_MASK,
+    MSTATUS_MPP_MMODE, REG_A0, REG_A1, REG_A7, REG_SP};
+use crate::pmp::{PmpError, PmpManager, PmpRegion, PmpRegionAddrMatchMode,
+    PmpRegionState, PmpEntryIndex, PMP_ENTRY_COUNT};
+
+pub type SyscallNum = u32;
+pub type SyscallArgs = [u32; 7];
+pub type SyscallHandler = fn(args: &SyscallArgs) -> u32;
+pub type UmodeEntry = extern "C" fn(arg: u32) -> u32;
+
+pub const SYSCALL_COUNT: usize = 32;
+
+/// Ends the user function, `a0` is the exit code. Reserved.
+pub const SYS_EXIT: SyscallNum = 0;
+
+/// Returned in `a0` for unknown or failed syscalls.
+pub const SYSCALL_ERROR: u32 = u32::MAX;
+
+const UMODE_EXIT_NORMAL: u32 = 0;
+const UMODE_EXIT_FAULT: u32 = 1;
+
+#[derive(Debug)]
+pub enum UmodeError {
+    InvalidStack,
+    InvalidSyscall,
+    Pmp(PmpError),
+    /// User function raised the exception with this `mcause`
+    Fault(u32),
+}
+
+static mut SYSCALL_TABLE: [Option<SyscallHandler>; SYSCALL_COUNT] = [None; SYSCALL_COUNT];
+
+global_asm!(r#"
+    .section .text.umode, "ax"
+    .align 2
+    .global _umode_enter
+_umode_enter: # a0 = entry, a1 = user sp, a2 = arg
+    addi sp, sp, -64
+    sw ra, 0(sp)
+    sw s0, 4(sp)
+    sw s1, 8(sp)
+    sw s2, 12(sp)
+    sw s3, 16(sp)
+    sw s4, 20(sp)
+    sw s5, 24(sp)
+    sw s6, 28(sp)
+    sw s7, 32(sp)
+    sw s8, 36(sp)
+    sw s9, 40(sp)
+    sw s10, 44(sp)
+    sw s11, 48(sp)
+    csrr t0, mstatus
+    sw t0, 52(sp)
+
+    csrw mscratch, sp  # Traps from U-mode use this stack
+    csrw mepc, a0
+    li t0, 0x1800
+    csrc mstatus, t0   # MPP = U
+    li t0, 0x80
+    csrs mstatus, t0   # MPIE = 1
+
+    mv sp, a1
+    mv a0, a2
+    la ra, _umode_exit
+    li s0, 0
+    li s1, 0
+    li s2, 0
+    li s3, 0
+    li s4, 0
+    li s5, 0
+    li s6, 0
+    li s7, 0
+    li s8, 0
+    li s9, 0
+    li s10, 0
+    li s11, 0
+    mret
+
+    .global _umode_exit
+_umode_exit: # U-mode, a0 = return value of entry
+    li a7, 0
+    ecall
+1:
+    j 1b
+
+    .global _umode_return
+_umode_return: # M-mode, a0 = exit code or mcause, a1 = exit kind
+    lw t0, 52(sp)
+    csrw mstatus, t0
+    lw ra, 0(sp)
+    lw s0, 4(sp)
+    lw s1, 8(sp)
+    lw s2, 12(sp)
+    lw s3, 16(sp)
+    lw s4, 20(sp)
+    lw s5, 24(sp)
+    lw s6, 28(sp)
+    lw s7, 32(sp)
+    lw s8, 36(sp)
+    lw s9, 40(sp)
+    lw s10, 44(sp)
+    lw s11, 48(sp)
+    addi sp, sp, 64
+    ret
+"#);
+
+extern "C" {
+    fn _umode_enter(entry: u32, sp: u32, arg: u32) -> u64;
+    fn _umode_return();
+}
+
+/// Runs `entry(arg)` in U-mode and returns its return value.
+///
+/// `stack` is granted read/write to the user function and each of
+/// `regions` is allocated from `pmp` for the duration of the call. The
+/// regions must at least cover the code of `entry`, including
+/// `_umode_exit` which it returns to. `m_trap_init` must have been called.
+pub fn umode_run(entry: UmodeEntry, arg: u32, stack: &mut [u32],
+                 regions: &mut [PmpRegion], pmp: &mut PmpManager) -> Result<u32, UmodeError> {
+
+    if stack.len() < 16 {
+        return Err(UmodeError::InvalidStack);
+    }
+
+    let base = stack.as_mut_ptr() as u32;
+    let top = (base + (stack.len() as u32) * 4) & !0xF; // 16 byte aligned sp
+
+    let mut stack_region = PmpRegion {
+        start: base,
+        sz: top - base,
+        amm: PmpRegionAddrMatchMode::TopOfRange,
+        r: true,
+        w: true,
+        x: false,
+        l: false,
+        status: PmpRegionState::Created,
+    };
+
+    let mut granted: [PmpEntryIndex; PMP_ENTRY_COUNT as usize] = [0; PMP_ENTRY_COUNT as usize];
+    let mut count = 0;
+    let mut err = None;
+
+    for r in core::iter::once(&mut stack_region).chain(regions.iter_mut()) {
+        match pmp.allocate(r) {
+            Ok(e) => {
+                granted[count] = e;
+                count += 1;
+            }
+            Err(e) => {
+                err = Some(e);
+                break;
+            }
+        }
+    }
+
+    let result = match err {
+        Some(e) => Err(UmodeError::Pmp(e)),
+        None => {
+            let ret = unsafe { _umode_enter(entry as *const () as u32, top, arg) };
+            let code = ret as u32;
+            if (ret >> 32) as u32 == UMODE_EXIT_NORMAL {
+                Ok(code)
+            } else {
+                Err(UmodeError::Fault(code))
+            }
+        }
+    };
+
+    for e in granted[..count].iter() {
+        let _ = pmp.free(*e);
+    }
+
+    result
+}
+
+/// Installs `handler` for syscall `num`.
+pub fn syscall_register(num: SyscallNum, handler: SyscallHandler) -> Result<(), UmodeError> {
+    if num == SYS_EXIT || num as usize >= SYSCALL_COUNT {
+        return Err(UmodeError::InvalidSyscall);
+    }
+    unsafe { SYSCALL_TABLE[num as usize] = Some(handler); }
+    Ok(())
+}
+
+/// Removes the handler of syscall `num`.
+pub fn syscall_unregister(num: SyscallNum) -> Result<(), UmodeError> {
+    if num == SYS_EXIT || num as usize >= SYSCALL_COUNT {
+        return Err(UmodeError::InvalidSyscall);
+    }
+    unsafe { SYSCALL_TABLE[num as usize] = None; }
+    Ok(())
+}
+
+/// Issues syscall `num` from U-mode (or M-mode) code.
+pub fn umode_syscall(num: SyscallNum, args: SyscallArgs) -> u32 {
+    let ret: u32;
+    unsafe {
+        asm!("ecall",
+            inlateout("a0") args[0] => ret,
+            in("a1") args[1],
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a4") args[4],
+            in("a5") args[5],
+            in("a6") args[6],
+            in("a7") num);
+    }
+    ret
+}
+
+/// Called from `m_trap_handler` for `ecall` from U or M mode.
+pub(crate) fn umode_dispatch_syscall(frame: &mut TrapFrame) {
+
+    frame.mepc += 4; // resume after ecall
+
+    let num = frame.regs[REG_A7];
+
+    if num == SYS_EXIT {
+        if frame.is_from_umode() {
+            let code = frame.regs[REG_A0];
+            umode_return_to_mmode(frame, code, UMODE_EXIT_NORMAL);
+        } else {
+            frame.regs[REG_A0] = SYSCALL_ERROR;
+        }
+        return;
+    }
+
+    let handler = if (num as usize) < SYSCALL_COUNT {
+        unsafe { SYSCALL_TABLE[num as usize] }
+    } else {
+        None
+    };
+
+    frame.regs[REG_A0] = match handler {
+        Some(h) => {
+            let mut args: SyscallArgs = [0; 7];
+            args.copy_from_slice(&frame.regs[REG_A0..REG_A7]);
+            h(&args)
+        }
+        None => SYSCALL_ERROR,
+    };
+}
+
+/// Called from `m_trap_handler` when the user function raises an exception.
+pub(crate) fn umode_kill(frame: &mut TrapFrame) {
+    let cause = frame.mcause;
+    umode_return_to_mmode(frame, cause, UMODE_EXIT_FAULT);
+}
+
+/// Rewrites `frame` so that the trap returns from `_umode_enter` in M-mode.
+fn umode_return_to_mmode(frame: &mut TrapFrame, a0: u32, a1: u32) {
+    frame.regs[REG_A0] = a0;
+    frame.regs[REG_A1] = a1;
+    // M-mode stack pointer saved by _umode_enter sits right above the frame
+    frame.regs[REG_SP] = frame as *mut TrapFrame as u32 + TRAP_FRAME_SIZE;
+    frame.mepc = _umode_return as *const () as u32;
+    frame.mstatus = (frame.mstatus & !(MSTATUS_MPP_MASK | MSTATUS_MPIE)) | MSTATUS_MPP_MMODE;
+}
@@ -0,0 +1,601 @@
+//! # A/B Firmware Update
+//!
+//! The flash holds two application slots and two metadata sectors:
+//!
+//! | Offset     | Size    |                                          |
+//! |------------|---------|------------------------------------------|
+//! | 0x01_0000  |         | `update-boot`, jumped to by the boot ROM |
+//! | 0x10_0000  | 1 MB    | slot A, `layout-flash-slot-a` images     |
+//! | 0x20_0000  | 1 MB    | slot B, `layout-flash-slot-b` images     |
+//! | 0x30_0000  | 2x 4 KB | `UpdateMeta`, two copies                 |
+//!
+//! The running application receives an image for the other slot with
+//! `update_receive`. It is written and read back with its CRC-32, its
+//! `image` header must name the slot as entry, then it becomes the
+//! active slot on trial. The `update-boot` stage counts the
+//! boots of a trial image and returns to the previous slot after
+//! `UPDATE_MAX_ATTEMPTS` boots without `update_confirm`, or when the
+//! active slot no longer matches its CRC.
+//!
+//! Each metadata write goes to the sector not holding the current copy,
+//! with the next sequence number, so a power cut during the write
+//! leaves the previous copy in place. Before the first update slot A is
+//! booted if it holds an image passing `image_verify`.
+//!
+//! ```ignore
+//! let flash = Flash::new(p.qspi0);
+//! update::update_confirm(&flash)?;
+//! ...
+//! let len = update::update_receive(&mut uart, &flash)?;
+//! ```
+
+use crate::error::HalError;
+use crate::flash::{NorFlash, FLASH_BASE, FLASH_SECTOR_SIZE};
+use crate::image::{self, ImageError, ImageHeader, IMAGE_HEADER_LEN, IMAGE_HEADER_OFFSET};
+use crate::xmodem::{self, XmodemError, XmodemPort};
+
+/// Flash offsets of slot A and B, as the `layout-flash-slot-*`
+/// features of build.rs
+pub const UPDATE_SLOTS: [u32; 2] = [0x10_0000, 0x20_0000];
+pub const UPDATE_SLOT_SIZE: u32 = 0x10_0000;
+/// Metadata sectors, written in turn
+pub const UPDATE_META: [u32; 2] = [0x30_0000, 0x30_1000];
+/// Boots of an unconfirmed image before falling back
+pub const UPDATE_MAX_ATTEMPTS: u8 = 3;
+
+const UPDATE_MAGIC: u32 = 0x5055_4241;
+const UPDATE_META_LEN: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum UpdateError {
+    Transfer(XmodemError),
+    Flash(HalError),
+    /// The slot read back does not match the received image, or the
+    /// image does not match its header
+    Verify,
+    /// The received file is not a firmware image
+    Image(ImageError),
+    /// The image is linked for the other slot
+    WrongSlot,
+    /// No slot holds a valid image
+    NoImage,
+}
+
+impl From<XmodemError> for UpdateError {
+    fn from(e: XmodemError) -> UpdateError {
+        UpdateError::Transfer(e)
+    }
+}
+
+impl From<ImageError> for UpdateError {
+    fn from(e: ImageError) -> UpdateError {
+        UpdateError::Image(e)
+    }
+}
+
+impl From<HalError> for UpdateError {
+    fn from(e: HalError) -> UpdateError {
+        UpdateError::Flash(e)
+    }
+}
+
+/// Image stored in a slot, `len` 0 when none.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SlotInfo {
+    pub len: u32,
+    pub crc: u32,
+}
+
+/// Content of a metadata sector, stored with its own CRC-32.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct UpdateMeta {
+    /// Incremented by every write, the copy with the higher one is
+    /// current. Copy `seq` is stored in sector `seq % 2`.
+    pub seq: u32,
+    /// Slot booted by `update-boot`, 0 (A) or 1 (B)
+    pub active: u8,
+    /// The active image is on trial until confirmed
+    pub pending: bool,
+    /// Boots of the image on trial
+    pub attempts: u8,
+    pub slots: [SlotInfo; 2],
+}
+
+impl UpdateMeta {
+    fn to_bytes(self) -> [u8; UPDATE_META_LEN] {
+        let mut b = [0u8; UPDATE_META_LEN];
+        b[0..4].copy_from_slice(&UPDATE_MAGIC.to_le_bytes());
+        b[4] = self.active;
+        b[5] = self.pending as u8;
+        b[6] = self.attempts;
+        b[8..12].copy_from_slice(&self.seq.to_le_bytes());
+        for (i, s) in self.slots.iter().enumerate() {
+            b[12 + i * 8..16 + i * 8].copy_from_slice(&s.len.to_le_bytes());
+            b[16 + i * 8..20 + i * 8].copy_from_slice(&s.crc.to_le_bytes());
+        }
+        let crc = crc32(&b[..28]);
+        b[28..].copy_from_slice(&crc.to_le_bytes());
+        b
+    }
+
+    /// `None` for an erased or corrupted sector.
+    fn from_bytes(b: &[u8; UPDATE_META_LEN]) -> Option<UpdateMeta> {
+        let word = |i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+        if word(0) != UPDATE_MAGIC || word(28) != crc32(&b[..28]) || b[4] > 1 {
+            return None;
+        }
+        Some(UpdateMeta {
+            seq: word(8),
+            active: b[4],
+            pending: b[5] != 0,
+            attempts: b[6],
+            slots: [SlotInfo { len: word(12), crc: word(16) }, SlotInfo { len: word(20), crc: word(24) }],
+        })
+    }
+}
+
+/// CRC-32 (IEEE 802.3), computed over data given in pieces.
+#[derive(Clone, Copy)]
+pub struct Crc32(u32);
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { (c >> 1) ^ 0xEDB8_8320 } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        Crc32(0xFFFF_FFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for b in data {
+            self.0 = CRC32_TABLE[((self.0 ^ *b as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Crc32 {
+        Crc32::new()
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = Crc32::new();
+    c.update(data);
+    c.finish()
+}
+
+/// Newest valid metadata copy, `None` before the first update.
+pub fn update_read_meta(flash: &impl NorFlash) -> Result<Option<UpdateMeta>, HalError> {
+    let mut newest: Option<UpdateMeta> = None;
+    for sector in UPDATE_META {
+        let mut b = [0u8; UPDATE_META_LEN];
+        flash.read(sector, &mut b)?;
+        let Some(meta) = UpdateMeta::from_bytes(&b) else {
+            continue;
+        };
+        // Sequence numbers wrap around
+        if newest.is_none_or(|n| meta.seq.wrapping_sub(n.seq) as i32 > 0) {
+            newest = Some(meta);
+        }
+    }
+    Ok(newest)
+}
+
+/// Writes `meta` as the next copy, into the sector the current copy
+/// is not in.
+fn update_write_meta(flash: &impl NorFlash, meta: &mut UpdateMeta) -> Result<(), HalError> {
+    meta.seq = meta.seq.wrapping_add(1);
+    let sector = UPDATE_META[(meta.seq % 2) as usize];
+    flash.erase_sector(sector)?;
+    flash.program(sector, &meta.to_bytes())
+}
+
+/// Slot `update_receive` writes to, the one not active.
+pub fn update_target_slot(flash: &impl NorFlash) -> Result<usize, HalError> {
+    let meta = update_read_meta(flash)?.unwrap_or_default();
+    Ok(1 - meta.active as usize)
+}
+
+/// CRC-32 of the first `len` bytes of `slot`.
+fn update_slot_crc(flash: &impl NorFlash, slot: usize, len: u32) -> Result<u32, HalError> {
+    let mut crc = Crc32::new();
+    let mut buf = [0u8; 256];
+    let mut at = 0;
+    while at < len {
+        let n = (len - at).min(buf.len() as u32);
+        flash.read(UPDATE_SLOTS[slot] + at, &mut buf[..n as usize])?;
+        crc.update(&buf[..n as usize]);
+        at += n;
+    }
+    Ok(crc.finish())
+}
+
+/// Header of the image in `slot`.
+pub fn update_slot_header(flash: &impl NorFlash, slot: usize) -> Result<ImageHeader, UpdateError> {
+    let mut b = [0u8; IMAGE_HEADER_OFFSET + IMAGE_HEADER_LEN];
+    flash.read(UPDATE_SLOTS[slot], &mut b)?;
+    ImageHeader::parse(&b).map_err(UpdateError::Image)
+}
+
+/// Checks the image in `slot` against its header.
+fn update_slot_verify(flash: &impl NorFlash, slot: usize) -> Result<ImageHeader, UpdateError> {
+    image::image_verify_with(|at, buf: &mut [u8]| {
+        if at as usize + buf.len() > UPDATE_SLOT_SIZE as usize {
+            return Err(UpdateError::Image(ImageError::Truncated));
+        }
+        flash.read(UPDATE_SLOTS[slot] + at, buf).map_err(UpdateError::Flash)
+    })
+}
+
+fn update_slot_valid(flash: &impl NorFlash, meta: &UpdateMeta, slot: usize) -> Result<bool, HalError> {
+    let info = meta.slots[slot];
+    Ok(info.len != 0 && update_slot_crc(flash, slot, info.len)? == info.crc)
+}
+
+/// Receives an image over XMODEM or YMODEM into the inactive slot and
+/// makes it the active slot, on trial from the next boot. Returns the
+/// image length. The running slot is left untouched on any error.
+pub fn update_receive(port: &mut impl XmodemPort, flash: &impl NorFlash) -> Result<usize, UpdateError> {
+    let mut meta = match update_read_meta(flash)? {
+        Some(meta) => meta,
+        None => update_first_meta(flash)?,
+    };
+    let slot = 1 - meta.active as usize;
+    let base = UPDATE_SLOTS[slot];
+
+    let mut erased = 0;
+    let mut crc = Crc32::new();
+    let len = xmodem::xmodem_receive_with(port, UPDATE_SLOT_SIZE as usize, |offset, data| {
+        let end = (offset + data.len()) as u32;
+        while erased < end {
+            flash.erase_sector(base + erased)?;
+            erased += FLASH_SECTOR_SIZE;
+        }
+        crc.update(data);
+        flash.program(base + offset as u32, data)
+    })?;
+
+    let crc = crc.finish();
+    if len == 0 || update_slot_crc(flash, slot, len as u32)? != crc {
+        return Err(UpdateError::Verify);
+    }
+    if update_slot_header(flash, slot)?.entry != FLASH_BASE + base {
+        return Err(UpdateError::WrongSlot);
+    }
+    match update_slot_verify(flash, slot) {
+        Ok(header) if header.length as usize <= len => {}
+        Err(UpdateError::Flash(e)) => return Err(UpdateError::Flash(e)),
+        _ => return Err(UpdateError::Verify),
+    }
+
+    meta.slots[slot] = SlotInfo { len: len as u32, crc };
+    meta.active = slot as u8;
+    meta.pending = true;
+    meta.attempts = 0;
+    update_write_meta(flash, &mut meta)?;
+    Ok(len)
+}
+
+/// Metadata before the first update: slot A holds the image flashed over
+/// JTAG, recorded if it verifies so a failed update can fall back to it.
+fn update_first_meta(flash: &impl NorFlash) -> Result<UpdateMeta, HalError> {
+    let mut meta = UpdateMeta::default();
+    match update_slot_verify(flash, 0) {
+        Ok(header) => meta.slots[0] = SlotInfo {
+            len: header.length,
+            crc: update_slot_crc(flash, 0, header.length)?,
+        },
+        Err(UpdateError::Flash(e)) => return Err(e),
+        Err(_) => {}
+    }
+    Ok(meta)
+}
+
+/// Ends the trial of the running image, `update-boot` keeps booting it.
+pub fn update_confirm(flash: &impl NorFlash) -> Result<(), HalError> {
+    match update_read_meta(flash)? {
+        Some(mut meta) if meta.pending => {
+            meta.pending = false;
+            meta.attempts = 0;
+            update_write_meta(flash, &mut meta)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Picks the slot to boot and records the boot attempt, for the boot
+/// stage. Before the first update slot A is booted if its image
+/// verifies.
+pub fn update_boot_slot(flash: &impl NorFlash) -> Result<usize, UpdateError> {
+    let Some(mut meta) = update_read_meta(flash)? else {
+        return match update_slot_verify(flash, 0) {
+            Ok(_) => Ok(0),
+            Err(UpdateError::Flash(e)) => Err(UpdateError::Flash(e)),
+            Err(_) => Err(UpdateError::NoImage),
+        };
+    };
+
+    let active = meta.active as usize;
+    let mut slot = active;
+    if meta.pending && meta.attempts >= UPDATE_MAX_ATTEMPTS {
+        slot = 1 - active;
+    }
+    if !update_slot_valid(flash, &meta, slot)? {
+        slot = 1 - slot;
+        if !update_slot_valid(flash, &meta, slot)? {
+            return Err(UpdateError::NoImage);
+        }
+    }
+
+    if slot != active {
+        meta.active = slot as u8;
+        meta.pending = false;
+        meta.attempts = 0;
+        update_write_meta(flash, &mut meta)?;
+    } else if meta.pending && meta.attempts < UPDATE_MAX_ATTEMPTS {
+        // Stops counting once there is nothing valid to fall back to
+        meta.attempts += 1;
+        update_write_meta(flash, &mut meta)?;
+    }
+    Ok(slot)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::image::{image_stamp, IMAGE_MAGIC, IMAGE_VERSION};
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::vec;
+    use std::vec::Vec;
+
+    const META_END: usize = (UPDATE_META[1] + FLASH_SECTOR_SIZE) as usize;
+
+    /// Flash in a vector: programming only clears bits
+    struct FakeFlash {
+        mem: RefCell<Vec<u8>>,
+    }
+
+    impl FakeFlash {
+        fn new() -> FakeFlash {
+            FakeFlash { mem: RefCell::new(vec![0xFF; META_END]) }
+        }
+
+        fn corrupt(&self, offset: u32) {
+            self.mem.borrow_mut()[offset as usize] ^= 0x01;
+        }
+    }
+
+    impl NorFlash for FakeFlash {
+        fn read(&self, offset: u32, buf: &mut [u8]) -> Result<(), HalError> {
+            let o = offset as usize;
+            buf.copy_from_slice(&self.mem.borrow()[o..o + buf.len()]);
+            Ok(())
+        }
+
+        fn erase_sector(&self, offset: u32) -> Result<(), HalError> {
+            assert_eq!(offset % FLASH_SECTOR_SIZE, 0);
+            let o = offset as usize;
+            self.mem.borrow_mut()[o..o + FLASH_SECTOR_SIZE as usize].fill(0xFF);
+            Ok(())
+        }
+
+        fn program(&self, offset: u32, bytes: &[u8]) -> Result<(), HalError> {
+            let mut mem = self.mem.borrow_mut();
+            for (m, b) in mem[offset as usize..].iter_mut().zip(bytes) {
+                *m &= *b;
+            }
+            Ok(())
+        }
+    }
+
+    /// Sends one XMODEM file: a block per 128 bytes, then EOT
+    struct Sender {
+        rx: VecDeque<u8>,
+    }
+
+    impl Sender {
+        fn new(data: &[u8]) -> Sender {
+            let mut rx = VecDeque::new();
+            for (i, chunk) in data.chunks(128).enumerate() {
+                let mut block = chunk.to_vec();
+                block.resize(128, 0x1A);
+                let num = (i + 1) as u8;
+                rx.extend([0x01, num, !num]);
+                rx.extend(&block);
+                rx.extend(xmodem::xmodem_crc16(&block).to_be_bytes());
+            }
+            rx.push_back(0x04);
+            Sender { rx }
+        }
+    }
+
+    impl XmodemPort for Sender {
+        fn send(&mut self, _b: u8) -> Result<(), HalError> {
+            Ok(())
+        }
+
+        fn recv(&mut self, _ms: u32) -> Result<Option<u8>, HalError> {
+            Ok(self.rx.pop_front())
+        }
+    }
+
+    /// `len` bytes of `fill` with the stamped header of an image for `slot`
+    fn slot_image(slot: usize, fill: u8, len: u32) -> Vec<u8> {
+        let mut b = vec![0x6F, 0, 0x80, 0x01];
+        for w in [IMAGE_MAGIC, IMAGE_VERSION, len, FLASH_BASE + UPDATE_SLOTS[slot], 0] {
+            b.extend(w.to_le_bytes());
+        }
+        b.resize(len as usize, fill);
+        image_stamp(&mut b).unwrap();
+        b
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn meta_round_trips_and_rejects_corruption() {
+        let meta = UpdateMeta { seq: 7, active: 1, pending: true, attempts: 2, slots: [SlotInfo { len: 5, crc: 6 }; 2] };
+        let mut b = meta.to_bytes();
+        assert_eq!(UpdateMeta::from_bytes(&b), Some(meta));
+        b[9] ^= 1;
+        assert_eq!(UpdateMeta::from_bytes(&b), None);
+        assert_eq!(UpdateMeta::from_bytes(&[0xFF; UPDATE_META_LEN]), None);
+    }
+
+    #[test]
+    fn received_image_goes_to_inactive_slot_on_trial() {
+        let flash = FakeFlash::new();
+        flash.program(UPDATE_SLOTS[0], &slot_image(0, 0x13, 200)).unwrap();
+        assert_eq!(update_boot_slot(&flash), Ok(0));
+
+        let image = slot_image(1, 0x5A, 300);
+        assert_eq!(update_receive(&mut Sender::new(&image), &flash), Ok(384));
+        assert_eq!(flash.mem.borrow()[0x20_0000..0x20_012C], image);
+        assert_eq!(update_slot_header(&flash, 1).unwrap().length, 300);
+
+        let meta = update_read_meta(&flash).unwrap().unwrap();
+        assert_eq!((meta.active, meta.pending, meta.attempts), (1, true, 0));
+        assert_eq!(update_target_slot(&flash), Ok(0));
+
+        assert_eq!(update_boot_slot(&flash), Ok(1));
+        update_confirm(&flash).unwrap();
+        for _ in 0..5 {
+            assert_eq!(update_boot_slot(&flash), Ok(1));
+        }
+        assert_eq!(update_read_meta(&flash).unwrap().unwrap().attempts, 0);
+    }
+
+    #[test]
+    fn unconfirmed_image_falls_back_after_max_attempts() {
+        let flash = FakeFlash::new();
+        update_receive(&mut Sender::new(&slot_image(1, 1, 128)), &flash).unwrap();
+        update_confirm(&flash).unwrap();
+        update_receive(&mut Sender::new(&slot_image(0, 2, 128)), &flash).unwrap();
+
+        for _ in 0..UPDATE_MAX_ATTEMPTS {
+            assert_eq!(update_boot_slot(&flash), Ok(0));
+        }
+        assert_eq!(update_boot_slot(&flash), Ok(1));
+        let meta = update_read_meta(&flash).unwrap().unwrap();
+        assert_eq!((meta.active, meta.pending), (1, false));
+    }
+
+    #[test]
+    fn first_update_falls_back_to_jtag_image() {
+        let flash = FakeFlash::new();
+        flash.program(UPDATE_SLOTS[0], &slot_image(0, 3, 200)).unwrap();
+        update_receive(&mut Sender::new(&slot_image(1, 1, 128)), &flash).unwrap();
+
+        for _ in 0..UPDATE_MAX_ATTEMPTS {
+            assert_eq!(update_boot_slot(&flash), Ok(1));
+        }
+        assert_eq!(update_boot_slot(&flash), Ok(0));
+        let meta = update_read_meta(&flash).unwrap().unwrap();
+        assert_eq!((meta.active, meta.pending, meta.slots[0].len), (0, false, 200));
+    }
+
+    #[test]
+    fn attempts_stop_without_fallback() {
+        let flash = FakeFlash::new();
+        update_receive(&mut Sender::new(&slot_image(1, 1, 128)), &flash).unwrap();
+
+        for _ in 0..300 {
+            assert_eq!(update_boot_slot(&flash), Ok(1));
+        }
+        let meta = update_read_meta(&flash).unwrap().unwrap();
+        assert_eq!((meta.active, meta.pending, meta.attempts), (1, true, UPDATE_MAX_ATTEMPTS));
+    }
+
+    #[test]
+    fn corrupted_slot_is_not_booted() {
+        let flash = FakeFlash::new();
+        update_receive(&mut Sender::new(&slot_image(1, 1, 128)), &flash).unwrap();
+        update_confirm(&flash).unwrap();
+        update_receive(&mut Sender::new(&slot_image(0, 2, 128)), &flash).unwrap();
+        update_confirm(&flash).unwrap();
+
+        flash.corrupt(UPDATE_SLOTS[0] + 7);
+        assert_eq!(update_boot_slot(&flash), Ok(1));
+        flash.corrupt(UPDATE_SLOTS[1]);
+        assert_eq!(update_boot_slot(&flash), Err(UpdateError::NoImage));
+
+        let empty = FakeFlash::new();
+        assert_eq!(update_boot_slot(&empty), Err(UpdateError::NoImage));
+    }
+
+    #[test]
+    fn image_for_the_other_slot_is_refused() {
+        let flash = FakeFlash::new();
+        let res = update_receive(&mut Sender::new(&slot_image(0, 1, 128)), &flash);
+        assert_eq!(res, Err(UpdateError::WrongSlot));
+        assert_eq!(update_read_meta(&flash), Ok(None));
+
+        let res = update_receive(&mut Sender::new(&[1; 128]), &flash);
+        assert_eq!(res, Err(UpdateError::Image(ImageError::BadMagic)));
+
+        // Not stamped, or longer than what was sent
+        let mut image = slot_image(1, 1, 128);
+        image[100] ^= 1;
+        assert_eq!(update_receive(&mut Sender::new(&image), &flash), Err(UpdateError::Verify));
+        let image = slot_image(1, 1, 300);
+        assert_eq!(update_receive(&mut Sender::new(&image[..256]), &flash), Err(UpdateError::Verify));
+        assert_eq!(update_read_meta(&flash), Ok(None));
+    }
+
+    #[test]
+    fn slot_a_without_metadata_must_verify() {
+        let flash = FakeFlash::new();
+        let image = slot_image(0, 3, 200);
+        // Power cut while flashing slot A
+        flash.program(UPDATE_SLOTS[0], &image[..150]).unwrap();
+        assert_eq!(update_boot_slot(&flash), Err(UpdateError::NoImage));
+        flash.program(UPDATE_SLOTS[0] + 150, &image[150..]).unwrap();
+        assert_eq!(update_boot_slot(&flash), Ok(0));
+    }
+
+    #[test]
+    fn interrupted_meta_write_keeps_previous_copy() {
+        let flash = FakeFlash::new();
+        update_receive(&mut Sender::new(&slot_image(1, 1, 128)), &flash).unwrap();
+        update_confirm(&flash).unwrap();
+        let confirmed = update_read_meta(&flash).unwrap().unwrap();
+        assert_eq!(confirmed.seq, 2);
+
+        // The next write erases the other sector, power fails before programming it
+        flash.erase_sector(UPDATE_META[1]).unwrap();
+        assert_eq!(update_read_meta(&flash), Ok(Some(confirmed)));
+        assert_eq!(update_boot_slot(&flash), Ok(1));
+
+        update_receive(&mut Sender::new(&slot_image(0, 2, 128)), &flash).unwrap();
+        let meta = update_read_meta(&flash).unwrap().unwrap();
+        assert_eq!((meta.seq, meta.active, meta.pending), (3, 0, true));
+        flash.corrupt(UPDATE_META[1] + 4);
+        assert_eq!(update_read_meta(&flash), Ok(Some(confirmed)));
+    }
+}
@@ -0,0 +1,393 @@
+//! # XMODEM / YMODEM Receiver
+//!
+//! Receives a file sent with XMODEM-CRC, XMODEM-1K or YMODEM (a batch
+//! of one file) into a buffer. Each block is checked with its CRC-16
+//! and requested again when it is corrupted or incomplete.
+//!
+//! ```ignore
+//! let len = xmodem::xmodem_receive(&mut uart, &mut image)?;
+//! ```
+//!
+//! `xmodem_receive_with` hands each block to a callback instead, for
+//! files larger than RAM.
+//!
+//! XMODEM pads the last block with `0x1A`, the length returned is a
+//! multiple of 128. YMODEM sends the file size, the length returned is
+//! the size of the file.
+
+use crate::clint;
+use crate::error::HalError;
+use crate::mmio::MMIO;
+use crate::serial::{DoReceiveByte, DoSendByte, Uart};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Sent by the receiver to ask for CRC-16 blocks
+const CRC_REQ: u8 = b'C';
+
+/// Wait for each byte of a block, and for the sender between retries
+pub const XMODEM_TIMEOUT_MS: u32 = 1000;
+/// Consecutive timeouts or bad blocks before the transfer is cancelled
+pub const XMODEM_MAX_ERRORS: u32 = 10;
+
+/// Byte link with the sender.
+pub trait XmodemPort {
+    fn send(&mut self, b: u8) -> Result<(), HalError>;
+    /// Next received byte, `None` when nothing arrived within `ms`.
+    fn recv(&mut self, ms: u32) -> Result<Option<u8>, HalError>;
+}
+
+/// Polls the Rx FIFO, timed with the machine timer.
+impl XmodemPort for Uart {
+    fn send(&mut self, b: u8) -> Result<(), HalError> {
+        self.do_send_byte(b)
+    }
+
+    fn recv(&mut self, ms: u32) -> Result<Option<u8>, HalError> {
+        let deadline = clint::clint_read_mtime(&MMIO) + clint::clint_ms_to_ticks(ms);
+        loop {
+            if let Some(b) = self.do_receive_byte()? {
+                return Ok(Some(b));
+            }
+            if clint::clint_read_mtime(&MMIO) >= deadline {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum XmodemError {
+    /// The sender did not start or stopped responding
+    Timeout,
+    /// The sender cancelled the transfer
+    Cancelled,
+    /// A block was skipped
+    Sequence,
+    /// The file does not fit in the buffer
+    TooLarge,
+    Serial(HalError),
+    /// The block could not be stored
+    Write(HalError),
+}
+
+impl From<HalError> for XmodemError {
+    fn from(e: HalError) -> XmodemError {
+        XmodemError::Serial(e)
+    }
+}
+
+/// CRC-16/XMODEM: polynomial 0x1021, initial value 0.
+pub fn xmodem_crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for b in data {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Receives a file into `dest` and returns its length. The caller
+/// retries after `Timeout` to keep waiting for a sender.
+pub fn xmodem_receive(port: &mut impl XmodemPort, dest: &mut [u8]) -> Result<usize, XmodemError> {
+    let max_len = dest.len();
+    xmodem_receive_with(port, max_len, |offset, data| {
+        dest[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    })
+}
+
+/// Receives a file of at most `max_len` bytes, passing the data of each
+/// block with its offset in the file to `write`, in order. A `write`
+/// error cancels the transfer.
+pub fn xmodem_receive_with<F>(port: &mut impl XmodemPort, max_len: usize, mut write: F) -> Result<usize, XmodemError>
+where
+    F: FnMut(usize, &[u8]) -> Result<(), HalError>,
+{
+    let mut block = [0u8; 1024];
+    let mut expected: u8 = 1;
+    let mut len = 0;
+    // YMODEM file size
+    let mut size: Option<usize> = None;
+    let mut started = false;
+    let mut errors = 0;
+    let mut reply = CRC_REQ;
+
+    loop {
+        port.send(reply)?;
+
+        let head = match port.recv(XMODEM_TIMEOUT_MS)? {
+            Some(b) => b,
+            None => {
+                errors += 1;
+                if errors >= XMODEM_MAX_ERRORS {
+                    xmodem_cancel(port)?;
+                    return Err(XmodemError::Timeout);
+                }
+                reply = if started { NAK } else { CRC_REQ };
+                continue;
+            }
+        };
+
+        let n = match head {
+            SOH => 128,
+            STX => 1024,
+            EOT => {
+                port.send(ACK)?;
+                if size.is_some() {
+                    xmodem_end_batch(port, &mut block)?;
+                }
+                return Ok(size.map_or(len, |s| s.min(len)));
+            }
+            CAN => {
+                if port.recv(XMODEM_TIMEOUT_MS)? == Some(CAN) {
+                    return Err(XmodemError::Cancelled);
+                }
+                reply = NAK;
+                continue;
+            }
+            _ => {
+                xmodem_purge(port)?;
+                reply = NAK;
+                continue;
+            }
+        };
+
+        let num = match xmodem_recv_block(port, &mut block[..n])? {
+            Some(num) => num,
+            None => {
+                errors += 1;
+                if errors >= XMODEM_MAX_ERRORS {
+                    xmodem_cancel(port)?;
+                    return Err(XmodemError::Timeout);
+                }
+                xmodem_purge(port)?;
+                reply = NAK;
+                continue;
+            }
+        };
+        errors = 0;
+
+        if !started && num == 0 {
+            // YMODEM header: name, NUL, size in decimal
+            size = Some(ymodem_file_size(&block[..n]));
+            port.send(ACK)?;
+            started = true;
+            reply = CRC_REQ;
+        } else if num == expected {
+            // With a known size the padding of the last block is dropped
+            let keep = size.map_or(n, |s| n.min(s.saturating_sub(len)));
+            if len + keep > max_len {
+                xmodem_cancel(port)?;
+                return Err(XmodemError::TooLarge);
+            }
+            if let Err(e) = write(len, &block[..keep]) {
+                xmodem_cancel(port)?;
+                return Err(XmodemError::Write(e));
+            }
+            len += keep;
+            expected = expected.wrapping_add(1);
+            started = true;
+            reply = ACK;
+        } else if started && num == expected.wrapping_sub(1) {
+            // Our ACK was lost, the sender repeated the block
+            reply = ACK;
+        } else {
+            xmodem_cancel(port)?;
+            return Err(XmodemError::Sequence);
+        }
+    }
+}
+
+/// Reads block number, its complement, `buf.len()` data bytes and the
+/// CRC. Returns the block number, `None` for a bad or incomplete block.
+fn xmodem_recv_block(port: &mut impl XmodemPort, buf: &mut [u8]) -> Result<Option<u8>, HalError> {
+    let (Some(num), Some(inv)) = (port.recv(XMODEM_TIMEOUT_MS)?, port.recv(XMODEM_TIMEOUT_MS)?) else {
+        return Ok(None);
+    };
+    for b in buf.iter_mut() {
+        match port.recv(XMODEM_TIMEOUT_MS)? {
+            Some(v) => *b = v,
+            None => return Ok(None),
+        }
+    }
+    let (Some(hi), Some(lo)) = (port.recv(XMODEM_TIMEOUT_MS)?, port.recv(XMODEM_TIMEOUT_MS)?) else {
+        return Ok(None);
+    };
+
+    if num != !inv || u16::from_be_bytes([hi, lo]) != xmodem_crc16(buf) {
+        return Ok(None);
+    }
+    Ok(Some(num))
+}
+
+/// Size field of a YMODEM header block, 0 when missing.
+fn ymodem_file_size(header: &[u8]) -> usize {
+    let after_name = header.iter().position(|b| *b == 0).map_or(&[][..], |i| &header[i + 1..]);
+    after_name.iter()
+        .take_while(|b| b.is_ascii_digit())
+        .fold(0, |n, b| n.saturating_mul(10).saturating_add((b - b'0') as usize))
+}
+
+/// Receives the empty header that ends a YMODEM batch. The file is
+/// complete, a sender that does not finish the batch is ignored.
+fn xmodem_end_batch(port: &mut impl XmodemPort, block: &mut [u8; 1024]) -> Result<(), HalError> {
+    port.send(CRC_REQ)?;
+    let n = match port.recv(XMODEM_TIMEOUT_MS)? {
+        Some(SOH) => 128,
+        Some(STX) => 1024,
+        _ => return Ok(()),
+    };
+    if xmodem_recv_block(port, &mut block[..n])? == Some(0) {
+        port.send(ACK)?;
+    }
+    Ok(())
+}
+
+/// Drops input until the line is quiet, so the sender restarts the
+/// block from its header.
+fn xmodem_purge(port: &mut impl XmodemPort) -> Result<(), HalError> {
+    while port.recv(XMODEM_TIMEOUT_MS / 10)?.is_some() {}
+    Ok(())
+}
+
+fn xmodem_cancel(port: &mut impl XmodemPort) -> Result<(), HalError> {
+    port.send(CAN)?;
+    port.send(CAN)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    /// Sender side: bytes queued for the receiver, a `None` entry is a
+    /// timeout. Everything the receiver sends is recorded.
+    struct Script {
+        rx: VecDeque<Option<u8>>,
+        sent: Vec<u8>,
+    }
+
+    impl Script {
+        fn new() -> Script {
+            Script { rx: VecDeque::new(), sent: Vec::new() }
+        }
+
+        fn bytes(&mut self, b: &[u8]) {
+            self.rx.extend(b.iter().map(|b| Some(*b)));
+        }
+
+        fn timeout(&mut self) {
+            self.rx.push_back(None);
+        }
+
+        fn block(&mut self, num: u8, data: &[u8]) {
+            let mut data = data.to_vec();
+            data.resize(if data.len() > 128 { 1024 } else { 128 }, 0x1A);
+            self.bytes(&[if data.len() == 128 { SOH } else { STX }, num, !num]);
+            self.bytes(&data);
+            self.bytes(&xmodem_crc16(&data).to_be_bytes());
+        }
+    }
+
+    impl XmodemPort for Script {
+        fn send(&mut self, b: u8) -> Result<(), HalError> {
+            self.sent.push(b);
+            Ok(())
+        }
+
+        fn recv(&mut self, _ms: u32) -> Result<Option<u8>, HalError> {
+            Ok(self.rx.pop_front().flatten())
+        }
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(xmodem_crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn xmodem_blocks_are_acked_and_copied() {
+        let mut s = Script::new();
+        s.timeout();
+        s.block(1, &[0xAA; 128]);
+        s.block(2, b"end");
+        s.bytes(&[EOT]);
+
+        let mut dest = [0u8; 512];
+        assert_eq!(xmodem_receive(&mut s, &mut dest), Ok(256));
+        assert_eq!(&dest[..131], [&[0xAA; 128][..], b"end"].concat());
+        assert_eq!(dest[131], 0x1A);
+        assert_eq!(s.sent, [CRC_REQ, CRC_REQ, ACK, ACK, ACK]);
+    }
+
+    #[test]
+    fn corrupted_block_is_requested_again() {
+        let mut s = Script::new();
+        s.bytes(&[SOH, 1, 0xFE]);
+        s.bytes(&[0x55; 128]);
+        s.bytes(&[0, 0]);
+        s.timeout();
+        s.block(1, &[0x55; 128]);
+        // Repeated after a lost ACK
+        s.block(1, &[0x55; 128]);
+        s.bytes(&[EOT]);
+
+        let mut dest = [0u8; 128];
+        assert_eq!(xmodem_receive(&mut s, &mut dest), Ok(128));
+        assert_eq!(dest, [0x55; 128]);
+        assert_eq!(s.sent, [CRC_REQ, NAK, ACK, ACK, ACK]);
+    }
+
+    #[test]
+    fn ymodem_uses_file_size() {
+        let mut s = Script::new();
+        s.block(0, b"app.bin\x001100 0\x00");
+        s.block(1, &[7; 1024]);
+        s.block(2, &[8; 76]);
+        s.bytes(&[EOT]);
+        s.block(0, &[]);
+
+        let mut dest = [0u8; 2048];
+        assert_eq!(xmodem_receive(&mut s, &mut dest), Ok(1100));
+        assert_eq!(dest[1023..1025], [7, 8]);
+        assert_eq!(dest[1100], 0);
+        assert_eq!(s.sent, [CRC_REQ, ACK, CRC_REQ, ACK, ACK, ACK, CRC_REQ, ACK]);
+    }
+
+    #[test]
+    fn errors_cancel_the_transfer() {
+        let mut s = Script::new();
+        s.block(1, &[1; 200]);
+        let mut small = [0u8; 512];
+        assert_eq!(xmodem_receive(&mut s, &mut small[..100]), Err(XmodemError::TooLarge));
+        assert!(s.sent.ends_with(&[CAN, CAN]));
+
+        let mut s = Script::new();
+        s.block(2, &[]);
+        assert_eq!(xmodem_receive(&mut s, &mut small), Err(XmodemError::Sequence));
+
+        let mut s = Script::new();
+        s.block(1, &[]);
+        let res = xmodem_receive_with(&mut s, 512, |_, _| Err(HalError::Timeout));
+        assert_eq!(res, Err(XmodemError::Write(HalError::Timeout)));
+        assert!(s.sent.ends_with(&[CAN, CAN]));
+
+        let mut s = Script::new();
+        s.bytes(&[CAN, CAN]);
+        assert_eq!(xmodem_receive(&mut s, &mut small), Err(XmodemError::Cancelled));
+
+        let mut s = Script::new();
+        assert_eq!(xmodem_receive(&mut s, &mut small), Err(XmodemError::Timeout));
+        assert_eq!(s.sent.len() as u32, XMODEM_MAX_ERRORS + 2);
+    }
+}
@@ -0,0 +1,175 @@
+//! QEMU `sifive_e` boot tests for the firmware binaries.
+//!
+//! The binaries are built with the `layout-flash-bootloader` layout into
+//! `target/qemu`, which boots at 0x2001_0000 like QEMU's revb mask ROM,
+//! run headless in `qemu-system-riscv32` and UART0 output is checked.
+//! Semihosting is enabled, its console output is mixed with UART0 and
+//! `SYS_EXIT` sets the QEMU exit status.
+//!
+//!     cargo test-qemu
+//!
+//! The tests are ignored when `qemu-system-riscv32` is not installed,
+//! unless `QEMU_REQUIRED` is set in the environment, see `build.rs`.
+
+use std::env;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TARGET: &str = "riscv32imac-unknown-none-elf";
+const QEMU: &str = "qemu-system-riscv32";
+
+struct QemuRun {
+    /// Everything the firmware wrote to UART0 or the semihosting console
+    output: String,
+    /// Exit status, `None` when QEMU was still running and got killed
+    status: Option<ExitStatus>,
+    timed_out: bool,
+}
+
+/// Builds all binaries once and returns their directory.
+fn firmware_dir() -> &'static Path {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+    DIR.get_or_init(|| {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let target_dir = root.join("target").join("qemu");
+        let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+
+        let status = Command::new(cargo)
+            .current_dir(root)
+            .args(["build", "--bins", "--target", TARGET, "--no-default-features"])
+            .args(["--features", "rt,layout-flash-bootloader,kernel", "--target-dir"])
+            .arg(&target_dir)
+            .status()
+            .expect("failed to run cargo");
+        assert!(status.success(), "firmware build for QEMU failed");
+
+        target_dir.join(TARGET).join("debug")
+    })
+}
+
+/// Runs `bin` until `done` returns true for the output collected so far,
+/// QEMU exits, or `timeout` expires.
+fn run_qemu(bin: &str, timeout: Duration, done: impl Fn(&str) -> bool) -> QemuRun {
+    let elf = firmware_dir().join(bin);
+
+    let mut child = Command::new(QEMU)
+        .args(["-machine", "sifive_e,revb=true", "-nographic", "-monitor", "none"])
+        .args(["-serial", "stdio", "-bios", "none"])
+        .args(["-semihosting-config", "enable=on,target=native"])
+        .arg("-kernel")
+        .arg(&elf)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .unwrap_or_else(|e| panic!("failed to start {}: {}", QEMU, e));
+
+    let mut stdout = child.stdout.take().unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 256];
+        while let Ok(n) = stdout.read(&mut buf) {
+            if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let deadline = Instant::now() + timeout;
+    let mut raw = Vec::new();
+    let mut run = QemuRun { output: String::new(), status: None, timed_out: false };
+
+    loop {
+        if let Ok(chunk) = rx.recv_timeout(Duration::from_millis(50)) {
+            raw.extend_from_slice(&chunk);
+            run.output = String::from_utf8_lossy(&raw).into_owned();
+        }
+        if done(&run.output) {
+            break;
+        }
+        if let Some(status) = child.try_wait().unwrap() {
+            run.status = Some(status);
+            break;
+        }
+        if Instant::now() >= deadline {
+            run.timed_out = true;
+            break;
+        }
+    }
+
+    if run.status.is_none() {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    run
+}
+
+#[test]
+#[cfg_attr(qemu_missing, ignore = "qemu-system-riscv32 not found")]
+fn print_writes_welcome_on_uart0() {
+    let run = run_qemu("print", Duration::from_secs(10), |out| out.matches("Welcome\n\r").count() >= 9);
+
+    assert!(!run.timed_out, "timed out, UART0 output: {:?}", run.output);
+    assert_eq!(run.output.matches("Welcome\n\r").count(), 9, "UART0 output: {:?}", run.output);
+}
+
+#[test]
+#[cfg_attr(qemu_missing, ignore = "qemu-system-riscv32 not found")]
+fn led_blink_keeps_running() {
+    let run = run_qemu("led-blink", Duration::from_secs(2), |_| false);
+
+    assert!(run.timed_out, "QEMU exited early with {:?}", run.status);
+    assert!(run.output.is_empty(), "unexpected UART0 output: {:?}", run.output);
+}
+
+#[test]
+#[cfg_attr(qemu_missing, ignore = "qemu-system-riscv32 not found")]
+fn semihosting_prints_and_exits_with_status() {
+    let run = run_qemu("semihosting", Duration::from_secs(10), |_| false);
+
+    assert!(!run.timed_out, "timed out, output: {:?}", run.output);
+    assert!(run.output.contains("Hello from semihosting\n"), "output: {:?}", run.output);
+    assert_eq!(run.status.and_then(|s| s.code()), Some(0));
+}
+
+#[test]
+#[cfg_attr(qemu_missing, ignore = "qemu-system-riscv32 not found")]
+fn async_echo_timer_ticks() {
+    let run = run_qemu("async-echo", Duration::from_secs(10), |out| out.matches("tick\r\n").count() >= 3);
+
+    assert!(!run.timed_out, "timed out, UART0 output: {:?}", run.output);
+    assert_eq!(run.output, "tick\r\ntick\r\ntick\r\n");
+}
+
+#[test]
+#[cfg_attr(qemu_missing, ignore = "qemu-system-riscv32 not found")]
+fn tasks_pass_messages() {
+    let run = run_qemu("tasks", Duration::from_secs(10), |out| out.contains("done\r\n"));
+
+    assert!(!run.timed_out, "timed out, UART0 output: {:?}", run.output);
+    assert_eq!(run.output, "recv 0\r\nrecv 1\r\nrecv 2\r\nrecv 3\r\nrecv 4\r\ndone\r\n");
+}
+
+#[test]
+#[cfg_attr(qemu_missing, ignore = "qemu-system-riscv32 not found")]
+fn serial_boot_asks_for_an_image() {
+    let banner = "serial-boot: send a RAM image with XMODEM or YMODEM\r\n";
+    let run = run_qemu("serial-boot", Duration::from_secs(10), |out| out.starts_with(banner) && out.ends_with("CC"));
+
+    assert!(!run.timed_out, "timed out, UART0 output: {:?}", run.output);
+}
+
+#[test]
+#[cfg_attr(qemu_missing, ignore = "qemu-system-riscv32 not found")]
+fn monitor_prints_a_prompt() {
+    let banner = "monitor: type help for the commands\r\n> ";
+    let run = run_qemu("monitor", Duration::from_secs(10), |out| out.starts_with(banner));
+
+    assert!(!run.timed_out, "timed out, UART0 output: {:?}", run.output);
+}
@@ -0,0 +1,60 @@
+//! Writes the checksum into the image header of firmware binaries made
+//! with `objcopy -O binary`, see `hal::image`.
+//!
+//!     cargo image-stamp led-blink.bin
+//!
+//! An ELF file from `cargo build` is converted first, into `<elf>.bin`
+//! next to it, with `$OBJCOPY` or `riscv64-unknown-elf-objcopy`:
+//!
+//!     cargo image-stamp target/riscv32imac-unknown-none-elf/release/led-blink
+
+use std::env;
+use std::fs;
+use std::process::{self, Command};
+
+const ELF_MAGIC: &[u8] = b"\x7FELF";
+
+fn fail(path: &str, e: impl std::fmt::Display) -> ! {
+    eprintln!("{}: {}", path, e);
+    process::exit(1);
+}
+
+/// `path` itself for a binary, the `objcopy` output for an ELF file.
+fn binary_path(path: &str) -> String {
+    let image = fs::read(path).unwrap_or_else(|e| fail(path, e));
+    if !image.starts_with(ELF_MAGIC) {
+        return path.to_string();
+    }
+
+    let bin = format!("{}.bin", path);
+    let objcopy = env::var("OBJCOPY").unwrap_or_else(|_| "riscv64-unknown-elf-objcopy".into());
+    let status = Command::new(&objcopy)
+        .args(["-O", "binary", path, &bin])
+        .status()
+        .unwrap_or_else(|e| fail(path, format!("{}: {}", objcopy, e)));
+    if !status.success() {
+        fail(path, format!("{} failed", objcopy));
+    }
+    bin
+}
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        eprintln!("usage: image-stamp <image.bin | elf>...");
+        process::exit(2);
+    }
+
+    for path in &paths {
+        let path = &binary_path(path);
+        let mut image = fs::read(path).unwrap_or_else(|e| fail(path, e));
+        let header = hal::image::image_stamp(&mut image).unwrap_or_else(|e| fail(path, format!("{:?}", e)));
+        if let Err(e) = fs::write(path, &image) {
+            fail(path, e);
+        }
+
+        let (major, minor, patch) = header.version();
+        println!("{}: version {}.{}.{}, {} bytes, entry 0x{:08X}, checksum 0x{:08X}",
+            path, major, minor, patch, header.length, header.entry, header.checksum);
+    }
+}